@@ -5,16 +5,17 @@ use {
     alloc::{alloc, dealloc, Layout},
     collections::VecDeque,
     mem::ManuallyDrop,
-    ptr::slice_from_raw_parts_mut,
+    ptr::{self, slice_from_raw_parts_mut},
   },
   vrisc::{Cpu, Emu, REG_COUNT},
   yuvutils_rs::{YuvRange, YuvStandardMatrix},
 };
 
 pub use vrisc;
-use vrisc::{
-  csr,
-  dev::vga::{VGA_HEIGHT, VGA_WIDTH},
+use vrisc::dev::{
+  audio::{self, Audio},
+  vga::{text, Vga},
+  virtio::input::{self, Event, Input},
 };
 
 #[repr(u32)]
@@ -75,6 +76,23 @@ impl From<vrisc::Mode> for Mode {
   }
 }
 
+#[repr(u32)]
+pub enum Button {
+  Left,
+  Right,
+  Middle,
+}
+
+impl From<Button> for input::Button {
+  fn from(value: Button) -> Self {
+    match value {
+      Button::Left => Self::Left,
+      Button::Right => Self::Right,
+      Button::Middle => Self::Middle,
+    }
+  }
+}
+
 #[repr(C)]
 pub struct RegPlace<T> {
   regs: [T; REG_COUNT],
@@ -145,6 +163,18 @@ impl CpuRepr {
   }
 }
 
+/// Audio played between two vertical blanks of the display.
+#[repr(C)]
+pub struct AudioPacket {
+  /// Frame of the video the samples play along with, counted across the
+  /// mode changes of the display like the frames of the video stream.
+  pub pts: u64,
+  /// Frames per second of the samples.
+  pub rate: u32,
+  /// Signed 16-bit samples, the left and the right ones in turn.
+  pub samples: Slice<i16>,
+}
+
 #[repr(C)]
 pub struct EmuRepr {
   cpu: CpuRepr,
@@ -158,25 +188,46 @@ type Av1 = rav1e::Context<Px>;
 pub struct Context {
   emu: Emu,
   av1: Av1,
+  // resolution and frame rate the encoder was made for
+  size: (usize, usize),
+  fps: u32,
+  // last frame of the display sent to the encoder
+  frame: u64,
+  // frames of the video stream so far, over every encoder
+  frames: u64,
   packets: VecDeque<Packet<Px>>,
+  // start, rate and frames of the audio of each frame of the display
+  audio: VecDeque<(u64, u32, Vec<audio::Frame>)>,
 }
 
-pub fn ctx() -> Config {
+pub fn ctx((width, height): (usize, usize), fps: u32) -> Config {
   Config::new().with_encoder_config(EncoderConfig {
-    width: VGA_WIDTH as usize,
-    height: VGA_HEIGHT as usize,
+    width,
+    height,
+    time_base: Rational::new(1, fps as u64),
     chroma_sampling: ChromaSampling::Cs444,
     ..Default::default()
   })
 }
 
 impl Context {
-  pub fn new(ram: usize) -> Self {
-    Self {
+  /// A machine with `ram` bytes of main memory, `None` if the input device
+  /// finds no free virtio slot or the encoder can't be made.
+  pub fn new(ram: usize) -> Option<Self> {
+    let size = (text::WIDTH as usize, text::HEIGHT as usize);
+    // a remote client drives the guest through the input device
+    let mut emu = Emu::new(ram);
+    emu.cpu.bus.attach_virtio(Input::new()).ok()?;
+    Some(Self {
       packets: VecDeque::new(),
-      emu: Emu::new(ram),
-      av1: ctx().new_context().unwrap(),
-    }
+      audio: VecDeque::new(),
+      emu,
+      av1: ctx(size, Vga::FPS).new_context().ok()?,
+      size,
+      fps: Vga::FPS,
+      frame: 0,
+      frames: 0,
+    })
   }
 }
 
@@ -194,9 +245,13 @@ pub unsafe extern "C" fn vfree(ptr: *mut u8, len: u64) {
   dealloc(ptr, layout(len))
 }
 
+/// Null if the machine can't be built.
 #[no_mangle]
 pub extern "C" fn vempty_emu(ram: u64) -> *mut Context {
-  Box::into_raw(Box::new(Context::new(ram as usize)))
+  match Context::new(ram as usize) {
+    Some(ctx) => Box::into_raw(Box::new(ctx)),
+    None => ptr::null_mut(),
+  }
 }
 
 #[no_mangle]
@@ -213,39 +268,103 @@ pub unsafe extern "C" fn vmap_emu(ctx: *mut Context, repr: EmuRepr) {
 #[no_mangle]
 pub unsafe extern "C" fn vcycle_emu(ctx: *mut Context) -> Trap {
   let ctx = &mut *ctx;
+  capture(ctx);
+  match ctx.emu.cycle() {
+    Ok(_) => Trap::Requested,
+    Err(ex) => ctx.emu.cpu.catch_exception(ex).into(),
+  }
+}
 
-  const SYNC: u64 = 131_072;
+// A frame is captured on each vertical blank of the display, right after
+// the guest finished it. Without a display there is neither video nor audio.
+fn capture(ctx: &mut Context) {
+  let Some(vga) = ctx.emu.cpu.bus.device::<Vga>() else { return };
+  let last = std::mem::replace(&mut ctx.frame, vga.frame());
+  if last == ctx.frame {
+    return;
+  }
+
+  let pts = ctx.frames;
+  if let Some((width, height)) = vga.resolution() {
+    // the encoder is made for a single resolution and frame rate, a new mode
+    // needs another
+    let (size, fps) = ((width as usize, height as usize), vga.fps().max(1));
+    if (size, fps) != (ctx.size, ctx.fps) {
+      recv_frames(&mut ctx.av1, &mut ctx.packets, true).unwrap();
+      ctx.av1 = self::ctx(size, fps).new_context().unwrap();
+      (ctx.size, ctx.fps) = (size, fps);
+    }
 
-  if ctx.emu.cpu.state.load(csr::TIME) % SYNC == 0 {
     let av1 = &mut ctx.av1;
     let mut frame = av1.new_frame();
     let Frame { planes: [y, u, v] } = &mut frame;
 
-    let rgb_stride = VGA_WIDTH as u32;
-
+    let rgb_stride = 3 * width;
     #[rustfmt::skip]
     rgb_to_yuv444(
       &mut y.data, y.cfg.stride as u32,
       &mut u.data, u.cfg.stride as u32,
       &mut v.data, v.cfg.stride as u32,
-      ctx.emu.cpu.bus.vga.buf.as_slice(), rgb_stride,
-      VGA_WIDTH as u32,
-      VGA_HEIGHT as u32,
+      &vga.rgb(), rgb_stride,
+      width,
+      height,
       YuvRange::Full,
       YuvStandardMatrix::Bt601,
     );
 
     av1.send_frame(frame).unwrap();
+    ctx.frames += 1;
 
     recv_frames(av1, &mut ctx.packets, /* to fit */ false).unwrap();
   }
 
-  match ctx.emu.cycle() {
-    Ok(_) => Trap::Requested,
-    Err(ex) => ctx.emu.cpu.catch_exception(ex).into(),
+  // the audio is cut at the same vertical blanks as the video
+  let Some(audio) = ctx.emu.cpu.bus.device_mut::<Audio>() else { return };
+  let (rate, samples) = (audio.rate(), audio.take());
+  if !samples.is_empty() {
+    if ctx.audio.len() > 128 {
+      let _ = ctx.audio.pop_front();
+    }
+    ctx.audio.push_back((pts, rate, samples));
   }
 }
 
+#[no_mangle]
+pub unsafe extern "C" fn vinput_key(
+  ctx: *mut Context,
+  code: u16,
+  pressed: bool,
+) -> bool {
+  let ctx = &mut *ctx;
+  ctx.emu.input(Event::Key(code, pressed))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn vinput_button(
+  ctx: *mut Context,
+  button: Button,
+  pressed: bool,
+) -> bool {
+  let ctx = &mut *ctx;
+  ctx.emu.input(Event::Button(button.into(), pressed))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn vinput_motion(
+  ctx: *mut Context,
+  dx: i32,
+  dy: i32,
+) -> bool {
+  let ctx = &mut *ctx;
+  ctx.emu.input(Event::Motion(dx, dy))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn vinput_wheel(ctx: *mut Context, clicks: i32) -> bool {
+  let ctx = &mut *ctx;
+  ctx.emu.input(Event::Wheel(clicks))
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn vcpu_repr(ctx: *const Context) -> CpuRepr {
   let ctx = &*ctx;
@@ -261,6 +380,27 @@ pub unsafe extern "C" fn vrecv_packets(ctx: *mut Context) -> Slice<Slice<u8>> {
   Slice::new(ctx.packets.drain(..).map(into_slice).collect())
 }
 
+#[no_mangle]
+pub unsafe extern "C" fn vrecv_audio(ctx: *mut Context) -> Slice<AudioPacket> {
+  let ctx = &mut *ctx;
+
+  let into_packet =
+    move |(pts, rate, samples): (u64, u32, Vec<audio::Frame>)| {
+      let samples = samples.into_iter().flatten().collect();
+      AudioPacket { pts, rate, samples: Slice::new(samples) }
+    };
+  Slice::new(ctx.audio.drain(..).map(into_packet).collect())
+}
+
+/// Free the packets of [`vrecv_audio`] along with their samples, which
+/// [`vfree`] can't as they aren't bytes.
+#[no_mangle]
+pub unsafe extern "C" fn vfree_audio(audio: Slice<AudioPacket>) {
+  for packet in audio.into_owned().into_vec() {
+    let _ = packet.samples.into_owned();
+  }
+}
+
 fn recv_frames(
   av1: &mut Av1,
   packets: &mut VecDeque<Packet<Px>>,
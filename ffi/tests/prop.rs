@@ -1,6 +1,8 @@
 use {
   ffi::{
-    vrisc::{Bus, Cpu, Dram, Mode, State, Xregs, DRAM_SIZE, REG_COUNT},
+    vrisc::{
+      Bus, Cpu, Dram, Misaligned, Mode, State, Xregs, DRAM_SIZE, REG_COUNT,
+    },
     CpuRepr,
   },
   proptest::{collection::vec, prelude::*},
@@ -24,13 +26,21 @@ prop_compose! {
   fn bus()(bytes in vec(any::<u8>(), 128..=128)) -> Bus {
     let mut dram = Dram::with_capacity(bytes.len());
     dram.init(&bytes);
-    Bus { dram }
+    Bus::new(dram)
   }
 }
 
 prop_compose! {
   fn cpu()(pc in any::<u64>(), mode in mode(), xregs in xregs(), bus in bus()) -> Cpu {
-    Cpu { pc, mode, xregs, state: State::new(), bus }
+    Cpu {
+      pc,
+      mode,
+      xregs,
+      state: State::new(),
+      bus,
+      misaligned: Misaligned::Emulate,
+      reservation: None,
+    }
   }
 }
 
@@ -1,6 +1,25 @@
-use crate::{
-  dev::vga::{Vga, VGA_HEIGHT, VGA_WIDTH},
-  Dram, Exception, DRAM_SIZE,
+use {
+  crate::{
+    cpu::WORD,
+    dev::{
+      aplic::Aplic,
+      audio::Audio,
+      clint::Clint,
+      imsic::Imsic,
+      plic::{Plic, PLIC_SOURCES},
+      rom::Rom,
+      rtc::{Clock, Rtc},
+      serial::Null,
+      syscon::Syscon,
+      uart::Uart,
+      vga::Vga,
+      virtio::{Mmio, Virtio, MMIO_SIZE},
+      Device,
+    },
+    fdt::{Fdt, Mapping},
+    Dram, Exception, Mode, DRAM_SIZE,
+  },
+  std::any::Any,
 };
 
 macro_rules! devices {
@@ -11,43 +30,410 @@ macro_rules! devices {
       pub const ADDR: u64 = $addr;
       pub const SIZE: u64 = $size;
       pub const END: u64 = ADDR + $size;
+
+      /// Whether an access of `size` bits at `addr` lies entirely within
+      /// the region.
+      pub const fn contains(addr: u64, size: u8) -> bool {
+        addr >= ADDR && addr.saturating_add(size as u64 / 8) <= END
+      }
     }
   )*};
 }
 
 devices! {
+  rom = [0x1000; Rom::SIZE];
   vga = [0xb8000; Vga::SIZE];
+  syscon = [0x0100_0000; Syscon::SIZE];
+  rtc = [0x0100_1000; Rtc::SIZE];
+  audio = [0x0100_2000; Audio::SIZE];
+  clint = [0x0200_0000; Clint::SIZE];
+  plic = [0x0c00_0000; Plic::size(2)];
+  uart = [0x1000_0000; Uart::SIZE];
+  virtio = [0x1000_1000; VIRTIO_SLOTS as u64 * MMIO_SIZE];
+  aplic = [0x0d00_0000; Aplic::SIZE];
+  imsic_m = [0x2400_0000; Imsic::SIZE];
+  imsic_s = [0x2800_0000; Imsic::SIZE];
   dram = [0x8000_0000; DRAM_SIZE];
 }
 
+/// Interrupt source the UART of the default machine is wired to.
+pub const UART_IRQ: usize = 10;
+/// Interrupt source of the vertical blank of the [`Vga`].
+pub const VGA_IRQ: usize = 11;
+/// Interrupt source of the alarm of the [`Rtc`].
+pub const RTC_IRQ: usize = 12;
+/// Interrupt source of the empty ring of the [`Audio`].
+pub const AUDIO_IRQ: usize = 13;
+
+/// Number of virtio-mmio slots, the slot `n` is mapped at
+/// `virtio::ADDR + n * MMIO_SIZE` and wired to the source `VIRTIO_IRQ + n`.
+pub const VIRTIO_SLOTS: usize = 8;
+pub const VIRTIO_IRQ: usize = 1;
+
+/// Error of [`Bus::attach`] and [`Bus::remap`].
+#[derive(Debug, PartialEq, Eq)]
+pub enum MapError {
+  /// The requested region overlaps the region `[base, base + size)` that is
+  /// already mapped.
+  Overlap { base: u64, size: u64 },
+  /// The requested region `[base, base + size)` is empty or wraps around
+  /// the address space.
+  Invalid { base: u64, size: u64 },
+  /// No device is mapped at `base`.
+  Unmapped { base: u64 },
+}
+
+#[derive(Debug)]
+struct Region {
+  base: u64,
+  size: u64,
+  // interrupt source the device is wired to
+  irq: Option<usize>,
+  device: Box<dyn Device>,
+}
+
+impl Region {
+  fn contains(&self, addr: u64, size: u8) -> bool {
+    addr >= self.base
+      && addr.saturating_add(size as u64 / 8) <= self.base + self.size
+  }
+
+  fn any(&self) -> &dyn Any {
+    &*self.device
+  }
+
+  fn any_mut(&mut self) -> &mut dyn Any {
+    &mut *self.device
+  }
+}
+
+// Positions in the regions of the devices the bus and the hart reach on
+// every step, found again whenever the map changes.
+#[derive(Debug, Default)]
+struct Index {
+  plic: Option<usize>,
+  aplic: Option<usize>,
+  clint: Option<usize>,
+  syscon: Option<usize>,
+  // interrupt controllers, which drive the bits of `mip`
+  controllers: Vec<usize>,
+}
+
+/// System bus: the main memory at [`dram::ADDR`] and a map of devices.
 #[derive(Debug)]
 pub struct Bus {
-  pub vga: Vga,
   pub dram: Dram,
+  // sorted by base address
+  regions: Vec<Region>,
+  index: Index,
 }
 
 impl Bus {
-  pub fn load(&mut self, addr: u64, size: u8) -> Result<u64, Exception> {
-    match addr {
-      vga::ADDR..=vga::END => self.vga.load(addr - vga::ADDR, size),
-      dram::ADDR..=dram::END => self.dram.load(addr - dram::ADDR, size),
-      _ => Err(Exception::LoadAccessFault),
+  /// Bus of the default machine, with the built-in devices mapped at their
+  /// usual addresses.
+  pub fn new(dram: Dram) -> Self {
+    let mut bus = Self::with_dram(dram);
+    let mut rom = Rom::new(rom::SIZE as usize);
+    rom.set_entry(dram::ADDR);
+    bus.attach(rom::ADDR, rom::SIZE, rom).unwrap();
+    bus.attach_irq(vga::ADDR, vga::SIZE, VGA_IRQ, Vga::new()).unwrap();
+    bus.attach(syscon::ADDR, syscon::SIZE, Syscon::new()).unwrap();
+    let rtc = Rtc::new(Clock::Host);
+    bus.attach_irq(rtc::ADDR, rtc::SIZE, RTC_IRQ, rtc).unwrap();
+    let audio = Audio::new();
+    bus.attach_irq(audio::ADDR, audio::SIZE, AUDIO_IRQ, audio).unwrap();
+    bus.attach(clint::ADDR, clint::SIZE, Clint::new(1)).unwrap();
+    bus.attach(plic::ADDR, plic::SIZE, Plic::new(PLIC_SOURCES, 2)).unwrap();
+    bus.attach_irq(uart::ADDR, uart::SIZE, UART_IRQ, Uart::new(Null)).unwrap();
+    bus.attach(aplic::ADDR, aplic::SIZE, Aplic::new(imsic_m::ADDR)).unwrap();
+    bus
+      .attach(imsic_m::ADDR, imsic_m::SIZE, Imsic::new(Mode::Machine))
+      .unwrap();
+    bus
+      .attach(imsic_s::ADDR, imsic_s::SIZE, Imsic::new(Mode::Supervisor))
+      .unwrap();
+    bus
+  }
+
+  /// Bus without any device besides the main memory.
+  pub fn with_dram(dram: Dram) -> Self {
+    Self { dram, regions: Vec::new(), index: Index::default() }
+  }
+
+  /// Map `device` at `[base, base + size)`.
+  pub fn attach(
+    &mut self,
+    base: u64,
+    size: u64,
+    device: impl Device,
+  ) -> Result<(), MapError> {
+    self.map(base, size, None, Box::new(device))
+  }
+
+  /// Map `device` at `[base, base + size)` with its interrupt line wired to
+  /// the source `irq` of the interrupt controllers.
+  pub fn attach_irq(
+    &mut self,
+    base: u64,
+    size: u64,
+    irq: usize,
+    device: impl Device,
+  ) -> Result<(), MapError> {
+    self.map(base, size, Some(irq), Box::new(device))
+  }
+
+  fn map(
+    &mut self,
+    base: u64,
+    size: u64,
+    irq: Option<usize>,
+    device: Box<dyn Device>,
+  ) -> Result<(), MapError> {
+    self.free(base, size)?;
+    let at = self.regions.partition_point(|r| r.base < base);
+    self.regions.insert(at, Region { base, size, irq, device });
+    self.reindex();
+    Ok(())
+  }
+
+  // Whether `[base, base + size)` is free to map a device.
+  fn free(&self, base: u64, size: u64) -> Result<(), MapError> {
+    let end = base.checked_add(size).filter(|_| size != 0);
+    let Some(end) = end else { return Err(MapError::Invalid { base, size }) };
+
+    let overlaps = |b: u64, s: u64| base < b + s && b < end;
+    if overlaps(dram::ADDR, dram::SIZE) {
+      return Err(MapError::Overlap { base: dram::ADDR, size: dram::SIZE });
+    }
+    match self.regions.iter().find(|r| overlaps(r.base, r.size)) {
+      Some(region) => {
+        Err(MapError::Overlap { base: region.base, size: region.size })
+      }
+      None => Ok(()),
+    }
+  }
+
+  /// Map `device` behind a virtio-mmio transport in the first free virtio
+  /// slot, returning the base address of the transport.
+  pub fn attach_virtio(
+    &mut self,
+    device: impl Virtio,
+  ) -> Result<u64, MapError> {
+    let base = |slot| virtio::ADDR + slot as u64 * MMIO_SIZE;
+    let Some(slot) = (0..VIRTIO_SLOTS)
+      .find(|&slot| self.regions.iter().all(|r| r.base != base(slot)))
+    else {
+      return Err(MapError::Overlap { base: virtio::ADDR, size: virtio::SIZE });
+    };
+
+    let irq = VIRTIO_IRQ + slot;
+    self.attach_irq(base(slot), MMIO_SIZE, irq, Mmio::new(device))?;
+    Ok(base(slot))
+  }
+
+  /// Move the device mapped at `base` to `to` with its interrupt line.
+  /// Nothing moves if the new region overlaps another one.
+  pub fn remap(&mut self, base: u64, to: u64) -> Result<(), MapError> {
+    let at = self.regions.iter().position(|r| r.base == base);
+    let Some(at) = at else { return Err(MapError::Unmapped { base }) };
+    let region = self.regions.remove(at);
+    if let Err(overlap) = self.free(to, region.size) {
+      self.regions.insert(at, region);
+      return Err(overlap);
+    }
+
+    let at = self.regions.partition_point(|r| r.base < to);
+    self.regions.insert(at, Region { base: to, ..region });
+    self.reindex();
+    Ok(())
+  }
+
+  /// Unmap the device mapped at `base`.
+  pub fn detach(&mut self, base: u64) -> Option<Box<dyn Device>> {
+    let at = self.regions.iter().position(|r| r.base == base)?;
+    let region = self.regions.remove(at);
+    self.reindex();
+    Some(region.device)
+  }
+
+  fn reindex(&mut self) {
+    let first =
+      |is: fn(&dyn Any) -> bool| self.regions.iter().position(|r| is(r.any()));
+    let controller = |r: &Region| {
+      let device = r.any();
+      device.is::<Plic>()
+        || device.is::<Aplic>()
+        || device.is::<Imsic>()
+        || device.is::<Clint>()
+    };
+    self.index = Index {
+      plic: first(<dyn Any>::is::<Plic>),
+      aplic: first(<dyn Any>::is::<Aplic>),
+      clint: first(<dyn Any>::is::<Clint>),
+      syscon: first(<dyn Any>::is::<Syscon>),
+      controllers: (0..self.regions.len())
+        .filter(|&at| controller(&self.regions[at]))
+        .collect(),
+    };
+  }
+
+  fn indexed<T: Device>(&self, at: Option<usize>) -> Option<&T> {
+    self.regions[at?].any().downcast_ref()
+  }
+
+  fn indexed_mut<T: Device>(&mut self, at: Option<usize>) -> Option<&mut T> {
+    self.regions[at?].any_mut().downcast_mut()
+  }
+
+  /// The first CLINT, which holds the time of the machine.
+  pub fn clint(&self) -> Option<&Clint> {
+    self.indexed(self.index.clint)
+  }
+
+  /// The first system controller, which takes the power requests.
+  pub fn syscon_mut(&mut self) -> Option<&mut Syscon> {
+    self.indexed_mut(self.index.syscon)
+  }
+
+  /// First mapped device of type `T`.
+  pub fn device<T: Device>(&self) -> Option<&T> {
+    self.devices().next()
+  }
+
+  /// First mapped device of type `T`.
+  pub fn device_mut<T: Device>(&mut self) -> Option<&mut T> {
+    self.devices_mut().next()
+  }
+
+  /// Every mapped device of type `T`, by ascending address.
+  pub fn devices<T: Device>(&self) -> impl Iterator<Item = &T> {
+    self.regions.iter().filter_map(|r| (&*r.device as &dyn Any).downcast_ref())
+  }
+
+  /// Every mapped device of type `T`, by ascending address.
+  pub fn devices_mut<T: Device>(&mut self) -> impl Iterator<Item = &mut T> {
+    self
+      .regions
+      .iter_mut()
+      .filter_map(|r| (&mut *r.device as &mut dyn Any).downcast_mut())
+  }
+
+  /// Add the nodes of the mapped devices to `fdt`. With a PLIC the AIA
+  /// controllers are left out, the PLIC takes the external interrupts.
+  pub(crate) fn describe(&self, fdt: &mut Fdt, plic: bool) {
+    for region in &self.regions {
+      let device = &*region.device as &dyn Any;
+      if plic && (device.is::<Aplic>() || device.is::<Imsic>()) {
+        continue;
+      }
+      let Region { base, size, irq, .. } = *region;
+      region.device.fdt(fdt, &Mapping { base, size, irq });
     }
   }
 
+  /// Device tree path of the console, the first UART.
+  pub(crate) fn stdout_path(&self) -> Option<String> {
+    let uart =
+      self.regions.iter().find(|r| (&*r.device as &dyn Any).is::<Uart>())?;
+    Some(format!("/soc/serial@{:x}", uart.base))
+  }
+
+  /// Interrupt file of the IMSIC for the privilege `level`.
+  pub fn imsic(&self, level: Mode) -> Option<&Imsic> {
+    self.devices::<Imsic>().find(|imsic| imsic.level == level)
+  }
+
+  /// Interrupt file of the IMSIC for the privilege `level`.
+  pub fn imsic_mut(&mut self, level: Mode) -> Option<&mut Imsic> {
+    self.devices_mut::<Imsic>().find(|imsic| imsic.level == level)
+  }
+
+  pub fn load(&mut self, addr: u64, size: u8) -> Result<u64, Exception> {
+    let result = if dram::contains(addr, size) {
+      self.dram.load(addr - dram::ADDR, size)
+    } else {
+      match self.region(addr, size) {
+        Some(r) => r.device.load(addr - r.base, size),
+        None => Err(Exception::LoadAccessFault(addr)),
+      }
+    };
+    // devices report faults at their own offsets, and accesses that cross
+    // a region boundary fault as a whole
+    result.map_err(|_| Exception::LoadAccessFault(addr))
+  }
+
   pub fn store(
     &mut self,
     addr: u64,
     value: u64,
     size: u8,
   ) -> Result<(), Exception> {
-    match addr {
-      vga::ADDR..=vga::END => self.vga.store(addr - vga::ADDR, value, size),
-      dram::ADDR..=dram::END => self.dram.store(addr - dram::ADDR, value, size),
-      _ => {
-        println!("0x{:x}", addr);
-        Err(Exception::StoreAMOAccessFault)
+    let result = if dram::contains(addr, size) {
+      self.dram.store(addr - dram::ADDR, value, size)
+    } else {
+      match self.region(addr, size) {
+        Some(r) => r.device.store(addr - r.base, value, size),
+        None => Err(Exception::StoreAMOAccessFault(addr)),
+      }
+    };
+    result.map_err(|_| Exception::StoreAMOAccessFault(addr))
+  }
+
+  /// Advance every device by one step of the machine.
+  pub fn tick(&mut self) {
+    for i in 0..self.regions.len() {
+      let region = &mut self.regions[i];
+      region.device.tick(&mut self.dram);
+      if let Some(irq) = region.irq {
+        let level = region.device.irq();
+        self.drive_irq(irq, level);
       }
     }
+    self.deliver_msis();
+  }
+
+  /// Return every device to its power-on state.
+  pub fn reset(&mut self) {
+    for region in &mut self.regions {
+      region.device.reset();
+    }
+  }
+
+  /// Bits of `mip` asserted by the interrupt controllers.
+  pub fn mip(&self) -> u64 {
+    let regions = self.index.controllers.iter().map(|&at| &self.regions[at]);
+    regions.fold(0, |mip, r| mip | r.device.mip())
+  }
+
+  /// Drive the input wire of an interrupt source of the PLIC and the APLIC.
+  pub fn set_irq(&mut self, source: usize, level: bool) {
+    self.drive_irq(source, level);
+    self.deliver_msis();
+  }
+
+  fn drive_irq(&mut self, source: usize, level: bool) {
+    if let Some(plic) = self.indexed_mut::<Plic>(self.index.plic) {
+      plic.set_input(source, level);
+    }
+    if let Some(aplic) = self.indexed_mut::<Aplic>(self.index.aplic) {
+      aplic.set_input(source, level);
+    }
+  }
+
+  fn region(&mut self, addr: u64, size: u8) -> Option<&mut Region> {
+    let at = self.regions.partition_point(|r| r.base <= addr);
+    let region = &mut self.regions[at.checked_sub(1)?];
+    region.contains(addr, size).then_some(region)
+  }
+
+  // MSIs are plain memory writes, a write that fails is dropped.
+  fn deliver_msis(&mut self) {
+    let msis = match self.indexed_mut::<Aplic>(self.index.aplic) {
+      Some(aplic) => aplic.take_msis(),
+      None => return,
+    };
+    for (addr, data) in msis {
+      let _ = self.store(addr, data as u64, WORD);
+    }
   }
 }
@@ -1,85 +1,289 @@
 use {
-  std::{fs, ops::RangeBounds, time::Instant},
-  vrisc::{bus::dram, Emu, Exception, Xregs, REG_COUNT},
+  std::{env, fs, process},
+  vrisc::{
+    dev::{
+      audio::{Audio, Wav},
+      rtc::{Clock, Rtc},
+      serial::{Null, Serial, Stdio},
+      virtio::{
+        blk::Blk,
+        console::Console,
+        net::{Loopback, Net, Pcap},
+        rng::Rng,
+        slirp::User,
+      },
+    },
+    Emu, Exit, Htif,
+  },
 };
 
-pub const SP: u64 = dram::END;
+const RAM: usize = 128 * 1024 * 1024;
 
-pub fn xregs<const N: usize>(regs: [(usize, u64); N]) -> [u64; REG_COUNT] {
-  let mut xregs = Xregs::new().into_inner();
-  for &(i, rx) in regs.iter() {
-    xregs[i] = rx;
-  }
-  xregs
-}
+const USAGE: &str = "usage: cli [OPTIONS] IMAGE
 
-#[rustfmt::skip]
-macro_rules! xreg {
-  (x0) => {0};
-  (x1) => {1};
-  (x2) => {2};
-  (x3) => {3};
-  (x4) => {4};
-}
+Runs an ELF image, or a raw image loaded at the start of the main memory,
+until the guest exits through the HTIF or powers the machine off.
 
-macro_rules! xregs {
-  ($($reg:ident = $eq:literal),* $(,)?) => {
-    xregs([$((xreg!($reg), $eq)),*])
-  };
-}
+options:
+  --tohost ADDR    serve the HTIF at ADDR instead of the `tohost` symbol
+  --fromhost ADDR  answer the HTIF at ADDR, with `--tohost`
+  --blk PATH       attach the raw disk image at PATH as a virtio block device
+  --blk-ro PATH    same, but read-only
+  --share TAG=PATH share the host directory PATH through virtio-9p, which
+                   the guest mounts with `mount -t 9p -o trans=virtio TAG`
+  --share-ro TAG=PATH
+                   same, but read-only
+  --net MODE       attach a virtio network device: `user` reaches the
+                   localhost of the host through the gateway 10.0.2.2,
+                   `loopback` returns every frame to the guest
+  --pcap PATH      record the frames of the network device to PATH
+  --hvc            talk to the guest through a virtio console, its hvc0
+  --port NAME=PATH add a virtio console port NAME, served on the Unix
+                   socket PATH
+  --rng            attach a virtio entropy source
+  --rng-seed SEED  same, seeded for deterministic runs
+  --rtc CLOCK      clock of the real-time clock: `host` tells the time of
+                   the host, `virtual` starts at the epoch and follows the
+                   instructions, the same on every run
+  --wav PATH       record the audio output to the WAV file PATH";
 
-fn main() {
-  let mut emu = Emu::new(1024 * 1024); // 1Mb ram
+struct Args {
+  image: String,
+  tohost: Option<u64>,
+  fromhost: Option<u64>,
+  // disk images, and whether they are read-only
+  blk: Vec<(String, bool)>,
+  // shared directories, their tags and whether they are read-only
+  shares: Vec<(String, String, bool)>,
+  net: Option<String>,
+  pcap: Option<String>,
+  hvc: bool,
+  // named console ports and their sockets
+  ports: Vec<(String, String)>,
+  // the entropy source, and its seed
+  rng: Option<Option<u64>>,
+  rtc: Clock,
+  wav: Option<String>,
+}
 
-  let data = [
-    0x93, 0x01, 0x50, 0x00, // addi x3, x0, 5
-    0x13, 0x02, 0x60, 0x00, // addi x4, x0, 6
-    0x33, 0x81, 0x41, 0x00, // add x2, x3, x4
-  ];
+fn parse_args() -> Result<Args, String> {
+  let addr = |arg: Option<String>| {
+    let arg = arg.ok_or("missing address")?;
+    let hex = arg.trim_start_matches("0x");
+    u64::from_str_radix(hex, 16).map_err(|err| format!("{arg}: {err}"))
+  };
 
-  // run(&mut emu, &data, xregs!(x2 = 11, x3 = 5, x4 = 6));
+  let (mut image, mut tohost, mut fromhost) = (None, None, None);
+  let (mut blk, mut shares, mut net, mut pcap) =
+    (Vec::new(), Vec::new(), None, None);
+  let (mut hvc, mut ports, mut rng) = (false, Vec::new(), None);
+  let (mut rtc, mut wav) = (Clock::Host, None);
+  let mut args = env::args().skip(1);
+  while let Some(arg) = args.next() {
+    match arg.as_str() {
+      "--tohost" => tohost = Some(addr(args.next())?),
+      "--fromhost" => fromhost = Some(addr(args.next())?),
+      "--blk" | "--blk-ro" => {
+        let path = args.next().ok_or("missing disk image")?;
+        blk.push((path, arg == "--blk-ro"));
+      }
+      "--share" | "--share-ro" => {
+        let share = args.next().ok_or("missing share")?;
+        let (tag, path) = share.split_once('=').ok_or("expected TAG=PATH")?;
+        shares.push((tag.to_owned(), path.to_owned(), arg == "--share-ro"));
+      }
+      "--net" => match args.next().as_deref() {
+        Some(mode @ ("user" | "loopback")) => net = Some(mode.to_owned()),
+        Some(mode) => return Err(format!("unknown network mode: {mode}")),
+        None => return Err("missing network mode".into()),
+      },
+      "--pcap" => pcap = Some(args.next().ok_or("missing capture path")?),
+      "--hvc" => hvc = true,
+      "--port" => {
+        let port = args.next().ok_or("missing port")?;
+        let (name, path) = port.split_once('=').ok_or("expected NAME=PATH")?;
+        ports.push((name.to_owned(), path.to_owned()));
+      }
+      "--rng" => rng = Some(None),
+      "--rng-seed" => {
+        let seed = args.next().ok_or("missing seed")?;
+        let seed = seed.parse().map_err(|err| format!("{seed}: {err}"))?;
+        rng = Some(Some(seed));
+      }
+      "--rtc" => match args.next().as_deref() {
+        Some("host") => rtc = Clock::Host,
+        Some("virtual") => rtc = Clock::Virtual(0),
+        Some(clock) => return Err(format!("unknown clock: {clock}")),
+        None => return Err("missing clock".into()),
+      },
+      "--wav" => wav = Some(args.next().ok_or("missing capture path")?),
+      _ if image.is_none() && !arg.starts_with('-') => image = Some(arg),
+      _ => return Err(format!("unexpected argument: {arg}")),
+    }
+  }
 
-  {
-    let bin = fs::read("rv32-addi.bin").unwrap();
-    // let bin = fs::read("riscv-tests/isa/rv32ui-p-addi.bin").unwrap();
-    run(&mut emu, &bin, xregs!());
+  let image = image.ok_or("missing image")?;
+  if fromhost.is_some() && tohost.is_none() {
+    return Err("`--fromhost` needs `--tohost`".into());
+  }
+  if pcap.is_some() && net.is_none() {
+    return Err("`--pcap` needs `--net`".into());
   }
+  Ok(Args {
+    image,
+    tohost,
+    fromhost,
+    blk,
+    shares,
+    net,
+    pcap,
+    hvc,
+    ports,
+    rng,
+    rtc,
+    wav,
+  })
 }
 
-fn run(emu: &mut Emu, data: &[u8], xregs: [u64; REG_COUNT]) {
-  emu.with_dram(data).with_pc(dram::ADDR);
+fn main() {
+  let args = parse_args().unwrap_or_else(|err| {
+    eprintln!("{err}\n\n{USAGE}");
+    process::exit(2)
+  });
+  let image = fs::read(&args.image).unwrap_or_else(|err| {
+    eprintln!("{}: {err}", args.image);
+    process::exit(2)
+  });
 
-  let start = dram::ADDR;
-  let end = start + data.len() as u64;
-  loop {
-    if !(start..end).contains(&emu.cpu.pc) {
-      break;
+  let mut emu = Emu::new(RAM);
+  if let Some(rtc) = emu.cpu.bus.device_mut::<Rtc>() {
+    rtc.clock = args.rtc;
+  }
+  if let Some(path) = &args.wav {
+    let wav = Wav::create(path).unwrap_or_else(|err| {
+      eprintln!("{path}: {err}");
+      process::exit(2)
+    });
+    if let Some(audio) = emu.cpu.bus.device_mut::<Audio>() {
+      audio.wav = Some(wav);
+    }
+  }
+  for (path, readonly) in &args.blk {
+    let blk = Blk::open(path, *readonly).unwrap_or_else(|err| {
+      eprintln!("{path}: {err}");
+      process::exit(2)
+    });
+    if emu.cpu.bus.attach_virtio(blk).is_err() {
+      eprintln!("{path}: no virtio slot left");
+      process::exit(2)
+    }
+  }
+  for (tag, path, readonly) in &args.shares {
+    share(&mut emu, tag, path, *readonly);
+  }
+  if let Some(mode) = &args.net {
+    let mut net = match mode.as_str() {
+      "user" => Net::new(User::new()),
+      _ => Net::new(Loopback::new()),
+    };
+    if let Some(path) = &args.pcap {
+      let pcap = Pcap::create(path).unwrap_or_else(|err| {
+        eprintln!("{path}: {err}");
+        process::exit(2)
+      });
+      net = net.with_pcap(pcap);
+    }
+    if emu.cpu.bus.attach_virtio(net).is_err() {
+      eprintln!("no virtio slot left for the network device");
+      process::exit(2)
+    }
+  }
+  if let Some(seed) = args.rng {
+    if emu.cpu.bus.attach_virtio(Rng::new(seed)).is_err() {
+      eprintln!("no virtio slot left for the entropy source");
+      process::exit(2)
     }
+  }
+  if let Some(tohost) = args.tohost {
+    emu.with_htif(Htif::new(tohost, args.fromhost, Null));
+  }
 
-    match emu.cycle() {
-      Ok(inst) => {
-        println!("pc: {:x}, inst: {inst:#x}", emu.cpu.pc.wrapping_sub(4));
-      }
-      Err(ex) => {
-        println!(
-          "pc: {pc:#x}, inst: {inst:#x}",
-          inst = emu.cpu.fetch(32).unwrap(),
-          pc = emu.cpu.pc,
-        );
+  if image.starts_with(b"\x7fELF") {
+    if let Err(err) = emu.load_elf(&image) {
+      eprintln!("{}: {err:?}", args.image);
+      process::exit(2)
+    }
+  } else {
+    emu.with_dram(&image);
+  }
 
-        if let Exception::IllegalInst(inst) = ex {
-          panic!("invalid instruction: 0x{inst:x}");
-        }
+  if args.hvc || !args.ports.is_empty() {
+    let mut console =
+      if args.hvc { Console::new(Stdio::new()) } else { Console::new(Null) };
+    for (name, path) in &args.ports {
+      console = console.with_port(name, channel(path));
+    }
+    if emu.cpu.bus.attach_virtio(console).is_err() {
+      eprintln!("no virtio slot left for the console");
+      process::exit(2)
+    }
+  }
 
-        emu.cpu.catch_exception(ex);
+  // the guest talks through the virtio console with `--hvc`, otherwise
+  // through the HTIF if it has one, the UART otherwise
+  match &mut emu.htif {
+    _ if args.hvc => {}
+    Some(htif) => htif.console = Box::new(Stdio::new()),
+    None => {
+      emu.with_serial(Stdio::new());
+    }
+  }
 
-        std::thread::sleep_ms(100);
-      }
+  if let Err(err) = emu.with_fdt() {
+    // restore the terminal before exiting
+    drop(emu);
+    eprintln!("device tree: {err:?}");
+    process::exit(2)
+  }
+  let exit = emu.run();
+  // restore the terminal before exiting
+  drop(emu);
+  match exit {
+    Exit::Pass => {}
+    Exit::Fail(code) => {
+      eprintln!("FAIL: {code}");
+      process::exit(1)
     }
-    println!("{:?}", emu.cpu.xregs);
   }
+}
+
+// Host end of a console port.
+fn channel(path: &str) -> impl Serial {
+  #[cfg(unix)]
+  let channel = vrisc::dev::serial::Socket::bind(path);
+  #[cfg(not(unix))]
+  let channel = Err::<Null, _>("Unix sockets are not supported");
+  channel.unwrap_or_else(|err| {
+    eprintln!("{path}: {err}");
+    process::exit(2)
+  })
+}
 
-  for (i, &rx) in xregs.iter().enumerate() {
-    assert_eq!(rx, emu.cpu.xregs.load(i as u64), "fails at {i}");
+// Export the host directory `path` to the guest under `tag`.
+fn share(emu: &mut Emu, tag: &str, path: &str, readonly: bool) {
+  #[cfg(target_os = "linux")]
+  let share = vrisc::dev::virtio::p9::P9::open(path, tag, readonly)
+    .map_err(|err| err.to_string());
+  #[cfg(not(target_os = "linux"))]
+  let share = Err::<vrisc::dev::virtio::rng::Rng, _>(
+    "directory sharing is not supported",
+  );
+  let share = share.unwrap_or_else(|err| {
+    eprintln!("{path}: {err}");
+    process::exit(2)
+  });
+  if emu.cpu.bus.attach_virtio(share).is_err() {
+    eprintln!("{path}: no virtio slot left");
+    process::exit(2)
   }
 }
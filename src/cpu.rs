@@ -1,11 +1,17 @@
 use crate::{
-  bus::dram,
-  csr::{x, MCAUSE, MEDELEG, MEPC, MTVAL, MTVEC},
-  dev::vga::Vga,
+  bus::{dram, rom},
+  csr::{
+    highest_interrupt, x, Addr, MCAUSE, MEDELEG, MEIP_BIT, MEPC, MIDELEG, MIE,
+    MIP, MIREG, MISA, MISELECT, MSIP_BIT, MTIP_BIT, MTOPEI, MTVAL, MTVEC,
+    SCAUSE, SEIP_BIT, SEPC, SIREG, SISELECT, STIMECMP, STOPEI, STVAL, STVEC,
+  },
+  trap::INTERRUPT,
   Bus, Dram, Exception, State, Trap, DRAM_SIZE,
 };
 
 pub const REG_COUNT: usize = 32;
+/// Where the hart starts, the reset stub of the boot ROM.
+pub const RESET_VECTOR: u64 = rom::ADDR;
 pub const POINTER_TO_DTB: u64 = 0x1020;
 
 /// Access type that is used in the virtual address translation process. It decides which exception
@@ -56,11 +62,27 @@ impl Xregs {
   }
 }
 
+/// Bits of `mip` that are driven by the devices rather than by software.
+/// `SEIP` is writable too, the devices' line is ORed into it.
+const DEVICE_MIP: u64 = MSIP_BIT | MTIP_BIT | MEIP_BIT;
+
 pub const BYTE: u8 = 8;
 pub const HALF: u8 = 16;
 pub const WORD: u8 = 32;
 pub const DWORD: u8 = 64;
 
+/// How the hart handles misaligned loads and stores. Misaligned atomics
+/// always raise an address-misaligned exception.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum Misaligned {
+  /// Perform the access in hardware, it faults only if it crosses the end of
+  /// a memory region.
+  Emulate,
+  /// Raise `LoadAddrMisalign`/`StoreAMOAddrMisalign` and let the firmware
+  /// emulate the access.
+  Trap,
+}
+
 #[derive(Debug)]
 pub struct Cpu {
   pub pc: u64,
@@ -68,22 +90,33 @@ pub struct Cpu {
   pub xregs: Xregs,
   pub state: State,
   pub bus: Bus,
+  pub misaligned: Misaligned,
+  /// Address reserved by the last `lr`.
+  pub reservation: Option<u64>,
 }
 
 impl Cpu {
+  /// `mmu-type` of the hart in the device tree, paging isn't implemented.
+  pub const MMU_TYPE: &str = "riscv,none";
+
   pub fn new(cap: usize) -> Self {
     Self {
-      pc: 0,
+      pc: RESET_VECTOR,
       mode: Mode::Machine,
       xregs: Xregs::new(),
       state: State::new(),
-      bus: Bus { vga: Vga::new(), dram: Dram::with_capacity(cap) },
+      bus: Bus::new(Dram::with_capacity(cap)),
+      misaligned: Misaligned::Emulate,
+      reservation: None,
     }
   }
 
   pub(crate) fn debug(&self, _inst: u64, _name: &str) {}
 
   /// Translate a virtual address to a physical address for the paged virtual-memory system.
+  ///
+  /// Paging isn't implemented yet. Page-table walks must read the entries
+  /// with the supervisor endianness, see [`Cpu::big_endian`].
   fn translate(
     &mut self,
     addr: u64,
@@ -92,21 +125,72 @@ impl Cpu {
     Ok(addr)
   }
 
+  /// Take the trap for an exception raised by the instruction at `pc`, which
+  /// is reported in `*epc`.
   pub fn catch_exception(&mut self, ex: Exception) -> Trap {
-    let pc = ex.epc(self.pc);
     let cause = ex.cause();
+    let delegated =
+      self.mode < Mode::Machine && (self.state.load(MEDELEG) >> cause) & 1 == 1;
+    self.enter_trap(cause, ex.tval(self.pc), delegated);
+    Trap::from_ex(ex)
+  }
+
+  /// Take the trap for the interrupt `irq` before the instruction at `pc`,
+  /// which is reported in `*epc`.
+  pub fn catch_interrupt(&mut self, irq: u64) {
+    let delegated = (self.state.load(MIDELEG) >> irq) & 1 == 1;
+    self.enter_trap(INTERRUPT | irq, 0, delegated);
+  }
+
+  /// Interrupt the hart takes before its next instruction: the pending and
+  /// enabled one of the highest priority, among those the privilege mode
+  /// doesn't mask. Interrupts to M-mode go before the delegated ones.
+  pub fn pending_interrupt(&self) -> Option<u64> {
+    let pending = self.state.load(MIP) & self.state.load(MIE);
+    let delegated = self.state.load(MIDELEG);
+    let (machine, supervisor) = match self.mode {
+      Mode::Machine => (self.state.load_mstatus(x::MIE) == 1, false),
+      Mode::Supervisor => (true, self.state.load_sstatus(x::SIE) == 1),
+      Mode::User => (true, true),
+      Mode::Debug => (false, false),
+    };
+    let machine = if machine { pending & !delegated } else { 0 };
+    let supervisor = if supervisor { pending & delegated } else { 0 };
+    highest_interrupt(machine).or_else(|| highest_interrupt(supervisor))
+  }
+
+  // Enter the handler of the trap `cause` in M-mode, or in S-mode if it is
+  // `delegated`, reporting the current `pc` and `tval`.
+  fn enter_trap(&mut self, cause: u64, tval: u64, delegated: bool) {
+    let pc = self.pc;
     let prev = self.mode;
+    // exceptions always go to the base address, interrupts to their own
+    // vector in vectored mode
+    let target = |tvec: u64| match tvec & 0b11 {
+      1 if cause & INTERRUPT != 0 => (tvec & !0b11) + 4 * (cause & !INTERRUPT),
+      _ => tvec & !0b11,
+    };
+
+    if delegated {
+      self.mode = Mode::Supervisor;
+
+      self.pc = target(self.state.load(STVEC));
 
-    if prev < Mode::Machine && (self.state.load(MEDELEG) >> cause) & 1 == 1 {
-      todo!("unimplemented")
+      self.state.store(SEPC, pc & !1);
+      self.state.store(SCAUSE, cause);
+      self.state.store(STVAL, tval);
+
+      self.state.store_sstatus(x::SPIE, self.state.load_sstatus(x::SIE));
+      self.state.store_sstatus(x::SIE, 0);
+      self.state.store_sstatus(x::SPP, (prev == Mode::Supervisor) as u64);
     } else {
       self.mode = Mode::Machine;
 
-      self.pc = self.state.load(MTVEC) & !1;
+      self.pc = target(self.state.load(MTVEC));
 
       self.state.store(MEPC, pc & !1);
       self.state.store(MCAUSE, cause);
-      self.state.store(MTVAL, ex.mtval(pc));
+      self.state.store(MTVAL, tval);
 
       self.state.store_mstatus(x::MPIE, self.state.load_mstatus(x::MIE));
       self.state.store_mstatus(x::MIE, 0);
@@ -116,8 +200,123 @@ impl Cpu {
         panic!("privilege mode is invalid: 0b{:b}", prev as usize)
       }
     }
+  }
 
-    Trap::from_ex(ex)
+  /// Check the target of a jump or a taken branch, which must be aligned to
+  /// 4 bytes unless the compressed extension is enabled in `misa`.
+  pub(crate) fn jump_target(&self, target: u64) -> Result<u64, Exception> {
+    let mask = if self.state.load(MISA) & 1 << 2 != 0 { 0b01 } else { 0b11 };
+    match target & mask {
+      0 => Ok(target),
+      _ => Err(Exception::InstAddrMisalign(target)),
+    }
+  }
+
+  /// Read a CSR. The AIA registers are forwarded to the interrupt files,
+  /// `None` means the access is illegal.
+  pub(crate) fn load_csr(&self, csr: Addr) -> Option<u64> {
+    let imsic = |level| self.bus.imsic(level);
+    Some(match csr {
+      MIREG => {
+        let select = self.state.load(MISELECT);
+        match iprio(select) {
+          Some(prio) => prio,
+          None => imsic(Mode::Machine)?.load_indirect(select)?,
+        }
+      }
+      SIREG => {
+        let select = self.state.load(SISELECT);
+        match iprio(select) {
+          Some(prio) => prio,
+          None => imsic(Mode::Supervisor)?.load_indirect(select)?,
+        }
+      }
+      MTOPEI => imsic(Mode::Machine)?.topei(),
+      STOPEI => imsic(Mode::Supervisor)?.topei(),
+      STIMECMP if !self.stimecmp_allowed() => return None,
+      _ => self.state.load(csr),
+    })
+  }
+
+  /// Write a CSR. The AIA registers are forwarded to the interrupt files,
+  /// `None` means the access is illegal.
+  pub(crate) fn store_csr(&mut self, csr: Addr, val: u64) -> Option<()> {
+    match csr {
+      MIREG => {
+        let select = self.state.load(MISELECT);
+        if iprio(select).is_none() {
+          self.bus.imsic_mut(Mode::Machine)?.store_indirect(select, val)?;
+        }
+      }
+      SIREG => {
+        let select = self.state.load(SISELECT);
+        if iprio(select).is_none() {
+          self.bus.imsic_mut(Mode::Supervisor)?.store_indirect(select, val)?;
+        }
+      }
+      // any write claims the interrupt, the written value is ignored
+      MTOPEI => {
+        self.bus.imsic_mut(Mode::Machine)?.claim();
+      }
+      STOPEI => {
+        self.bus.imsic_mut(Mode::Supervisor)?.claim();
+      }
+      STIMECMP if !self.stimecmp_allowed() => return None,
+      _ => self.state.store(csr, val),
+    }
+    Some(())
+  }
+
+  /// Below machine mode `stimecmp` is only accessible while `menvcfg.STCE`
+  /// enables the supervisor timer.
+  fn stimecmp_allowed(&self) -> bool {
+    self.mode == Mode::Machine || self.state.sstc()
+  }
+
+  /// Latch `mtime` of the CLINT into the `time` CSR, without a CLINT time
+  /// advances by one tick per instruction.
+  fn sync_time(&mut self) {
+    match self.bus.clint() {
+      Some(clint) => self.state.set_time(clint.mtime),
+      None => self.state.cycle_time(),
+    }
+  }
+
+  /// Latch the interrupt lines driven by the devices into `mip`.
+  fn sync_interrupts(&mut self) {
+    let lines = self.bus.mip();
+    let mip = self.state.load_software_mip() & !DEVICE_MIP;
+    self.state.store(MIP, mip | (lines & DEVICE_MIP));
+    self.state.set_seip(lines & SEIP_BIT != 0);
+  }
+
+  /// Whether data accesses made on behalf of `mode` are big-endian, as
+  /// selected by `mstatus.MBE`, `SBE` and `UBE`. Instruction fetches are
+  /// always little-endian.
+  pub fn big_endian(&self, mode: Mode) -> bool {
+    let bit = match mode {
+      Mode::Machine | Mode::Debug => x::MBE,
+      Mode::Supervisor => x::SBE,
+      Mode::User => x::UBE,
+    };
+    self.state.load_mstatus(bit) == 1
+  }
+
+  pub(crate) fn load(
+    &mut self,
+    v_addr: u64,
+    size: u8,
+  ) -> Result<u64, Exception> {
+    if self.misaligned == Misaligned::Trap && !aligned(v_addr, size) {
+      return Err(Exception::LoadAddrMisalign(v_addr));
+    }
+
+    let p_addr = self.translate(v_addr, AccessType::Load)?;
+    let value = self
+      .bus
+      .load(p_addr, size)
+      .map_err(|_| Exception::LoadAccessFault(v_addr))?;
+    Ok(if self.big_endian(self.mode) { swap_bytes(value, size) } else { value })
   }
 
   pub(crate) fn store(
@@ -126,13 +325,22 @@ impl Cpu {
     value: u64,
     size: u8,
   ) -> Result<(), Exception> {
+    if self.misaligned == Misaligned::Trap && !aligned(v_addr, size) {
+      return Err(Exception::StoreAMOAddrMisalign(v_addr));
+    }
+
     let p_addr = self.translate(v_addr, AccessType::Store)?;
-    self.bus.store(p_addr, value, size)
+    let value =
+      if self.big_endian(self.mode) { swap_bytes(value, size) } else { value };
+    self
+      .bus
+      .store(p_addr, value, size)
+      .map_err(|_| Exception::StoreAMOAccessFault(v_addr))
   }
 
   pub fn fetch(&mut self, size: u8) -> Result<u64, Exception> {
     let (HALF | WORD) = size else {
-      return Err(Exception::InstAccessFault);
+      return Err(Exception::InstAccessFault(self.pc));
     };
 
     let p_pc = self.translate(self.pc, AccessType::Instruction)?;
@@ -141,14 +349,42 @@ impl Cpu {
     // should be `InstAccessFault`.
     match self.bus.load(p_pc, size) {
       Ok(value) => Ok(value),
-      Err(_) => Err(Exception::InstAccessFault),
+      Err(_) => Err(Exception::InstAccessFault(self.pc)),
     }
   }
 
   pub fn execute(&mut self) -> Result<u64, Exception> {
+    self.bus.tick();
+    self.sync_time();
+    self.sync_interrupts();
+    if let Some(irq) = self.pending_interrupt() {
+      self.catch_interrupt(irq);
+    }
+
     let inst = self.fetch(WORD)?;
     self.execute_general(inst)?;
     self.pc += 4;
     Ok(inst)
   }
 }
+
+// Major interrupt priorities (`iprio0`..`iprio15`) are read-only zero, on RV64
+// only the even-numbered registers exist.
+fn iprio(select: u64) -> Option<u64> {
+  (0x30..=0x3f).contains(&select).then_some(0).filter(|_| select & 1 == 0)
+}
+
+pub(crate) fn aligned(addr: u64, size: u8) -> bool {
+  addr.is_multiple_of(size as u64 / 8)
+}
+
+// The bus is little-endian, so big-endian accesses reverse the bytes of the
+// accessed value.
+fn swap_bytes(value: u64, size: u8) -> u64 {
+  match size {
+    HALF => (value as u16).swap_bytes() as u64,
+    WORD => (value as u32).swap_bytes() as u64,
+    DWORD => value.swap_bytes(),
+    _ => value,
+  }
+}
@@ -57,6 +57,22 @@ reg! { "Supervisor traps setup"
   STVEC = 0x105
 }
 
+reg! { "Supervisor timers (Sstc)"
+  /// Supervisor timer compare
+  STIMECMP = 0x14d
+}
+
+reg! { "Supervisor-level interrupts (Ssaia)"
+  /// Supervisor indirect register select
+  SISELECT = 0x150
+  /// Supervisor indirect register alias
+  SIREG = 0x151
+  /// Supervisor top external interrupt
+  STOPEI = 0x15c
+  /// Supervisor top interrupt
+  STOPI = 0xdb0
+}
+
 reg! { "Supervisor traps handling"
   /// Supervisor exception program counter
   SEPC = 0x141
@@ -68,6 +84,11 @@ reg! { "Supervisor traps handling"
   SIP = 0x144
 }
 
+reg! { "Machine information"
+  /// Hart ID, read-only
+  MHARTID = 0xf14
+}
+
 reg! { "Machine traps setup"
   /// Machine status register.
   MSTATUS = 0x300
@@ -83,6 +104,11 @@ reg! { "Machine traps setup"
   MTVEC = 0x305
 }
 
+reg! { "Machine configuration"
+  /// Machine environment configuration
+  MENVCFG = 0x30a
+}
+
 reg! { "Machine traps handling"
   /// Machine exception program counter
   MEPC = 0x341
@@ -94,6 +120,17 @@ reg! { "Machine traps handling"
   MIP = 0x344
 }
 
+reg! { "Machine-level interrupts (Smaia)"
+  /// Machine indirect register select
+  MISELECT = 0x350
+  /// Machine indirect register alias
+  MIREG = 0x351
+  /// Machine top external interrupt
+  MTOPEI = 0x35c
+  /// Machine top interrupt
+  MTOPI = 0xfb0
+}
+
 reg! { "MIP fields" as u64
   /// Supervisor software interrupt.
   SSIP_BIT= 1 << 1
@@ -109,6 +146,9 @@ reg! { "MIP fields" as u64
   MEIP_BIT = 1 << 11
 }
 
+/// Major interrupts from the highest to the lowest default priority.
+pub const INTERRUPT_ORDER: [u64; 6] = [11, 3, 7, 9, 1, 5];
+
 const fn mask<const R: Range>() -> u64 {
   let (start, end) = R;
   let len = end - start;
@@ -122,21 +162,29 @@ const _: () = {
   assert!(mask::<{ x::MPP }>() == 0b1100000000000);
 };
 
-const SSTATUS_MASK: u64 = mask! { x::SIE x::SPIE x::SPP };
+const SSTATUS_MASK: u64 = mask! { x::SIE x::SPIE x::UBE x::SPP };
 
 pub mod x {
   field![SIE = 1:1];
   field![SPIE = 5:5];
+  field![UBE = 6:6];
   field![SPP = 8:8];
   //
   field![MIE = 3:3];
   field![MPIE = 7:7];
   field![MPP = 11:12];
+  field![SBE = 36:36];
+  field![MBE = 37:37];
+  //
+  field![STCE = 63:63];
 }
 
 #[derive(Debug)]
 pub struct State {
   regs: [u64; REGISTERS],
+  // external supervisor interrupt, `mip.SEIP` reads as its OR with the
+  // software-writable bit
+  seip: bool,
 }
 
 impl State {
@@ -152,25 +200,54 @@ impl State {
         (1 << 2) | // Extensions[2] (Compressed extension)
         1; // Extensions[0] (Atomic extension)
     regs[MISA as usize] = misa;
+    // the timer never fires until the supervisor programs it
+    regs[STIMECMP as usize] = u64::MAX;
 
-    Self { regs }
+    Self { regs, seip: false }
   }
 
   pub fn cycle_time(&mut self) {
-    self.regs[TIME as usize] = self.regs[TIME as usize].wrapping_add(1);
+    self.set_time(self.regs[TIME as usize].wrapping_add(1));
+  }
+
+  /// Set the value of the `time` CSR.
+  pub fn set_time(&mut self, time: u64) {
+    self.regs[TIME as usize] = time;
+    self.sync_timer();
+  }
+
+  /// Drive the external supervisor interrupt line.
+  pub fn set_seip(&mut self, level: bool) {
+    self.seip = level;
+  }
+
+  /// Value of `mip` the set and clear forms of the CSR instructions modify:
+  /// `SEIP` is only its software-writable bit.
+  pub fn load_software_mip(&self) -> u64 {
+    self.regs[MIP as usize]
+  }
+
+  fn mip(&self) -> u64 {
+    self.regs[MIP as usize] | if self.seip { SEIP_BIT } else { 0 }
   }
 
   pub fn load(&self, addr: Addr) -> u64 {
     match addr {
       SSTATUS => self.regs[MSTATUS as usize] & SSTATUS_MASK,
       SIE => self.regs[MIE as usize] & self.regs[MIDELEG as usize],
-      SIP => self.regs[MIP as usize] & self.regs[MIDELEG as usize],
+      MIP => self.mip(),
+      SIP => self.mip() & self.regs[MIDELEG as usize],
+      MTOPI => top_interrupt(
+        self.mip() & self.regs[MIE as usize] & !self.regs[MIDELEG as usize],
+      ),
+      STOPI => top_interrupt(self.load(SIP) & self.load(SIE)),
       _ => self.regs[addr as usize],
     }
   }
 
   pub fn store(&mut self, addr: Addr, val: u64) {
     match addr {
+      MHARTID | MTOPI | STOPI => {}
       SSTATUS => {
         self.regs[MSTATUS as usize] =
           (self.regs[MSTATUS as usize] & !SSTATUS_MASK) | (val & SSTATUS_MASK);
@@ -185,10 +262,35 @@ impl State {
         self.regs[MIP as usize] =
           (self.regs[MIP as usize] & !mask) | (val & mask);
       }
+      MIP => {
+        // `STIP` is driven by `stimecmp` while Sstc is enabled
+        let mask = if self.sstc() { !STIP_BIT } else { !0 };
+        self.regs[MIP as usize] =
+          (self.regs[MIP as usize] & !mask) | (val & mask);
+      }
+      STIMECMP | MENVCFG => {
+        self.regs[addr as usize] = val;
+        self.sync_timer();
+      }
       _ => self.regs[addr as usize] = val,
     }
   }
 
+  /// Whether `menvcfg.STCE` enables the supervisor timer.
+  pub fn sstc(&self) -> bool {
+    self.load_bits(MENVCFG, x::STCE) == 1
+  }
+
+  // Recompute `mip.STIP` from the comparison of `time` against `stimecmp`.
+  fn sync_timer(&mut self) {
+    if self.sstc() {
+      let time = self.regs[TIME as usize];
+      let stip =
+        if time >= self.regs[STIMECMP as usize] { STIP_BIT } else { 0 };
+      self.regs[MIP as usize] = (self.regs[MIP as usize] & !STIP_BIT) | stip;
+    }
+  }
+
   pub fn store_bits(&mut self, addr: Addr, (start, end): Range, val: u64) {
     let mask = (!0 << end) | !(!0 << start);
     self.store(addr, (self.load(addr) & mask) | (val << start))
@@ -215,3 +317,15 @@ impl State {
     self.store_bits(MSTATUS, bits, val);
   }
 }
+
+/// Major interrupt of the highest default priority among the bits of
+/// `interrupts`.
+pub fn highest_interrupt(interrupts: u64) -> Option<u64> {
+  INTERRUPT_ORDER.into_iter().find(|&i| interrupts >> i & 1 == 1)
+}
+
+// Value of `mtopi`/`stopi` for the pending and enabled interrupts. Priority
+// numbers aren't implemented, so every interrupt reports priority 1.
+fn top_interrupt(interrupts: u64) -> u64 {
+  highest_interrupt(interrupts).map_or(0, |i| i << 16 | 1)
+}
@@ -0,0 +1,435 @@
+use crate::{
+  cpu::WORD,
+  csr::MEIP_BIT,
+  dev::Device,
+  fdt::{phandle, Fdt, Mapping},
+  Exception,
+};
+
+/// Number of interrupt sources of the domain, including the reserved
+/// source 0.
+pub const APLIC_SOURCES: usize = 64;
+
+/// Register offsets of an APLIC domain.
+pub mod reg {
+  pub const DOMAINCFG: u64 = 0x0000;
+  pub const SOURCECFG: u64 = 0x0004;
+  pub const MMSIADDRCFG: u64 = 0x1bc0;
+  pub const MMSIADDRCFGH: u64 = 0x1bc4;
+  pub const SMSIADDRCFG: u64 = 0x1bc8;
+  pub const SMSIADDRCFGH: u64 = 0x1bcc;
+  pub const SETIP: u64 = 0x1c00;
+  pub const SETIPNUM: u64 = 0x1cdc;
+  pub const IN_CLRIP: u64 = 0x1d00;
+  pub const CLRIPNUM: u64 = 0x1ddc;
+  pub const SETIE: u64 = 0x1e00;
+  pub const SETIENUM: u64 = 0x1edc;
+  pub const CLRIE: u64 = 0x1f00;
+  pub const CLRIENUM: u64 = 0x1fdc;
+  pub const SETIPNUM_LE: u64 = 0x2000;
+  pub const SETIPNUM_BE: u64 = 0x2004;
+  pub const GENMSI: u64 = 0x3000;
+  pub const TARGET: u64 = 0x3004;
+  pub const IDC: u64 = 0x4000;
+}
+
+/// Registers of the interrupt delivery control structure of a hart.
+pub mod idc {
+  pub const IDELIVERY: u64 = 0x00;
+  pub const IFORCE: u64 = 0x04;
+  pub const ITHRESHOLD: u64 = 0x08;
+  pub const TOPI: u64 = 0x18;
+  pub const CLAIMI: u64 = 0x1c;
+}
+
+/// Source modes of `sourcecfg`.
+mod sm {
+  pub const INACTIVE: u32 = 0;
+  pub const DETACHED: u32 = 1;
+  pub const EDGE1: u32 = 4;
+  pub const EDGE0: u32 = 5;
+  pub const LEVEL1: u32 = 6;
+  pub const LEVEL0: u32 = 7;
+}
+
+const WORDS: usize = APLIC_SOURCES.div_ceil(32);
+
+const DOMAINCFG_IE: u32 = 1 << 8;
+const DOMAINCFG_DM: u32 = 1 << 2;
+
+/// Advanced platform-level interrupt controller with a single machine-level
+/// domain. Interrupts are delivered either directly to the hart through its
+/// IDC, or as MSIs written to an IMSIC.
+#[derive(Debug)]
+pub struct Aplic {
+  msi_base: u64,
+  domaincfg: u32,
+  sourcecfg: [u32; APLIC_SOURCES],
+  target: [u32; APLIC_SOURCES],
+  msiaddrcfg: [u32; 4],
+  // bit sets indexed by the source number
+  pending: u64,
+  enabled: u64,
+  input: u64,
+  // interrupt delivery control of the only hart
+  idelivery: u32,
+  iforce: u32,
+  ithreshold: u32,
+  msis: Vec<(u64, u32)>,
+}
+
+impl Aplic {
+  pub const SIZE: u64 = reg::IDC + 0x20;
+
+  /// `msi_base` is the address of the machine-level interrupt file the
+  /// domain sends its MSIs to until `mmsiaddrcfg` is reprogrammed.
+  pub fn new(msi_base: u64) -> Self {
+    let ppn = msi_base >> 12;
+    Self {
+      msi_base,
+      domaincfg: 0,
+      sourcecfg: [0; APLIC_SOURCES],
+      target: [0; APLIC_SOURCES],
+      msiaddrcfg: [ppn as u32, (ppn >> 32) as u32 & 0xfff, 0, 0],
+      pending: 0,
+      enabled: 0,
+      input: 0,
+      idelivery: 0,
+      iforce: 0,
+      ithreshold: 0,
+      msis: Vec::new(),
+    }
+  }
+
+  /// Drive the input wire of `source`.
+  pub fn set_input(&mut self, source: usize, level: bool) {
+    if source == 0 || source >= APLIC_SOURCES {
+      return;
+    }
+
+    let bit = 1 << source;
+    let prev = self.rectified() & bit;
+    self.input = if level { self.input | bit } else { self.input & !bit };
+    let next = self.rectified() & bit;
+
+    match self.sourcecfg[source] {
+      sm::EDGE1 | sm::EDGE0 if prev == 0 && next != 0 => self.pending |= bit,
+      sm::LEVEL1 | sm::LEVEL0 if self.msi_mode() => {
+        self.pending = (self.pending & !bit) | next
+      }
+      _ => {}
+    }
+    self.forward();
+  }
+
+  /// Interrupt reported by the IDC of the hart, in `topi` format.
+  pub fn topi(&self) -> u32 {
+    if self.msi_mode() {
+      return 0;
+    }
+
+    let mut top: Option<(u32, usize)> = None;
+    let candidates = self.pending() & self.enabled;
+    for i in (1..APLIC_SOURCES).filter(|i| candidates >> i & 1 == 1) {
+      // only hart 0 exists
+      if self.target[i] >> 18 != 0 {
+        continue;
+      }
+      let prio = self.target[i] & 0xff;
+      if top.is_none_or(|(best, _)| prio < best) {
+        top = Some((prio, i));
+      }
+    }
+
+    match top {
+      Some((prio, i)) if self.ithreshold == 0 || prio < self.ithreshold => {
+        (i as u32) << 16 | prio
+      }
+      _ => 0,
+    }
+  }
+
+  /// Claim the interrupt reported by `topi`, as a read of `claimi` does.
+  pub fn claim(&mut self) -> u32 {
+    let top = self.topi();
+    match top >> 16 {
+      0 => self.iforce = 0,
+      i => self.clear_pending(1 << i),
+    }
+    top
+  }
+
+  /// Whether the domain signals an external interrupt to the hart.
+  pub fn irq(&self) -> bool {
+    !self.msi_mode()
+      && self.domaincfg & DOMAINCFG_IE != 0
+      && self.idelivery == 1
+      && (self.iforce == 1 || self.topi() != 0)
+  }
+
+  /// Take the MSIs generated since the last call as `(address, data)` pairs.
+  pub fn take_msis(&mut self) -> Vec<(u64, u32)> {
+    std::mem::take(&mut self.msis)
+  }
+
+  fn msi_mode(&self) -> bool {
+    self.domaincfg & DOMAINCFG_DM != 0
+  }
+
+  fn source(&self, offset: u64) -> Option<usize> {
+    let i = (offset / 4 + 1) as usize;
+    (i < APLIC_SOURCES).then_some(i)
+  }
+
+  fn bit(&self, num: u32) -> u64 {
+    match num as usize {
+      i @ 1..APLIC_SOURCES => 1 << i,
+      _ => 0,
+    }
+  }
+
+  fn bits(&self, offset: u64, value: u32) -> u64 {
+    match (offset / 4) as usize {
+      i @ 0..WORDS => ((value as u64) << (i * 32)) & !1,
+      _ => 0,
+    }
+  }
+
+  fn words(&self, offset: u64, set: u64) -> u32 {
+    match (offset / 4) as usize {
+      i @ 0..WORDS => (set >> (i * 32)) as u32,
+      _ => 0,
+    }
+  }
+
+  fn active(&self) -> u64 {
+    (1..APLIC_SOURCES)
+      .filter(|&i| self.sourcecfg[i] != sm::INACTIVE)
+      .fold(0, |set, i| set | 1 << i)
+  }
+
+  // Input wires after inversion by the source modes, detached and
+  // inactive sources always read as low.
+  fn rectified(&self) -> u64 {
+    (1..APLIC_SOURCES).fold(0, |set, i| {
+      let input = self.input >> i & 1;
+      set
+        | match self.sourcecfg[i] {
+          sm::EDGE1 | sm::LEVEL1 => input,
+          sm::EDGE0 | sm::LEVEL0 => input ^ 1,
+          _ => 0,
+        } << i
+    })
+  }
+
+  fn level(&self) -> u64 {
+    (1..APLIC_SOURCES)
+      .filter(|&i| matches!(self.sourcecfg[i], sm::LEVEL1 | sm::LEVEL0))
+      .fold(0, |set, i| set | 1 << i)
+  }
+
+  // In direct delivery mode the pending bit of a level-sensitive source
+  // follows its rectified input.
+  fn pending(&self) -> u64 {
+    if self.msi_mode() {
+      self.pending
+    } else {
+      (self.pending & !self.level()) | (self.rectified() & self.level())
+    }
+  }
+
+  fn set_pending(&mut self, bits: u64) {
+    // a level-sensitive source can only be set pending while asserted
+    let level = self.level();
+    self.pending |= bits & self.active() & (!level | self.rectified());
+  }
+
+  // A level-sensitive source whose input is still asserted becomes pending
+  // again as soon as it is cleared.
+  fn clear_pending(&mut self, bits: u64) {
+    self.pending &= !bits;
+    self.pending |= bits & self.active() & self.level() & self.rectified();
+  }
+
+  fn configure(&mut self, i: usize, value: u32) {
+    // bit D (delegation) is read-only zero without child domains
+    let mode = match value & 0x7 {
+      mode @ (sm::DETACHED | sm::EDGE1..=sm::LEVEL0)
+        if value & 1 << 10 == 0 =>
+      {
+        mode
+      }
+      _ => sm::INACTIVE,
+    };
+
+    self.sourcecfg[i] = mode;
+    if mode == sm::INACTIVE {
+      self.pending &= !(1 << i);
+      self.enabled &= !(1 << i);
+      self.target[i] = 0;
+    } else if self.target[i] == 0 && !self.msi_mode() {
+      self.target[i] = 1;
+    }
+  }
+
+  fn msi_addr(&self, hart: u32) -> u64 {
+    let [lo, hi, ..] = self.msiaddrcfg.map(|x| x as u64);
+    let hart = hart as u64;
+
+    let ppn = (hi & 0xfff) << 32 | lo;
+    let lhxw = hi >> 12 & 0xf;
+    let hhxw = hi >> 16 & 0x7;
+    let lhxs = hi >> 20 & 0x7;
+    let hhxs = hi >> 24 & 0x1f;
+
+    let group = (hart >> lhxw) & ((1 << hhxw) - 1);
+    let hart = hart & ((1 << lhxw) - 1);
+    (ppn | group << (hhxs + 12) | hart << lhxs) << 12
+  }
+
+  // Turn the pending and enabled interrupts into MSIs.
+  fn forward(&mut self) {
+    if !self.msi_mode() || self.domaincfg & DOMAINCFG_IE == 0 {
+      return;
+    }
+
+    let ready = self.pending & self.enabled;
+    for i in (1..APLIC_SOURCES).filter(|i| ready >> i & 1 == 1) {
+      let target = self.target[i];
+      let addr = self.msi_addr(target >> 18);
+      self.msis.push((addr, target & 0x7ff));
+    }
+    self.clear_pending(ready);
+  }
+}
+
+impl Device for Aplic {
+  fn load(&mut self, addr: u64, size: u8) -> Result<u64, Exception> {
+    if size != WORD || !addr.is_multiple_of(4) {
+      return Err(Exception::LoadAccessFault(addr));
+    }
+
+    Ok(match addr {
+      reg::DOMAINCFG => 0x8000_0000 | self.domaincfg,
+      reg::SOURCECFG..reg::MMSIADDRCFG => {
+        self.source(addr - reg::SOURCECFG).map_or(0, |i| self.sourcecfg[i])
+      }
+      reg::MMSIADDRCFG..=reg::SMSIADDRCFGH => {
+        self.msiaddrcfg[(addr - reg::MMSIADDRCFG) as usize / 4]
+      }
+      reg::SETIP..reg::SETIPNUM => {
+        self.words(addr - reg::SETIP, self.pending())
+      }
+      reg::IN_CLRIP..reg::CLRIPNUM => {
+        self.words(addr - reg::IN_CLRIP, self.rectified())
+      }
+      reg::SETIE..reg::SETIENUM => self.words(addr - reg::SETIE, self.enabled),
+      reg::GENMSI => 0,
+      reg::TARGET..reg::IDC => {
+        self.source(addr - reg::TARGET).map_or(0, |i| self.target[i])
+      }
+      _ if addr >= reg::IDC => match addr - reg::IDC {
+        idc::IDELIVERY => self.idelivery,
+        idc::IFORCE => self.iforce,
+        idc::ITHRESHOLD => self.ithreshold,
+        idc::TOPI => self.topi(),
+        idc::CLAIMI => self.claim(),
+        _ => 0,
+      },
+      _ => 0,
+    } as u64)
+  }
+
+  fn store(
+    &mut self,
+    addr: u64,
+    value: u64,
+    size: u8,
+  ) -> Result<(), Exception> {
+    if size != WORD || !addr.is_multiple_of(4) {
+      return Err(Exception::StoreAMOAccessFault(addr));
+    }
+
+    let value = value as u32;
+    match addr {
+      reg::DOMAINCFG => self.domaincfg = value & (DOMAINCFG_IE | DOMAINCFG_DM),
+      reg::SOURCECFG..reg::MMSIADDRCFG => {
+        if let Some(i) = self.source(addr - reg::SOURCECFG) {
+          self.configure(i, value);
+        }
+      }
+      // the domain has no supervisor-level children, so only the
+      // machine-level MSI address is writable
+      reg::MMSIADDRCFG => self.msiaddrcfg[0] = value,
+      reg::MMSIADDRCFGH => self.msiaddrcfg[1] = value & 0x1f7f_ffff,
+      reg::SETIP..reg::SETIPNUM => {
+        let bits = self.bits(addr - reg::SETIP, value);
+        self.set_pending(bits);
+      }
+      reg::SETIPNUM | reg::SETIPNUM_LE => self.set_pending(self.bit(value)),
+      reg::SETIPNUM_BE => self.set_pending(self.bit(value.swap_bytes())),
+      reg::IN_CLRIP..reg::CLRIPNUM => {
+        let bits = self.bits(addr - reg::IN_CLRIP, value);
+        self.clear_pending(bits);
+      }
+      reg::CLRIPNUM => self.clear_pending(self.bit(value)),
+      reg::SETIE..reg::SETIENUM => {
+        self.enabled |= self.bits(addr - reg::SETIE, value) & self.active();
+      }
+      reg::SETIENUM => self.enabled |= self.bit(value) & self.active(),
+      reg::CLRIE..reg::CLRIENUM => {
+        self.enabled &= !self.bits(addr - reg::CLRIE, value);
+      }
+      reg::CLRIENUM => self.enabled &= !self.bit(value),
+      reg::GENMSI => {
+        if self.msi_mode() {
+          let addr = self.msi_addr(value >> 18);
+          self.msis.push((addr, value & 0x7ff));
+        }
+      }
+      reg::TARGET..reg::IDC => {
+        if let Some(i) = self.source(addr - reg::TARGET) {
+          self.target[i] = if self.msi_mode() {
+            value & 0xfffc_07ff
+          } else {
+            // priority 0 is reserved and is replaced by 1
+            (value & 0xfffc_0000) | (value & 0xff).max(1)
+          };
+        }
+      }
+      _ if addr >= reg::IDC => match addr - reg::IDC {
+        idc::IDELIVERY => self.idelivery = value & 1,
+        idc::IFORCE => self.iforce = value & 1,
+        idc::ITHRESHOLD => self.ithreshold = value & 0xff,
+        _ => {}
+      },
+      _ => {}
+    }
+    self.forward();
+    Ok(())
+  }
+
+  fn reset(&mut self) {
+    *self = Self::new(self.msi_base);
+  }
+
+  fn mip(&self) -> u64 {
+    if self.irq() {
+      MEIP_BIT
+    } else {
+      0
+    }
+  }
+
+  fn fdt(&self, fdt: &mut Fdt, mapping: &Mapping) {
+    fdt.begin_node(&format!("aplic@{:x}", mapping.base));
+    fdt.prop_str("compatible", "riscv,aplic");
+    fdt.prop_reg(&[(mapping.base, mapping.size)]);
+    fdt.prop_u32("#interrupt-cells", 2);
+    fdt.prop_empty("interrupt-controller");
+    fdt.prop_u32("riscv,num-sources", APLIC_SOURCES as u32 - 1);
+    fdt.prop_u32("msi-parent", phandle::IMSIC_M);
+    fdt.prop_u32("phandle", phandle::APLIC);
+    fdt.end_node();
+  }
+}
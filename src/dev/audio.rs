@@ -0,0 +1,357 @@
+use {
+  crate::{
+    bus::dram,
+    cpu::WORD,
+    dev::{clint::TIMEBASE_FREQ, Device},
+    fdt::{Fdt, Mapping},
+    Dram, Exception,
+  },
+  std::{
+    collections::VecDeque,
+    fs::File,
+    io::{self, BufWriter, Seek, SeekFrom, Write},
+    path::Path,
+  },
+};
+
+pub mod reg {
+  /// Bits of [`control`](super::control).
+  pub const CONTROL: u64 = 0x00;
+  /// Bits of [`status`](super::status), written 1 to clear.
+  pub const STATUS: u64 = 0x04;
+  /// Frames per second, in the time of the CLINT.
+  pub const RATE: u64 = 0x08;
+  /// Layout of a sample, from [`Format`](super::Format).
+  pub const FORMAT: u64 = 0x0c;
+  /// Samples of a frame, 1 or 2.
+  pub const CHANNELS: u64 = 0x10;
+  /// Address of the ring of samples in the main memory.
+  pub const RING_LOW: u64 = 0x14;
+  pub const RING_HIGH: u64 = 0x18;
+  /// Size of the ring, in bytes. The ring is empty when the head meets the
+  /// tail, so it holds a frame less than its size at most.
+  pub const RING_SIZE: u64 = 0x1c;
+  /// Offset in the ring of the next frame played, moved by the device.
+  pub const HEAD: u64 = 0x20;
+  /// Offset in the ring past the last frame queued, moved by the guest.
+  pub const TAIL: u64 = 0x24;
+  /// Frames played so far, read-only.
+  pub const PLAYED_LOW: u64 = 0x28;
+  pub const PLAYED_HIGH: u64 = 0x2c;
+}
+
+/// Bits of the control register.
+pub mod control {
+  /// Play the ring, silence while it is empty.
+  pub const RUN: u32 = 1 << 0;
+  /// Raise the interrupt when the ring runs empty.
+  pub const EMPTY: u32 = 1 << 1;
+}
+
+/// Bits of the status register.
+pub mod status {
+  /// The ring ran empty, the guest may queue more frames.
+  pub const EMPTY: u32 = 1 << 0;
+  /// The ring reached out of the main memory, or the registers are invalid.
+  /// The device stopped.
+  pub const ERROR: u32 = 1 << 1;
+}
+
+/// Layout of a sample, little-endian.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+  U8 = 0,
+  S16 = 1,
+}
+
+impl Format {
+  pub fn from_u32(format: u32) -> Option<Self> {
+    match format {
+      0 => Some(Self::U8),
+      1 => Some(Self::S16),
+      _ => None,
+    }
+  }
+
+  pub const fn bytes(self) -> u32 {
+    match self {
+      Self::U8 => 1,
+      Self::S16 => 2,
+    }
+  }
+
+  // Sample at the start of `bytes`, as a signed 16-bit one.
+  fn sample(self, bytes: &[u8]) -> i16 {
+    match self {
+      Self::U8 => (bytes[0] as i16 - 0x80) << 8,
+      Self::S16 => i16::from_le_bytes([bytes[0], bytes[1]]),
+    }
+  }
+}
+
+/// Frame of the output, the left and the right samples.
+pub type Frame = [i16; 2];
+
+/// PCM output: the device plays the frames the guest queues in a ring of the
+/// main memory at the sample rate, and the host takes them as signed 16-bit
+/// stereo.
+#[derive(Debug)]
+pub struct Audio {
+  /// Capture of the output.
+  pub wav: Option<Wav>,
+  control: u32,
+  status: u32,
+  rate: u32,
+  format: u32,
+  channels: u32,
+  ring: [u32; 2],
+  size: u32,
+  head: u32,
+  tail: u32,
+  played: u64,
+  // ticks of the machine toward the next frame, in units of the rate
+  phase: u64,
+  // output the host did not take yet, the oldest frames are dropped
+  output: VecDeque<Frame>,
+}
+
+impl Audio {
+  pub const SIZE: u64 = 0x1000;
+  pub const RATE: u32 = 48000;
+  /// Frames of the output kept for the host.
+  pub const BACKLOG: usize = 1 << 16;
+
+  pub fn new() -> Self {
+    let mut audio = Self {
+      wav: None,
+      control: 0,
+      status: 0,
+      rate: 0,
+      format: 0,
+      channels: 0,
+      ring: [0; 2],
+      size: 0,
+      head: 0,
+      tail: 0,
+      played: 0,
+      phase: 0,
+      output: VecDeque::new(),
+    };
+    audio.reset();
+    audio
+  }
+
+  /// Frames per second of the output.
+  pub fn rate(&self) -> u32 {
+    self.rate
+  }
+
+  /// Take the frames played since the last call.
+  pub fn take(&mut self) -> Vec<Frame> {
+    self.output.drain(..).collect()
+  }
+
+  fn register(&mut self, offset: u64) -> Option<&mut u32> {
+    match offset {
+      reg::CONTROL => Some(&mut self.control),
+      reg::RATE => Some(&mut self.rate),
+      reg::FORMAT => Some(&mut self.format),
+      reg::CHANNELS => Some(&mut self.channels),
+      reg::RING_LOW => Some(&mut self.ring[0]),
+      reg::RING_HIGH => Some(&mut self.ring[1]),
+      reg::RING_SIZE => Some(&mut self.size),
+      reg::HEAD => Some(&mut self.head),
+      reg::TAIL => Some(&mut self.tail),
+      _ => None,
+    }
+  }
+
+  // Next frame of the ring, silence if it is empty, `None` if it can't be
+  // read.
+  fn next(&mut self, dram: &Dram) -> Option<Frame> {
+    let format = Format::from_u32(self.format)?;
+    if !(1..=2).contains(&self.channels) || self.rate == 0 {
+      return None;
+    }
+    let len = (format.bytes() * self.channels) as u64;
+    let [size, head, tail] = [self.size, self.head, self.tail].map(u64::from);
+    let queued =
+      (tail + size).wrapping_sub(head).checked_rem(size).unwrap_or(0);
+    if queued < len {
+      return Some([0; 2]);
+    }
+
+    let ring = self.ring[0] as u64 | (self.ring[1] as u64) << 32;
+    let at = usize::try_from(ring.checked_sub(dram::ADDR)?).ok()?;
+    let ring = dram.as_slice().get(at..at.checked_add(size as usize)?)?;
+    // a frame may wrap around the end of the ring
+    let bytes: Vec<_> =
+      (0..len).map(|i| ring[((head + i) % size) as usize]).collect();
+    let [left, right] = [0, self.channels as usize - 1].map(|channel| {
+      format.sample(&bytes[channel * format.bytes() as usize..])
+    });
+
+    self.head = ((head + len) % size) as u32;
+    if queued - len < len {
+      self.status |= status::EMPTY;
+    }
+    Some([left, right])
+  }
+
+  fn play(&mut self, frame: Frame) {
+    self.played += 1;
+    if self.output.len() == Self::BACKLOG {
+      self.output.pop_front();
+    }
+    self.output.push_back(frame);
+    if let Some(wav) = &mut self.wav {
+      // a capture that can't be written is not worth stopping the guest
+      if wav.write(self.rate, frame).is_err() {
+        self.wav = None;
+      }
+    }
+  }
+}
+
+impl Default for Audio {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl Device for Audio {
+  fn load(&mut self, addr: u64, size: u8) -> Result<u64, Exception> {
+    if size != WORD || !addr.is_multiple_of(4) {
+      return Err(Exception::LoadAccessFault(addr));
+    }
+    Ok(match addr {
+      reg::STATUS => self.status as u64,
+      reg::PLAYED_LOW => self.played as u32 as u64,
+      reg::PLAYED_HIGH => self.played >> 32,
+      offset => self.register(offset).map_or(0, |reg| *reg as u64),
+    })
+  }
+
+  fn store(
+    &mut self,
+    addr: u64,
+    value: u64,
+    size: u8,
+  ) -> Result<(), Exception> {
+    if size != WORD || !addr.is_multiple_of(4) {
+      return Err(Exception::StoreAMOAccessFault(addr));
+    }
+    if addr == reg::STATUS {
+      self.status &= !(value as u32);
+    } else if let Some(reg) = self.register(addr) {
+      *reg = value as u32;
+    }
+    Ok(())
+  }
+
+  fn tick(&mut self, dram: &mut Dram) {
+    if self.control & control::RUN == 0 {
+      return;
+    }
+    self.phase += self.rate as u64;
+    while self.phase >= TIMEBASE_FREQ {
+      self.phase -= TIMEBASE_FREQ;
+      match self.next(dram) {
+        Some(frame) => self.play(frame),
+        None => {
+          self.control &= !control::RUN;
+          self.status |= status::ERROR;
+          return;
+        }
+      }
+    }
+  }
+
+  // the capture and the output the host did not take yet are kept
+  fn reset(&mut self) {
+    self.control = 0;
+    self.status = 0;
+    self.rate = Self::RATE;
+    self.format = Format::S16 as u32;
+    self.channels = 2;
+    self.ring = [0; 2];
+    self.size = 0;
+    self.head = 0;
+    self.tail = 0;
+    self.played = 0;
+    self.phase = 0;
+  }
+
+  fn irq(&self) -> bool {
+    self.control & control::EMPTY != 0 && self.status & status::EMPTY != 0
+  }
+
+  fn fdt(&self, fdt: &mut Fdt, mapping: &Mapping) {
+    fdt.begin_node(&format!("pcm@{:x}", mapping.base));
+    fdt.prop_str("compatible", "vrisc,pcm");
+    fdt.prop_reg(&[(mapping.base, mapping.size)]);
+    if let Some(irq) = mapping.irq {
+      fdt.prop_interrupts(irq);
+    }
+    fdt.end_node();
+  }
+}
+
+/// Capture of the output in a WAV file of signed 16-bit stereo frames, at
+/// the rate of the first ones.
+#[derive(Debug)]
+pub struct Wav {
+  file: BufWriter<File>,
+  rate: Option<u32>,
+  frames: u32,
+}
+
+impl Wav {
+  const HEADER: usize = 44;
+
+  pub fn create(path: impl AsRef<Path>) -> io::Result<Self> {
+    let mut wav =
+      Self { file: BufWriter::new(File::create(path)?), rate: None, frames: 0 };
+    wav.header()?;
+    Ok(wav)
+  }
+
+  /// Append a frame played at `rate`.
+  pub fn write(&mut self, rate: u32, frame: Frame) -> io::Result<()> {
+    self.rate.get_or_insert(rate);
+    self.frames = self.frames.saturating_add(1);
+    self.file.write_all(&frame.map(i16::to_le_bytes).concat())
+  }
+
+  // The header at the start of the file, for the frames written so far.
+  fn header(&mut self) -> io::Result<()> {
+    let rate = self.rate.unwrap_or(Audio::RATE);
+    let data = self.frames.saturating_mul(4);
+    let mut header = Vec::with_capacity(Self::HEADER);
+    header.extend(b"RIFF");
+    header.extend(data.saturating_add(Self::HEADER as u32 - 8).to_le_bytes());
+    header.extend(b"WAVEfmt ");
+    header.extend(16u32.to_le_bytes());
+    header.extend(1u16.to_le_bytes()); // pcm
+    header.extend(2u16.to_le_bytes());
+    header.extend(rate.to_le_bytes());
+    header.extend(rate.saturating_mul(4).to_le_bytes());
+    header.extend(4u16.to_le_bytes());
+    header.extend(16u16.to_le_bytes());
+    header.extend(b"data");
+    header.extend(data.to_le_bytes());
+
+    let end = self.file.stream_position()?;
+    self.file.seek(SeekFrom::Start(0))?;
+    self.file.write_all(&header)?;
+    self.file.seek(SeekFrom::Start(end.max(Self::HEADER as u64)))?;
+    self.file.flush()
+  }
+}
+
+impl Drop for Wav {
+  fn drop(&mut self) {
+    // the sizes are only known at the end
+    let _ = self.header();
+  }
+}
@@ -0,0 +1,135 @@
+use crate::{
+  cpu::{DWORD, WORD},
+  csr::{MSIP_BIT, MTIP_BIT},
+  dev::Device,
+  fdt::{irq, phandle, Fdt, Mapping},
+  Dram, Exception,
+};
+
+/// Frequency of `mtime`, the CLINT counts one tick per executed instruction.
+pub const TIMEBASE_FREQ: u64 = 10_000_000;
+
+/// Register offsets of the CLINT.
+pub mod reg {
+  pub const MSIP: u64 = 0x0000;
+  pub const MTIMECMP: u64 = 0x4000;
+  pub const MTIME: u64 = 0xbff8;
+}
+
+/// SiFive-compatible core-local interruptor: the machine timer and the
+/// machine software interrupts of every hart.
+#[derive(Debug)]
+pub struct Clint {
+  pub mtime: u64,
+  msip: Vec<u32>,
+  mtimecmp: Vec<u64>,
+}
+
+impl Clint {
+  pub const SIZE: u64 = 0x10000;
+
+  pub fn new(harts: usize) -> Self {
+    Self { mtime: 0, msip: vec![0; harts], mtimecmp: vec![u64::MAX; harts] }
+  }
+
+  /// Bits of `mip` asserted for `hart`, none for a hart the CLINT doesn't
+  /// serve.
+  pub fn pending(&self, hart: usize) -> u64 {
+    let (Some(&msip), Some(&mtimecmp)) =
+      (self.msip.get(hart), self.mtimecmp.get(hart))
+    else {
+      return 0;
+    };
+    let msip = if msip & 1 == 1 { MSIP_BIT } else { 0 };
+    let mtip = if self.mtime >= mtimecmp { MTIP_BIT } else { 0 };
+    msip | mtip
+  }
+}
+
+impl Device for Clint {
+  fn load(&mut self, addr: u64, size: u8) -> Result<u64, Exception> {
+    if !matches!(size, WORD | DWORD) || !addr.is_multiple_of(size as u64 / 8) {
+      return Err(Exception::LoadAccessFault(addr));
+    }
+
+    Ok(match addr {
+      reg::MSIP..reg::MTIMECMP => {
+        let hart = ((addr - reg::MSIP) / 4) as usize;
+        self.msip.get(hart).map_or(0, |&msip| msip as u64)
+      }
+      reg::MTIMECMP..reg::MTIME => {
+        let hart = ((addr - reg::MTIMECMP) / 8) as usize;
+        part(self.mtimecmp.get(hart).copied().unwrap_or(0), addr, size)
+      }
+      _ => part(self.mtime, addr, size),
+    })
+  }
+
+  fn store(
+    &mut self,
+    addr: u64,
+    value: u64,
+    size: u8,
+  ) -> Result<(), Exception> {
+    if !matches!(size, WORD | DWORD) || !addr.is_multiple_of(size as u64 / 8) {
+      return Err(Exception::StoreAMOAccessFault(addr));
+    }
+
+    match addr {
+      reg::MSIP..reg::MTIMECMP => {
+        let hart = ((addr - reg::MSIP) / 4) as usize;
+        if let Some(msip) = self.msip.get_mut(hart) {
+          *msip = value as u32 & 1;
+        }
+      }
+      reg::MTIMECMP..reg::MTIME => {
+        let hart = ((addr - reg::MTIMECMP) / 8) as usize;
+        if let Some(mtimecmp) = self.mtimecmp.get_mut(hart) {
+          set_part(mtimecmp, addr, value, size);
+        }
+      }
+      _ => set_part(&mut self.mtime, addr, value, size),
+    }
+    Ok(())
+  }
+
+  fn tick(&mut self, _dram: &mut Dram) {
+    self.mtime = self.mtime.wrapping_add(1);
+  }
+
+  fn reset(&mut self) {
+    *self = Self::new(self.msip.len());
+  }
+
+  fn mip(&self) -> u64 {
+    self.pending(0)
+  }
+
+  fn fdt(&self, fdt: &mut Fdt, mapping: &Mapping) {
+    fdt.begin_node(&format!("clint@{:x}", mapping.base));
+    fdt.prop_strs("compatible", &["sifive,clint0", "riscv,clint0"]);
+    fdt.prop_reg(&[(mapping.base, mapping.size)]);
+    // only hart 0 exists
+    let intc = phandle::CPU_INTC;
+    fdt.prop_cells("interrupts-extended", &[intc, irq::MSI, intc, irq::MTI]);
+    fdt.end_node();
+  }
+}
+
+// The 64-bit registers can also be accessed as two 32-bit halves.
+fn part(reg: u64, addr: u64, size: u8) -> u64 {
+  match size {
+    DWORD => reg,
+    _ => reg >> ((addr & 4) * 8) & 0xffff_ffff,
+  }
+}
+
+fn set_part(reg: &mut u64, addr: u64, value: u64, size: u8) {
+  *reg = match size {
+    DWORD => value,
+    _ => {
+      let shift = (addr & 4) * 8;
+      (*reg & !(0xffff_ffff << shift)) | (value & 0xffff_ffff) << shift
+    }
+  };
+}
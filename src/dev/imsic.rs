@@ -0,0 +1,196 @@
+use crate::{
+  cpu::WORD,
+  csr::{MEIP_BIT, SEIP_BIT},
+  dev::Device,
+  fdt::{irq, phandle, Fdt, Mapping},
+  Exception, Mode,
+};
+
+/// Number of interrupt identities of an interrupt file, including the
+/// reserved identity 0.
+pub const IMSIC_IDS: usize = 256;
+
+const WORDS: usize = IMSIC_IDS / 64;
+
+/// Registers of an interrupt file that are reachable through `*iselect`.
+pub mod iselect {
+  pub const EIDELIVERY: u64 = 0x70;
+  pub const EITHRESHOLD: u64 = 0x72;
+  pub const EIP0: u64 = 0x80;
+  pub const EIP63: u64 = 0xbf;
+  pub const EIE0: u64 = 0xc0;
+  pub const EIE63: u64 = 0xff;
+}
+
+/// Incoming MSI controller interrupt file of a single privilege level.
+#[derive(Debug)]
+pub struct Imsic {
+  /// Privilege level of the external interrupts the file delivers.
+  pub level: Mode,
+  eidelivery: u64,
+  eithreshold: u64,
+  eip: [u64; WORDS],
+  eie: [u64; WORDS],
+}
+
+impl Imsic {
+  pub const SIZE: u64 = 0x1000;
+
+  pub fn new(level: Mode) -> Self {
+    Self {
+      level,
+      eidelivery: 0,
+      eithreshold: 0,
+      eip: [0; WORDS],
+      eie: [0; WORDS],
+    }
+  }
+
+  /// Mark the interrupt identity `id` as pending, as an MSI write does.
+  pub fn set_pending(&mut self, id: u64) {
+    let id = id as usize;
+    if id != 0 && id < IMSIC_IDS {
+      self.eip[id / 64] |= 1 << (id % 64);
+    }
+  }
+
+  /// Read a register selected through `*iselect`, `None` if it isn't
+  /// implemented.
+  pub fn load_indirect(&self, select: u64) -> Option<u64> {
+    Some(match select {
+      iselect::EIDELIVERY => self.eidelivery,
+      iselect::EITHRESHOLD => self.eithreshold,
+      iselect::EIP0..=iselect::EIP63 => {
+        Self::load_word(&self.eip, select - iselect::EIP0)?
+      }
+      iselect::EIE0..=iselect::EIE63 => {
+        Self::load_word(&self.eie, select - iselect::EIE0)?
+      }
+      _ => return None,
+    })
+  }
+
+  /// Write a register selected through `*iselect`, `None` if it isn't
+  /// implemented.
+  pub fn store_indirect(&mut self, select: u64, val: u64) -> Option<()> {
+    match select {
+      iselect::EIDELIVERY => self.eidelivery = val & 1,
+      iselect::EITHRESHOLD => self.eithreshold = val & 0x7ff,
+      iselect::EIP0..=iselect::EIP63 => {
+        // identity 0 is never pending
+        let val = if select == iselect::EIP0 { val & !1 } else { val };
+        Self::store_word(&mut self.eip, select - iselect::EIP0, val)?
+      }
+      iselect::EIE0..=iselect::EIE63 => {
+        let val = if select == iselect::EIE0 { val & !1 } else { val };
+        Self::store_word(&mut self.eie, select - iselect::EIE0, val)?
+      }
+      _ => return None,
+    }
+    Some(())
+  }
+
+  /// Value of `*topei`: the highest priority pending and enabled identity
+  /// under the threshold, in both the identity and priority fields.
+  pub fn topei(&self) -> u64 {
+    let limit = match self.eithreshold {
+      0 => IMSIC_IDS as u64,
+      threshold => threshold,
+    };
+
+    for (i, (&eip, &eie)) in self.eip.iter().zip(&self.eie).enumerate() {
+      let bits = eip & eie;
+      if bits != 0 {
+        let id = (i * 64) as u64 + bits.trailing_zeros() as u64;
+        return if id < limit { id << 16 | id } else { 0 };
+      }
+    }
+    0
+  }
+
+  /// Claim the interrupt reported by `*topei`, as a write to it does.
+  pub fn claim(&mut self) -> u64 {
+    let top = self.topei();
+    let id = (top >> 16) as usize;
+    if id != 0 {
+      self.eip[id / 64] &= !(1 << (id % 64));
+    }
+    top
+  }
+
+  /// Whether the interrupt file signals an external interrupt to the hart.
+  pub fn pending(&self) -> bool {
+    self.eidelivery == 1 && self.topei() != 0
+  }
+
+  // On RV64 only the even-numbered `eip`/`eie` registers exist, each one
+  // holds 64 identities. Registers past the implemented identities are zero.
+  fn load_word(regs: &[u64; WORDS], offset: u64) -> Option<u64> {
+    if offset & 1 == 1 {
+      return None;
+    }
+    Some(regs.get(offset as usize / 2).copied().unwrap_or(0))
+  }
+
+  fn store_word(regs: &mut [u64; WORDS], offset: u64, val: u64) -> Option<()> {
+    if offset & 1 == 1 {
+      return None;
+    }
+    if let Some(reg) = regs.get_mut(offset as usize / 2) {
+      *reg = val;
+    }
+    Some(())
+  }
+}
+
+impl Device for Imsic {
+  fn load(&mut self, _addr: u64, _size: u8) -> Result<u64, Exception> {
+    // `seteipnum_le` and `seteipnum_be` always read as zero
+    Ok(0)
+  }
+
+  fn store(
+    &mut self,
+    addr: u64,
+    value: u64,
+    size: u8,
+  ) -> Result<(), Exception> {
+    match (addr, size) {
+      (0x0, WORD) => self.set_pending(value as u32 as u64),
+      (0x4, WORD) => self.set_pending((value as u32).swap_bytes() as u64),
+      // other accesses are ignored by the interrupt file
+      _ => {}
+    }
+    Ok(())
+  }
+
+  fn reset(&mut self) {
+    *self = Self::new(self.level);
+  }
+
+  fn mip(&self) -> u64 {
+    match (self.pending(), self.level) {
+      (false, _) => 0,
+      (true, Mode::Supervisor) => SEIP_BIT,
+      (true, _) => MEIP_BIT,
+    }
+  }
+
+  fn fdt(&self, fdt: &mut Fdt, mapping: &Mapping) {
+    let (irq, phandle) = match self.level {
+      Mode::Supervisor => (irq::SEI, phandle::IMSIC_S),
+      _ => (irq::MEI, phandle::IMSIC_M),
+    };
+
+    fdt.begin_node(&format!("imsics@{:x}", mapping.base));
+    fdt.prop_str("compatible", "riscv,imsics");
+    fdt.prop_reg(&[(mapping.base, mapping.size)]);
+    fdt.prop_u32("#interrupt-cells", 0);
+    fdt.prop_empty("interrupt-controller");
+    fdt.prop_empty("msi-controller");
+    fdt.prop_u32("riscv,num-ids", IMSIC_IDS as u32 - 1);
+    fdt.prop_cells("interrupts-extended", &[phandle::CPU_INTC, irq]);
+    fdt.prop_u32("phandle", phandle);
+    fdt.end_node();
+  }
+}
@@ -1 +1,56 @@
+pub mod aplic;
+pub mod audio;
+pub mod clint;
+pub mod imsic;
+pub mod plic;
+pub mod rom;
+pub mod rtc;
+pub mod serial;
+pub mod syscon;
+pub mod uart;
 pub mod vga;
+pub mod virtio;
+
+use {
+  crate::{
+    fdt::{Fdt, Mapping},
+    Dram, Exception,
+  },
+  std::{any::Any, fmt::Debug},
+};
+
+/// Memory-mapped device attached to the [`Bus`](crate::Bus).
+///
+/// Addresses are offsets from the base of the device mapping, sizes are in
+/// bits like everywhere on the bus. Accesses that cross the end of the
+/// mapping never reach the device.
+pub trait Device: Any + Debug {
+  fn load(&mut self, addr: u64, size: u8) -> Result<u64, Exception>;
+
+  fn store(&mut self, addr: u64, value: u64, size: u8)
+    -> Result<(), Exception>;
+
+  /// Advance the device by one step of the machine. Devices that do DMA
+  /// access the main memory through `dram`, which is indexed from
+  /// [`dram::ADDR`](crate::bus::dram::ADDR).
+  fn tick(&mut self, _dram: &mut Dram) {}
+
+  /// Return the device to its power-on state.
+  fn reset(&mut self) {}
+
+  /// Bits of `mip` the device asserts, for devices wired to the hart
+  /// interrupt lines.
+  fn mip(&self) -> u64 {
+    0
+  }
+
+  /// Level of the interrupt line of the device, the bus routes it to the
+  /// source of the interrupt controllers the device is wired to.
+  fn irq(&self) -> bool {
+    false
+  }
+
+  /// Describe the device with a node under `/soc` of the device tree, the
+  /// device is left out by default.
+  fn fdt(&self, _fdt: &mut Fdt, _mapping: &Mapping) {}
+}
@@ -0,0 +1,266 @@
+use crate::{
+  cpu::WORD,
+  csr::{MEIP_BIT, SEIP_BIT},
+  dev::Device,
+  fdt::{irq, phandle, Fdt, Mapping},
+  Exception,
+};
+
+/// Register offsets of the PLIC.
+pub mod reg {
+  pub const PRIORITY: u64 = 0x0000;
+  pub const PENDING: u64 = 0x1000;
+  pub const ENABLE: u64 = 0x2000;
+  pub const CONTEXT: u64 = 0x20_0000;
+}
+
+/// Registers of a context, relative to its base.
+pub mod ctx {
+  pub const THRESHOLD: u64 = 0x0;
+  pub const CLAIM: u64 = 0x4;
+}
+
+/// Number of sources of the PLIC of the default machine, including the
+/// reserved source 0.
+pub const PLIC_SOURCES: usize = 64;
+
+const ENABLE_STRIDE: u64 = 0x80;
+const CONTEXT_STRIDE: u64 = 0x1000;
+const PRIORITY_MASK: u32 = 0x7;
+
+/// Platform-level interrupt controller. Context `2 * hart` takes the
+/// machine-level and `2 * hart + 1` the supervisor-level external
+/// interrupts of the hart.
+#[derive(Debug)]
+pub struct Plic {
+  sources: usize,
+  priority: Vec<u32>,
+  // bit sets indexed by the source number, 32 sources per word
+  pending: Vec<u32>,
+  claimed: Vec<u32>,
+  input: Vec<u32>,
+  enable: Vec<Vec<u32>>,
+  threshold: Vec<u32>,
+}
+
+impl Plic {
+  /// `sources` counts the reserved source 0.
+  pub fn new(sources: usize, contexts: usize) -> Self {
+    assert!(sources <= 1024, "the PLIC has at most 1023 sources");
+    let words = sources.div_ceil(32);
+    Self {
+      sources,
+      priority: vec![0; sources],
+      pending: vec![0; words],
+      claimed: vec![0; words],
+      input: vec![0; words],
+      enable: vec![vec![0; words]; contexts],
+      threshold: vec![0; contexts],
+    }
+  }
+
+  /// Size of the register file of a PLIC with `contexts` contexts.
+  pub const fn size(contexts: usize) -> u64 {
+    reg::CONTEXT + contexts as u64 * CONTEXT_STRIDE
+  }
+
+  /// Number of sources, including the reserved source 0.
+  pub fn sources(&self) -> usize {
+    self.sources
+  }
+
+  /// Number of contexts.
+  pub fn contexts(&self) -> usize {
+    self.threshold.len()
+  }
+
+  /// Drive the level-sensitive input wire of `source`.
+  pub fn set_input(&mut self, source: usize, level: bool) {
+    if source == 0 || source >= self.sources {
+      return;
+    }
+
+    set(&mut self.input, source, level);
+    // the gateway holds back the request while the source is claimed
+    if !get(&self.claimed, source) {
+      set(&mut self.pending, source, level);
+    }
+  }
+
+  /// Highest priority pending interrupt enabled for `context` above its
+  /// threshold, 0 if there is none.
+  pub fn top(&self, context: usize) -> u32 {
+    let mut top = (0, 0);
+    for i in 1..self.sources {
+      let prio = self.priority[i];
+      if prio > top.0 && get(&self.pending, i) && get(&self.enable[context], i)
+      {
+        top = (prio, i as u32);
+      }
+    }
+
+    if top.0 > self.threshold[context] {
+      top.1
+    } else {
+      0
+    }
+  }
+
+  /// Claim the interrupt reported by [`top`](Self::top), as a read of the
+  /// claim register does.
+  pub fn claim(&mut self, context: usize) -> u32 {
+    let id = self.top(context);
+    if id != 0 {
+      set(&mut self.pending, id as usize, false);
+      set(&mut self.claimed, id as usize, true);
+    }
+    id
+  }
+
+  /// Signal the completion of the claimed interrupt `id`.
+  pub fn complete(&mut self, context: usize, id: u32) {
+    let id = id as usize;
+    // completions of sources that are not enabled are ignored
+    if id == 0 || id >= self.sources || !get(&self.enable[context], id) {
+      return;
+    }
+
+    set(&mut self.claimed, id, false);
+    if get(&self.input, id) {
+      set(&mut self.pending, id, true);
+    }
+  }
+
+  fn context(&self, offset: u64) -> Option<(usize, u64)> {
+    let context = (offset / CONTEXT_STRIDE) as usize;
+    (context < self.contexts()).then_some((context, offset % CONTEXT_STRIDE))
+  }
+
+  fn enable(&self, offset: u64) -> Option<(usize, usize)> {
+    let context = (offset / ENABLE_STRIDE) as usize;
+    let word = (offset % ENABLE_STRIDE / 4) as usize;
+    (context < self.contexts() && word < self.pending.len())
+      .then_some((context, word))
+  }
+}
+
+impl Device for Plic {
+  fn load(&mut self, addr: u64, size: u8) -> Result<u64, Exception> {
+    if size != WORD || !addr.is_multiple_of(4) {
+      return Err(Exception::LoadAccessFault(addr));
+    }
+
+    Ok(match addr {
+      reg::PRIORITY..reg::PENDING => {
+        self.priority.get((addr / 4) as usize).copied().unwrap_or(0)
+      }
+      reg::PENDING..reg::ENABLE => {
+        let word = ((addr - reg::PENDING) / 4) as usize;
+        self.pending.get(word).copied().unwrap_or(0)
+      }
+      reg::ENABLE..reg::CONTEXT => match self.enable(addr - reg::ENABLE) {
+        Some((context, word)) => self.enable[context][word],
+        None => 0,
+      },
+      _ => match self.context(addr - reg::CONTEXT) {
+        Some((context, ctx::THRESHOLD)) => self.threshold[context],
+        Some((context, ctx::CLAIM)) => self.claim(context),
+        _ => 0,
+      },
+    } as u64)
+  }
+
+  fn store(
+    &mut self,
+    addr: u64,
+    value: u64,
+    size: u8,
+  ) -> Result<(), Exception> {
+    if size != WORD || !addr.is_multiple_of(4) {
+      return Err(Exception::StoreAMOAccessFault(addr));
+    }
+
+    let value = value as u32;
+    match addr {
+      // source 0 doesn't exist and has no priority
+      reg::PRIORITY..reg::PENDING if addr != 0 => {
+        if let Some(prio) = self.priority.get_mut((addr / 4) as usize) {
+          *prio = value & PRIORITY_MASK;
+        }
+      }
+      // pending bits are read-only
+      reg::PRIORITY..reg::ENABLE => {}
+      reg::ENABLE..reg::CONTEXT => {
+        if let Some((context, word)) = self.enable(addr - reg::ENABLE) {
+          let value = if word == 0 { value & !1 } else { value };
+          self.enable[context][word] = value & valid(self.sources, word);
+        }
+      }
+      _ => match self.context(addr - reg::CONTEXT) {
+        Some((context, ctx::THRESHOLD)) => {
+          self.threshold[context] = value & PRIORITY_MASK
+        }
+        Some((context, ctx::CLAIM)) => self.complete(context, value),
+        _ => {}
+      },
+    }
+    Ok(())
+  }
+
+  fn reset(&mut self) {
+    *self = Self::new(self.sources, self.contexts());
+  }
+
+  fn mip(&self) -> u64 {
+    // only the contexts of hart 0 are wired to the cpu
+    let level = |context, bit| {
+      if context < self.contexts() && self.top(context) != 0 {
+        bit
+      } else {
+        0
+      }
+    };
+    level(0, MEIP_BIT) | level(1, SEIP_BIT)
+  }
+
+  fn fdt(&self, fdt: &mut Fdt, mapping: &Mapping) {
+    fdt.begin_node(&format!("plic@{:x}", mapping.base));
+    fdt.prop_strs("compatible", &["sifive,plic-1.0.0", "riscv,plic0"]);
+    fdt.prop_reg(&[(mapping.base, mapping.size)]);
+    fdt.prop_u32("#address-cells", 0);
+    fdt.prop_u32("#interrupt-cells", 1);
+    fdt.prop_empty("interrupt-controller");
+    fdt.prop_u32("riscv,ndev", self.sources as u32 - 1);
+    // the contexts of hart 0, the contexts of harts that don't exist are
+    // left unconnected
+    let intc = phandle::CPU_INTC;
+    let contexts = [intc, irq::MEI, intc, irq::SEI];
+    fdt.prop_cells(
+      "interrupts-extended",
+      &contexts[..2 * self.contexts().min(2)],
+    );
+    fdt.prop_u32("phandle", phandle::PLIC);
+    fdt.end_node();
+  }
+}
+
+fn get(set: &[u32], i: usize) -> bool {
+  set[i / 32] >> (i % 32) & 1 == 1
+}
+
+fn set(set: &mut [u32], i: usize, level: bool) {
+  if level {
+    set[i / 32] |= 1 << (i % 32);
+  } else {
+    set[i / 32] &= !(1 << (i % 32));
+  }
+}
+
+// Bits of the enable `word` backed by a source.
+fn valid(sources: usize, word: usize) -> u32 {
+  match sources.saturating_sub(word * 32) {
+    0 => 0,
+    n @ 1..32 => (1 << n) - 1,
+    _ => !0,
+  }
+}
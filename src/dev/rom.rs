@@ -0,0 +1,62 @@
+use crate::{dev::Device, Dram, Exception};
+
+/// Bytes of the reset stub at the start of the ROM, the device tree follows
+/// it at [`POINTER_TO_DTB`](crate::POINTER_TO_DTB).
+pub const STUB: usize = 0x20;
+
+/// Read-only memory, the host fills it through [`Rom::as_slice_mut`].
+#[derive(Debug)]
+pub struct Rom {
+  mem: Dram,
+}
+
+impl Rom {
+  /// Size of the boot ROM of the default machine.
+  pub const SIZE: u64 = 0xf000;
+
+  pub fn new(size: usize) -> Self {
+    Self { mem: Dram::with_capacity(size) }
+  }
+
+  pub fn as_slice(&self) -> &[u8] {
+    self.mem.as_slice()
+  }
+
+  pub fn as_slice_mut(&mut self) -> &mut [u8] {
+    self.mem.as_slice_mut()
+  }
+
+  /// Write the reset stub, which the hart runs from the
+  /// [`RESET_VECTOR`](crate::RESET_VECTOR) at the start of the ROM: it
+  /// passes the hart ID in `a0` and the device tree in `a1`, then jumps to
+  /// `entry`.
+  pub fn set_entry(&mut self, entry: u64) {
+    #[rustfmt::skip]
+    let code: [u32; 6] = [
+      0x00000297, // auipc t0, 0
+      0x02028593, // addi a1, t0, 0x20    ; the device tree
+      0xf1402573, // csrr a0, mhartid
+      0x0182b283, // ld t0, 0x18(t0)      ; the entry
+      0x00028067, // jr t0
+      0x00000000,
+    ];
+    let stub =
+      [code.map(u32::to_le_bytes).concat(), entry.to_le_bytes().into()];
+    self.as_slice_mut()[..STUB].copy_from_slice(&stub.concat());
+  }
+}
+
+impl Device for Rom {
+  fn load(&mut self, addr: u64, size: u8) -> Result<u64, Exception> {
+    self.mem.load(addr, size)
+  }
+
+  fn store(
+    &mut self,
+    addr: u64,
+    _value: u64,
+    _size: u8,
+  ) -> Result<(), Exception> {
+    Err(Exception::StoreAMOAccessFault(addr))
+  }
+}
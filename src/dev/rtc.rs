@@ -0,0 +1,167 @@
+use {
+  crate::{
+    cpu::WORD,
+    dev::{clint::TIMEBASE_FREQ, Device},
+    fdt::{Fdt, Mapping},
+    Dram, Exception,
+  },
+  std::time::{SystemTime, UNIX_EPOCH},
+};
+
+pub mod reg {
+  /// Nanoseconds since the epoch, reading the low word latches the high one
+  /// and writing the low word sets the time.
+  pub const TIME_LOW: u64 = 0x00;
+  pub const TIME_HIGH: u64 = 0x04;
+  /// Time of the alarm, writing the low word arms it.
+  pub const ALARM_LOW: u64 = 0x08;
+  pub const ALARM_HIGH: u64 = 0x0c;
+  pub const IRQ_ENABLED: u64 = 0x10;
+  /// Disarm the alarm.
+  pub const CLEAR_ALARM: u64 = 0x14;
+  /// Whether the alarm is armed.
+  pub const ALARM_STATUS: u64 = 0x18;
+  /// Acknowledge the interrupt of the alarm.
+  pub const CLEAR_INTERRUPT: u64 = 0x1c;
+}
+
+// ticks between two checks of the alarm
+const POLL: u64 = 1024;
+
+/// Source of the wall-clock time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Clock {
+  /// Time of the host.
+  Host,
+  /// Time starting at these nanoseconds since the epoch and advancing with
+  /// the ticks of the machine at [`TIMEBASE_FREQ`], the same on every run.
+  Virtual(u64),
+}
+
+/// Goldfish real-time clock, with an alarm.
+#[derive(Debug)]
+pub struct Rtc {
+  pub clock: Clock,
+  ticks: u64,
+  // set time minus the time of the clock
+  offset: u64,
+  // high words latched by a read, or written before the low ones
+  time_high: u32,
+  alarm_high: u32,
+  alarm: Option<u64>,
+  irq_enabled: bool,
+  pending: bool,
+}
+
+impl Rtc {
+  pub const SIZE: u64 = 0x1000;
+
+  pub fn new(clock: Clock) -> Self {
+    Self {
+      clock,
+      ticks: 0,
+      offset: 0,
+      time_high: 0,
+      alarm_high: 0,
+      alarm: None,
+      irq_enabled: false,
+      pending: false,
+    }
+  }
+
+  /// Nanoseconds since the epoch.
+  pub fn time(&self) -> u64 {
+    let clock = match self.clock {
+      Clock::Host => SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_nanos() as u64),
+      Clock::Virtual(start) => {
+        let nanos = self.ticks as u128 * 1_000_000_000 / TIMEBASE_FREQ as u128;
+        start + nanos as u64
+      }
+    };
+    clock.wrapping_add(self.offset)
+  }
+}
+
+impl Device for Rtc {
+  fn load(&mut self, addr: u64, size: u8) -> Result<u64, Exception> {
+    if size != WORD || !addr.is_multiple_of(4) {
+      return Err(Exception::LoadAccessFault(addr));
+    }
+    Ok(match addr {
+      reg::TIME_LOW => {
+        let time = self.time();
+        self.time_high = (time >> 32) as u32;
+        time as u32 as u64
+      }
+      reg::TIME_HIGH => self.time_high as u64,
+      reg::ALARM_LOW => self.alarm.unwrap_or(0) as u32 as u64,
+      reg::ALARM_HIGH => self.alarm.unwrap_or(0) >> 32,
+      reg::IRQ_ENABLED => self.irq_enabled as u64,
+      reg::ALARM_STATUS => self.alarm.is_some() as u64,
+      _ => 0,
+    })
+  }
+
+  fn store(
+    &mut self,
+    addr: u64,
+    value: u64,
+    size: u8,
+  ) -> Result<(), Exception> {
+    if size != WORD || !addr.is_multiple_of(4) {
+      return Err(Exception::StoreAMOAccessFault(addr));
+    }
+    let value = value as u32;
+    match addr {
+      reg::TIME_LOW => {
+        let time = (self.time_high as u64) << 32 | value as u64;
+        let clock = self.time().wrapping_sub(self.offset);
+        self.offset = time.wrapping_sub(clock);
+      }
+      reg::TIME_HIGH => self.time_high = value,
+      reg::ALARM_LOW => {
+        self.alarm = Some((self.alarm_high as u64) << 32 | value as u64);
+      }
+      reg::ALARM_HIGH => self.alarm_high = value,
+      reg::IRQ_ENABLED => self.irq_enabled = value & 1 != 0,
+      reg::CLEAR_ALARM => self.alarm = None,
+      reg::CLEAR_INTERRUPT => self.pending = false,
+      _ => {}
+    }
+    Ok(())
+  }
+
+  fn tick(&mut self, _dram: &mut Dram) {
+    self.ticks += 1;
+    if !self.ticks.is_multiple_of(POLL) {
+      return;
+    }
+    if self.alarm.is_some_and(|alarm| self.time() >= alarm) {
+      self.alarm = None;
+      self.pending = true;
+    }
+  }
+
+  // the time keeps going, like on a battery
+  fn reset(&mut self) {
+    self.alarm = None;
+    self.irq_enabled = false;
+    self.pending = false;
+  }
+
+  fn irq(&self) -> bool {
+    self.irq_enabled && self.pending
+  }
+
+  fn fdt(&self, fdt: &mut Fdt, mapping: &Mapping) {
+    fdt.begin_node(&format!("rtc@{:x}", mapping.base));
+    fdt.prop_str("compatible", "google,goldfish-rtc");
+    fdt.prop_reg(&[(mapping.base, mapping.size)]);
+    if let Some(irq) = mapping.irq {
+      fdt.prop_interrupts(irq);
+    }
+    fdt.end_node();
+  }
+}
@@ -0,0 +1,175 @@
+use std::{
+  fmt::Debug,
+  fs::File,
+  io::{self, Read, Write},
+  path::Path,
+  sync::mpsc::{self, Receiver},
+  thread,
+};
+
+/// Host side of a serial line.
+pub trait Serial: Debug {
+  /// Next byte sent by the host, without blocking.
+  fn read(&mut self) -> Option<u8>;
+
+  /// Send a byte to the host.
+  fn write(&mut self, byte: u8);
+}
+
+/// Serial line with nothing connected to it.
+#[derive(Debug)]
+pub struct Null;
+
+impl Serial for Null {
+  fn read(&mut self) -> Option<u8> {
+    None
+  }
+
+  fn write(&mut self, _byte: u8) {}
+}
+
+/// Terminal of the emulator: stdin is switched to raw mode for as long as
+/// the backend lives, so that every key reaches the guest.
+#[derive(Debug)]
+pub struct Stdio {
+  input: Receiver<u8>,
+  #[cfg(unix)]
+  termios: Option<libc::termios>,
+}
+
+impl Stdio {
+  // not a `Default`, taking over the terminal is never implicit
+  #[allow(clippy::new_without_default)]
+  pub fn new() -> Self {
+    let (tx, input) = mpsc::channel();
+    thread::spawn(move || {
+      for byte in io::stdin().lock().bytes() {
+        let Ok(byte) = byte else { break };
+        if tx.send(byte).is_err() {
+          break;
+        }
+      }
+    });
+
+    Self {
+      input,
+      #[cfg(unix)]
+      termios: raw_mode(),
+    }
+  }
+}
+
+impl Serial for Stdio {
+  fn read(&mut self) -> Option<u8> {
+    self.input.try_recv().ok()
+  }
+
+  fn write(&mut self, byte: u8) {
+    let mut stdout = io::stdout().lock();
+    let _ = stdout.write_all(&[byte]).and_then(|_| stdout.flush());
+  }
+}
+
+impl Drop for Stdio {
+  fn drop(&mut self) {
+    #[cfg(unix)]
+    if let Some(termios) = self.termios {
+      unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &termios) };
+    }
+  }
+}
+
+// Switch stdin to raw mode, returning the previous settings if it is a
+// terminal.
+#[cfg(unix)]
+fn raw_mode() -> Option<libc::termios> {
+  unsafe {
+    let mut termios = std::mem::zeroed();
+    if libc::tcgetattr(libc::STDIN_FILENO, &mut termios) != 0 {
+      return None;
+    }
+    let mut raw = termios;
+    libc::cfmakeraw(&mut raw);
+    // keep translating the newlines of the guest, and let ^C stop the
+    // emulator
+    raw.c_oflag |= libc::OPOST;
+    raw.c_lflag |= libc::ISIG;
+    libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &raw);
+    Some(termios)
+  }
+}
+
+/// Output of the serial line logged to a file, the line never receives.
+#[derive(Debug)]
+pub struct Log {
+  file: File,
+}
+
+impl Log {
+  pub fn new(path: impl AsRef<Path>) -> io::Result<Self> {
+    Ok(Self { file: File::create(path)? })
+  }
+}
+
+impl Serial for Log {
+  fn read(&mut self) -> Option<u8> {
+    None
+  }
+
+  fn write(&mut self, byte: u8) {
+    let _ = self.file.write_all(&[byte]);
+  }
+}
+
+/// Serial line exposed as a listening Unix domain socket. A single client
+/// is served at a time, output is dropped while none is connected.
+#[cfg(unix)]
+#[derive(Debug)]
+pub struct Socket {
+  listener: std::os::unix::net::UnixListener,
+  stream: Option<std::os::unix::net::UnixStream>,
+}
+
+#[cfg(unix)]
+impl Socket {
+  pub fn bind(path: impl AsRef<Path>) -> io::Result<Self> {
+    let listener = std::os::unix::net::UnixListener::bind(path)?;
+    listener.set_nonblocking(true)?;
+    Ok(Self { listener, stream: None })
+  }
+
+  fn stream(&mut self) -> Option<&mut std::os::unix::net::UnixStream> {
+    if self.stream.is_none() {
+      let (stream, _) = self.listener.accept().ok()?;
+      stream.set_nonblocking(true).ok()?;
+      self.stream = Some(stream);
+    }
+    self.stream.as_mut()
+  }
+}
+
+#[cfg(unix)]
+impl Serial for Socket {
+  fn read(&mut self) -> Option<u8> {
+    let mut byte = 0;
+    match self.stream()?.read(std::slice::from_mut(&mut byte)) {
+      Ok(1) => Some(byte),
+      Err(err) if err.kind() == io::ErrorKind::WouldBlock => None,
+      // the client hung up
+      _ => {
+        self.stream = None;
+        None
+      }
+    }
+  }
+
+  fn write(&mut self, byte: u8) {
+    let Some(stream) = self.stream() else { return };
+    // the socket is non-blocking, a client that doesn't keep up loses output
+    match stream.write(&[byte]) {
+      Ok(_) => {}
+      Err(err) if err.kind() == io::ErrorKind::WouldBlock => {}
+      Err(_) => self.stream = None,
+    }
+  }
+}
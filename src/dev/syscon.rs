@@ -0,0 +1,104 @@
+use crate::{
+  cpu::WORD,
+  dev::Device,
+  fdt::{phandle, Fdt, Mapping},
+  Exception, Exit,
+};
+
+/// Values the guest writes, in the low half of the word.
+pub mod value {
+  /// Power off with the exit code in the high half.
+  pub const FAIL: u32 = 0x3333;
+  /// Power off.
+  pub const PASS: u32 = 0x5555;
+  pub const RESET: u32 = 0x7777;
+}
+
+/// What the guest asked the [`Syscon`] for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Request {
+  Poweroff(Exit),
+  Reset,
+}
+
+/// System controller compatible with the SiFive test finisher, Linux powers
+/// the machine off and reboots it through the register at offset 0.
+#[derive(Debug)]
+pub struct Syscon {
+  request: Option<Request>,
+}
+
+impl Syscon {
+  pub const SIZE: u64 = 0x1000;
+
+  pub fn new() -> Self {
+    Self { request: None }
+  }
+
+  /// Take the last request of the guest, the [`Emu`](crate::Emu) serves it
+  /// after each instruction.
+  pub fn take(&mut self) -> Option<Request> {
+    self.request.take()
+  }
+}
+
+impl Default for Syscon {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl Device for Syscon {
+  fn load(&mut self, addr: u64, size: u8) -> Result<u64, Exception> {
+    if size != WORD || !addr.is_multiple_of(4) {
+      return Err(Exception::LoadAccessFault(addr));
+    }
+    Ok(0)
+  }
+
+  fn store(
+    &mut self,
+    addr: u64,
+    value: u64,
+    size: u8,
+  ) -> Result<(), Exception> {
+    if size != WORD || !addr.is_multiple_of(4) {
+      return Err(Exception::StoreAMOAccessFault(addr));
+    }
+    if addr != 0 {
+      return Ok(());
+    }
+    let exit = match value >> 16 & 0xffff {
+      0 => Exit::Pass,
+      code => Exit::Fail(code),
+    };
+    self.request = match value as u32 & 0xffff {
+      value::PASS => Some(Request::Poweroff(Exit::Pass)),
+      value::FAIL => Some(Request::Poweroff(exit)),
+      value::RESET => Some(Request::Reset),
+      _ => self.request,
+    };
+    Ok(())
+  }
+
+  fn reset(&mut self) {
+    self.request = None;
+  }
+
+  fn fdt(&self, fdt: &mut Fdt, mapping: &Mapping) {
+    fdt.begin_node(&format!("test@{:x}", mapping.base));
+    fdt.prop_strs("compatible", &["sifive,test1", "sifive,test0", "syscon"]);
+    fdt.prop_reg(&[(mapping.base, mapping.size)]);
+    fdt.prop_u32("phandle", phandle::SYSCON);
+    fdt.end_node();
+
+    for (node, value) in [("poweroff", value::PASS), ("reboot", value::RESET)] {
+      fdt.begin_node(node);
+      fdt.prop_str("compatible", &format!("syscon-{node}"));
+      fdt.prop_u32("regmap", phandle::SYSCON);
+      fdt.prop_u32("offset", 0);
+      fdt.prop_u32("value", value);
+      fdt.end_node();
+    }
+  }
+}
@@ -0,0 +1,287 @@
+use {
+  crate::{
+    cpu::BYTE,
+    dev::{
+      serial::{Null, Serial},
+      Device,
+    },
+    fdt::{Fdt, Mapping},
+    Dram, Exception,
+  },
+  std::collections::VecDeque,
+};
+
+/// Register offsets of the UART.
+pub mod reg {
+  /// Receive buffer on reads, transmit holding on writes, low byte of the
+  /// divisor latch while `LCR.DLAB` is set.
+  pub const RBR: u64 = 0;
+  pub const THR: u64 = 0;
+  pub const DLL: u64 = 0;
+  pub const IER: u64 = 1;
+  pub const DLM: u64 = 1;
+  /// Interrupt identification on reads, FIFO control on writes.
+  pub const IIR: u64 = 2;
+  pub const FCR: u64 = 2;
+  pub const LCR: u64 = 3;
+  pub const MCR: u64 = 4;
+  pub const LSR: u64 = 5;
+  pub const MSR: u64 = 6;
+  pub const SCR: u64 = 7;
+}
+
+/// Bits of `IER`.
+pub mod ier {
+  pub const ERBFI: u8 = 1 << 0;
+  pub const ETBEI: u8 = 1 << 1;
+  pub const ELSI: u8 = 1 << 2;
+  pub const EDSSI: u8 = 1 << 3;
+}
+
+/// Bits of `LSR`.
+pub mod lsr {
+  pub const DR: u8 = 1 << 0;
+  pub const OE: u8 = 1 << 1;
+  pub const THRE: u8 = 1 << 5;
+  pub const TEMT: u8 = 1 << 6;
+}
+
+/// Interrupt identifiers reported in `IIR`.
+pub mod iir {
+  pub const NONE: u8 = 0x01;
+  pub const RLS: u8 = 0x06;
+  pub const RDA: u8 = 0x04;
+  pub const TIMEOUT: u8 = 0x0c;
+  pub const THRE: u8 = 0x02;
+  pub const FIFO: u8 = 0xc0;
+}
+
+const FIFO_SIZE: usize = 16;
+// input clock reported to the guest, the baud rate has no effect
+const CLOCK: u32 = 3_686_400;
+const LCR_DLAB: u8 = 1 << 7;
+const MCR_LOOP: u8 = 1 << 4;
+const FCR_ENABLE: u8 = 1 << 0;
+const FCR_CLEAR_RX: u8 = 1 << 1;
+// the host is polled once every so many ticks
+const POLL: u32 = 1024;
+
+/// NS16550A-compatible UART. Transmission is instantaneous, bytes written to
+/// `THR` go straight to the backend.
+#[derive(Debug)]
+pub struct Uart {
+  pub backend: Box<dyn Serial>,
+  rx: VecDeque<u8>,
+  ier: u8,
+  lcr: u8,
+  mcr: u8,
+  fcr: u8,
+  scr: u8,
+  dll: u8,
+  dlm: u8,
+  overrun: bool,
+  // `THRE` interrupt is raised until `IIR` reports it or `THR` is written
+  thre: bool,
+  poll: u32,
+}
+
+impl Uart {
+  pub const SIZE: u64 = 0x100;
+
+  pub fn new(backend: impl Serial + 'static) -> Self {
+    Self::with_backend(Box::new(backend))
+  }
+
+  fn with_backend(backend: Box<dyn Serial>) -> Self {
+    Self {
+      backend,
+      rx: VecDeque::with_capacity(FIFO_SIZE),
+      ier: 0,
+      lcr: 0,
+      mcr: 0,
+      fcr: 0,
+      scr: 0,
+      dll: 0,
+      dlm: 0,
+      overrun: false,
+      thre: true,
+      poll: 0,
+    }
+  }
+
+  fn receive(&mut self, byte: u8) {
+    if self.rx.len() < self.capacity() {
+      self.rx.push_back(byte);
+    } else {
+      self.overrun = true;
+    }
+  }
+
+  fn capacity(&self) -> usize {
+    if self.fcr & FCR_ENABLE != 0 {
+      FIFO_SIZE
+    } else {
+      1
+    }
+  }
+
+  fn trigger(&self) -> usize {
+    match self.fcr >> 6 {
+      0 => 1,
+      1 => 4,
+      2 => 8,
+      _ => 14,
+    }
+  }
+
+  fn lsr(&self) -> u8 {
+    let dr = if self.rx.is_empty() { 0 } else { lsr::DR };
+    let oe = if self.overrun { lsr::OE } else { 0 };
+    dr | oe | lsr::THRE | lsr::TEMT
+  }
+
+  /// Highest priority interrupt pending, as reported by `IIR`.
+  fn interrupt(&self) -> u8 {
+    if self.ier & ier::ELSI != 0 && self.overrun {
+      iir::RLS
+    } else if self.ier & ier::ERBFI != 0 && !self.rx.is_empty() {
+      // the FIFO under the trigger level times out instead
+      if self.rx.len() >= self.trigger() {
+        iir::RDA
+      } else {
+        iir::TIMEOUT
+      }
+    } else if self.ier & ier::ETBEI != 0 && self.thre {
+      iir::THRE
+    } else {
+      iir::NONE
+    }
+  }
+
+  fn iir(&mut self) -> u8 {
+    let id = self.interrupt();
+    if id == iir::THRE {
+      self.thre = false;
+    }
+    let fifo = if self.fcr & FCR_ENABLE != 0 { iir::FIFO } else { 0 };
+    fifo | id
+  }
+
+  fn transmit(&mut self, byte: u8) {
+    if self.mcr & MCR_LOOP != 0 {
+      self.receive(byte);
+    } else {
+      self.backend.write(byte);
+    }
+    self.thre = true;
+  }
+
+  fn msr(&self) -> u8 {
+    if self.mcr & MCR_LOOP != 0 {
+      // DTR, RTS, OUT1 and OUT2 loop back to DSR, CTS, RI and DCD
+      let mcr = self.mcr;
+      (mcr & 1) << 5 | (mcr & 2) << 3 | (mcr & 4) << 4 | (mcr & 8) << 4
+    } else {
+      // carrier, data set ready and clear to send
+      0xb0
+    }
+  }
+}
+
+impl Device for Uart {
+  fn load(&mut self, addr: u64, size: u8) -> Result<u64, Exception> {
+    if size != BYTE {
+      return Err(Exception::LoadAccessFault(addr));
+    }
+
+    let dlab = self.lcr & LCR_DLAB != 0;
+    Ok(match addr {
+      reg::DLL if dlab => self.dll,
+      reg::DLM if dlab => self.dlm,
+      reg::RBR => self.rx.pop_front().unwrap_or(0),
+      reg::IER => self.ier,
+      reg::IIR => self.iir(),
+      reg::LCR => self.lcr,
+      reg::MCR => self.mcr,
+      reg::LSR => {
+        let lsr = self.lsr();
+        self.overrun = false;
+        lsr
+      }
+      reg::MSR => self.msr(),
+      reg::SCR => self.scr,
+      _ => 0,
+    } as u64)
+  }
+
+  fn store(
+    &mut self,
+    addr: u64,
+    value: u64,
+    size: u8,
+  ) -> Result<(), Exception> {
+    if size != BYTE {
+      return Err(Exception::StoreAMOAccessFault(addr));
+    }
+
+    let value = value as u8;
+    let dlab = self.lcr & LCR_DLAB != 0;
+    match addr {
+      reg::DLL if dlab => self.dll = value,
+      reg::DLM if dlab => self.dlm = value,
+      reg::THR => self.transmit(value),
+      reg::IER => {
+        // enabling the interrupt of an empty transmitter raises it
+        if value & !self.ier & ier::ETBEI != 0 {
+          self.thre = true;
+        }
+        self.ier = value & 0x0f;
+      }
+      reg::FCR => {
+        if value & FCR_CLEAR_RX != 0 || (value ^ self.fcr) & FCR_ENABLE != 0 {
+          self.rx.clear();
+        }
+        // the transmitter FIFO is always empty, DMA mode is meaningless
+        self.fcr = value & 0xc1;
+      }
+      reg::LCR => self.lcr = value,
+      reg::MCR => self.mcr = value & 0x1f,
+      reg::SCR => self.scr = value,
+      _ => {}
+    }
+    Ok(())
+  }
+
+  fn tick(&mut self, _dram: &mut Dram) {
+    self.poll += 1;
+    if self.poll < POLL || self.mcr & MCR_LOOP != 0 {
+      return;
+    }
+    self.poll = 0;
+
+    while self.rx.len() < self.capacity() {
+      let Some(byte) = self.backend.read() else { break };
+      self.rx.push_back(byte);
+    }
+  }
+
+  fn reset(&mut self) {
+    let backend = std::mem::replace(&mut self.backend, Box::new(Null));
+    *self = Self::with_backend(backend);
+  }
+
+  fn irq(&self) -> bool {
+    self.interrupt() != iir::NONE
+  }
+
+  fn fdt(&self, fdt: &mut Fdt, mapping: &Mapping) {
+    fdt.begin_node(&format!("serial@{:x}", mapping.base));
+    fdt.prop_str("compatible", "ns16550a");
+    fdt.prop_reg(&[(mapping.base, mapping.size)]);
+    fdt.prop_u32("clock-frequency", CLOCK);
+    if let Some(irq) = mapping.irq {
+      fdt.prop_interrupts(irq);
+    }
+    fdt.end_node();
+  }
+}
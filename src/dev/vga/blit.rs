@@ -0,0 +1,220 @@
+//! 2D blitter: fills and copies of rectangles between the pixel memory and
+//! the main memory, much faster than drawing them a store at a time.
+
+use crate::{bus::dram, Dram};
+
+/// Offsets of the blitter registers from [`reg::BLIT`](super::reg::BLIT).
+pub mod reg {
+  /// Operation and flags from [`op`](super::op), writing it starts the blit.
+  pub const OP: u64 = 0x00;
+  pub const SRC_LOW: u64 = 0x04;
+  pub const SRC_HIGH: u64 = 0x08;
+  /// Bytes from a line of the source to the next one.
+  pub const SRC_PITCH: u64 = 0x0c;
+  pub const DST_LOW: u64 = 0x10;
+  pub const DST_HIGH: u64 = 0x14;
+  pub const DST_PITCH: u64 = 0x18;
+  /// Size of the rectangle, in pixels.
+  pub const WIDTH: u64 = 0x1c;
+  pub const HEIGHT: u64 = 0x20;
+  /// Bytes of a pixel, 1 to 4.
+  pub const BYTES: u64 = 0x24;
+  /// Colour of a fill, or of the set bits of a glyph.
+  pub const FG: u64 = 0x28;
+  /// Colour of the clear bits of a glyph.
+  pub const BG: u64 = 0x2c;
+  /// Colour of the source pixels a keyed copy leaves out.
+  pub const KEY: u64 = 0x30;
+  /// Pixels processed so far, read-only.
+  pub const PIXELS_LOW: u64 = 0x34;
+  pub const PIXELS_HIGH: u64 = 0x38;
+}
+
+/// Operations of the blitter, and their flags.
+pub mod op {
+  pub const FILL: u32 = 1;
+  /// Copy the source, which may overlap the destination.
+  pub const COPY: u32 = 2;
+  /// Copy the source but the pixels of the colour key.
+  pub const KEY: u32 = 3;
+  /// Draw the 1-bit glyph of the source, the leftmost pixel in the high bit
+  /// of a byte.
+  pub const EXPAND: u32 = 4;
+  pub const MASK: u32 = 0xff;
+
+  /// The source, or the destination, is an address in the main memory
+  /// rather than an offset in the pixel memory.
+  pub const SRC_DRAM: u32 = 1 << 8;
+  pub const DST_DRAM: u32 = 1 << 9;
+  /// The clear bits of a glyph leave the destination alone.
+  pub const TRANSPARENT: u32 = 1 << 10;
+}
+
+/// Pixels a blit draws in a tick of the machine at most, a larger one goes on
+/// over the next ticks. An empty line counts as a pixel.
+pub const STEP: u64 = 1 << 16;
+
+#[derive(Debug)]
+pub(super) struct Blitter {
+  op: u32,
+  src: [u32; 2],
+  src_pitch: u32,
+  dst: [u32; 2],
+  dst_pitch: u32,
+  width: u32,
+  height: u32,
+  bytes: u32,
+  fg: u32,
+  bg: u32,
+  key: u32,
+  pub pixels: u64,
+  // lines of the running blit done so far, in the order they are drawn
+  done: u32,
+}
+
+impl Blitter {
+  pub fn new() -> Self {
+    Self {
+      op: 0,
+      src: [0; 2],
+      src_pitch: 0,
+      dst: [0; 2],
+      dst_pitch: 0,
+      width: 0,
+      height: 0,
+      bytes: 4,
+      fg: 0,
+      bg: 0,
+      key: 0,
+      pixels: 0,
+      done: 0,
+    }
+  }
+
+  fn register(&mut self, offset: u64) -> Option<&mut u32> {
+    match offset {
+      reg::OP => Some(&mut self.op),
+      reg::SRC_LOW => Some(&mut self.src[0]),
+      reg::SRC_HIGH => Some(&mut self.src[1]),
+      reg::SRC_PITCH => Some(&mut self.src_pitch),
+      reg::DST_LOW => Some(&mut self.dst[0]),
+      reg::DST_HIGH => Some(&mut self.dst[1]),
+      reg::DST_PITCH => Some(&mut self.dst_pitch),
+      reg::WIDTH => Some(&mut self.width),
+      reg::HEIGHT => Some(&mut self.height),
+      reg::BYTES => Some(&mut self.bytes),
+      reg::FG => Some(&mut self.fg),
+      reg::BG => Some(&mut self.bg),
+      reg::KEY => Some(&mut self.key),
+      _ => None,
+    }
+  }
+
+  pub fn load(&mut self, offset: u64) -> u64 {
+    match offset {
+      reg::PIXELS_LOW => self.pixels as u32 as u64,
+      reg::PIXELS_HIGH => self.pixels >> 32,
+      _ => self.register(offset).map_or(0, |reg| *reg as u64),
+    }
+  }
+
+  /// Write a register, true if it starts a blit.
+  pub fn store(&mut self, offset: u64, value: u64) -> bool {
+    if let Some(reg) = self.register(offset) {
+      *reg = value as u32;
+    }
+    if offset == reg::OP {
+      self.done = 0;
+    }
+    offset == reg::OP
+  }
+
+  /// Draw the next lines of the blit, true once it is done and `None` if it
+  /// reaches out of the memories or the registers are invalid. The lines
+  /// done by then stay drawn.
+  pub fn step(&mut self, vram: &mut [u8], dram: &mut Dram) -> Option<bool> {
+    let bytes = self.bytes as usize;
+    if !(1..=4).contains(&bytes) {
+      return None;
+    }
+    let (src_dram, dst_dram) =
+      (self.op & op::SRC_DRAM != 0, self.op & op::DST_DRAM != 0);
+    let src = self.src[0] as u64 | (self.src[1] as u64) << 32;
+    let dst = self.dst[0] as u64 | (self.dst[1] as u64) << 32;
+    let (width, height) = (self.width as usize, self.height);
+    let line = width * bytes;
+    let colour = |colour: u32| colour.to_le_bytes()[..bytes].to_vec();
+    let (fg, bg, key) = (colour(self.fg), colour(self.bg), colour(self.key));
+
+    // a copy down the same memory goes from the last line, for the lines of
+    // the source to be read before they are overwritten
+    let up = src_dram == dst_dram && dst > src;
+    let mut budget = STEP;
+    while self.done < height {
+      if budget == 0 {
+        return Some(false);
+      }
+      let y = if up { height - 1 - self.done } else { self.done } as u64;
+      let src = src.checked_add(y * self.src_pitch as u64)?;
+      let dst = dst.checked_add(y * self.dst_pitch as u64)?;
+      let mut read = |in_dram, addr, len| {
+        memory(vram, dram, in_dram, addr, len).map(|mem| mem.to_vec())
+      };
+
+      let pixels = match self.op & op::MASK {
+        op::FILL => None,
+        op::COPY => Some(read(src_dram, src, line)?),
+        op::KEY => {
+          let source = read(src_dram, src, line)?;
+          let mut pixels = read(dst_dram, dst, line)?;
+          let pairs = pixels.chunks_mut(bytes).zip(source.chunks(bytes));
+          for (px, source) in pairs.filter(|(_, source)| *source != key) {
+            px.copy_from_slice(source);
+          }
+          Some(pixels)
+        }
+        op::EXPAND => {
+          let bits = read(src_dram, src, width.div_ceil(8))?;
+          let mut pixels = read(dst_dram, dst, line)?;
+          for (x, px) in pixels.chunks_mut(bytes).enumerate() {
+            if bits[x / 8] & 0x80 >> (x % 8) != 0 {
+              px.copy_from_slice(&fg);
+            } else if self.op & op::TRANSPARENT == 0 {
+              px.copy_from_slice(&bg);
+            }
+          }
+          Some(pixels)
+        }
+        _ => return None,
+      };
+      // the line is checked against the memory before anything is built the
+      // size of it, a fill is drawn in place
+      let target = memory(vram, dram, dst_dram, dst, line)?;
+      match pixels {
+        Some(pixels) => target.copy_from_slice(&pixels),
+        None => target.chunks_mut(bytes).for_each(|px| px.copy_from_slice(&fg)),
+      }
+      self.pixels += width as u64;
+      self.done += 1;
+      budget = budget.saturating_sub((width as u64).max(1));
+    }
+    Some(true)
+  }
+}
+
+// Bytes `[addr, addr + len)` of the main memory, or of the pixel memory.
+fn memory<'a>(
+  vram: &'a mut [u8],
+  dram: &'a mut Dram,
+  in_dram: bool,
+  addr: u64,
+  len: usize,
+) -> Option<&'a mut [u8]> {
+  let (memory, at) = if in_dram {
+    (dram.as_slice_mut(), addr.checked_sub(dram::ADDR)?)
+  } else {
+    (vram, addr)
+  };
+  let at = usize::try_from(at).ok()?;
+  memory.get_mut(at..at.checked_add(len)?)
+}
@@ -0,0 +1,500 @@
+pub mod blit;
+pub mod text;
+pub mod tile;
+
+use {
+  self::{blit::Blitter, text::crtc, tile::Tiles},
+  crate::{
+    cpu::WORD,
+    dev::{clint::TIMEBASE_FREQ, Device},
+    fdt::{Fdt, Mapping},
+    Dram, Exception,
+  },
+};
+
+/// Size of the pixel memory, mapped at the base of the device. The control
+/// registers follow it.
+pub const VRAM: u64 = 0x40_0000;
+
+/// Offsets of the control registers from the end of the pixel memory.
+pub mod reg {
+  pub const WIDTH: u64 = 0x00;
+  pub const HEIGHT: u64 = 0x04;
+  /// Bytes from the start of a line to the start of the next one.
+  pub const STRIDE: u64 = 0x08;
+  pub const FORMAT: u64 = 0x0c;
+  /// Size of the pixel memory, read-only.
+  pub const VRAM_SIZE: u64 = 0x10;
+  /// Bits of [`control`](super::control).
+  pub const CONTROL: u64 = 0x14;
+  /// Index of the CRTC register [`CRTC_DATA`] accesses, from
+  /// [`crtc`](super::text::crtc).
+  pub const CRTC_INDEX: u64 = 0x18;
+  pub const CRTC_DATA: u64 = 0x1c;
+  /// Offset of the displayed buffer in the pixel memory. The guest draws the
+  /// next frame in another buffer, then flips to it.
+  pub const FRONT: u64 = 0x20;
+  /// Frames per second, in the time of the CLINT. No frames are shown at 0.
+  pub const FPS: u64 = 0x24;
+  /// Vertical blanks so far, read-only.
+  pub const FRAME: u64 = 0x28;
+  /// Bits of [`status`](super::status), written 1 to clear.
+  pub const STATUS: u64 = 0x2c;
+  /// Registers of the [`blit`](super::blit) engine, up to 0x100 bytes.
+  pub const BLIT: u64 = 0x100;
+  /// Registers of the [`tile`](super::tile) map and sprites, up to 0x100
+  /// bytes.
+  pub const TILE: u64 = 0x200;
+  /// Colours of the indexed format, 256 words of `0x00rrggbb`.
+  pub const PALETTE: u64 = 0x400;
+}
+
+/// Bits of the control register.
+pub mod control {
+  /// Show the cells of the [`text`](super::text) mode rather than the
+  /// pixels of the mode.
+  pub const TEXT: u32 = 1 << 0;
+  /// The high bit of the background blinks the character.
+  pub const BLINK: u32 = 1 << 1;
+  /// Raise the interrupt on vertical blanks.
+  pub const VBLANK: u32 = 1 << 2;
+  /// Raise the interrupt at the end of blits.
+  pub const BLIT: u32 = 1 << 3;
+  /// Compose the [`tile`](super::tile) map and sprites into the front buffer
+  /// on each vertical blank, in the graphics mode.
+  pub const TILES: u32 = 1 << 4;
+}
+
+/// Bits of the status register.
+pub mod status {
+  /// A vertical blank happened, the picture of the frame is out.
+  pub const VBLANK: u32 = 1 << 0;
+  /// A blit is done.
+  pub const BLIT: u32 = 1 << 1;
+  /// The last blit was invalid, or reached out of the memories.
+  pub const BLIT_ERROR: u32 = 1 << 2;
+  /// A blit is running, read-only.
+  pub const BUSY: u32 = 1 << 3;
+}
+
+/// Layout of a pixel, as a little-endian word with the red channel in the
+/// high bits, or an index in the palette. The names are those of
+/// `simple-framebuffer`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+  Rgb565 = 0,
+  Xrgb8888 = 1,
+  Rgb888 = 2,
+  Indexed8 = 3,
+}
+
+impl Format {
+  pub fn from_u32(format: u32) -> Option<Self> {
+    match format {
+      0 => Some(Self::Rgb565),
+      1 => Some(Self::Xrgb8888),
+      2 => Some(Self::Rgb888),
+      3 => Some(Self::Indexed8),
+      _ => None,
+    }
+  }
+
+  pub const fn bytes(self) -> u32 {
+    match self {
+      Self::Rgb565 => 2,
+      Self::Xrgb8888 => 4,
+      Self::Rgb888 => 3,
+      Self::Indexed8 => 1,
+    }
+  }
+
+  /// Name of the format in the `simple-framebuffer` binding, which has no
+  /// indexed formats.
+  pub const fn name(self) -> Option<&'static str> {
+    match self {
+      Self::Rgb565 => Some("r5g6b5"),
+      Self::Xrgb8888 => Some("x8r8g8b8"),
+      Self::Rgb888 => Some("r8g8b8"),
+      Self::Indexed8 => None,
+    }
+  }
+
+  // Pixel of the colour `index` of the palette, in its first bytes.
+  fn pixel(self, index: u8, palette: &[u32; 256]) -> [u8; 4] {
+    let [b, g, r, _] = palette[index as usize].to_le_bytes();
+    match self {
+      Self::Rgb565 => {
+        let [r, g, b] = [r, g, b].map(u16::from);
+        let px = (r >> 3) << 11 | (g >> 2) << 5 | b >> 3;
+        let [low, high] = px.to_le_bytes();
+        [low, high, 0, 0]
+      }
+      Self::Xrgb8888 | Self::Rgb888 => [b, g, r, 0],
+      Self::Indexed8 => [index, 0, 0, 0],
+    }
+  }
+
+  // Red, green and blue of the pixel `px`, scaled to 8 bits.
+  fn rgb(self, px: &[u8], palette: &[u32; 256]) -> [u8; 3] {
+    match self {
+      Self::Rgb565 => {
+        let px = u16::from_le_bytes([px[0], px[1]]);
+        let (r, g, b) = (px >> 11, px >> 5 & 0x3f, px & 0x1f);
+        [
+          (r << 3 | r >> 2) as u8,
+          (g << 2 | g >> 4) as u8,
+          (b << 3 | b >> 2) as u8,
+        ]
+      }
+      Self::Xrgb8888 | Self::Rgb888 => [px[2], px[1], px[0]],
+      Self::Indexed8 => {
+        let [b, g, r, _] = palette[px[0] as usize].to_le_bytes();
+        [r, g, b]
+      }
+    }
+  }
+}
+
+/// Palette at power-on: the colours of the text mode, a 6x6x6 colour cube
+/// and a ramp of greys, like the 256 colours of xterm.
+pub fn palette() -> [u32; 256] {
+  const LEVELS: [u32; 6] = [0x00, 0x5f, 0x87, 0xaf, 0xd7, 0xff];
+  let mut palette = [0; 256];
+  for (colour, [r, g, b]) in palette.iter_mut().zip(text::PALETTE) {
+    *colour = u32::from_be_bytes([0, r, g, b]);
+  }
+  for (i, colour) in palette[16..232].iter_mut().enumerate() {
+    let [r, g, b] = [i / 36, i / 6 % 6, i % 6].map(|level| LEVELS[level]);
+    *colour = r << 16 | g << 8 | b;
+  }
+  for (i, colour) in palette[232..].iter_mut().enumerate() {
+    let grey = 8 + 10 * i as u32;
+    *colour = grey * 0x01_0101;
+  }
+  palette
+}
+
+/// Display mode of the framebuffer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Mode {
+  pub width: u32,
+  pub height: u32,
+  pub stride: u32,
+  pub format: Format,
+}
+
+impl Mode {
+  /// Mode of `width` by `height` pixels, with lines packed together.
+  pub const fn packed(width: u32, height: u32, format: Format) -> Self {
+    Self { width, height, stride: width * format.bytes(), format }
+  }
+
+  /// Bytes of pixel memory the mode displays.
+  pub const fn size(&self) -> u64 {
+    self.stride as u64 * self.height as u64
+  }
+}
+
+/// Framebuffer whose mode the guest sets through the control registers.
+/// It starts in text mode, with the cells at the base of the pixel memory.
+#[derive(Debug)]
+pub struct Vga {
+  pub buf: Dram,
+  width: u32,
+  height: u32,
+  stride: u32,
+  format: u32,
+  control: u32,
+  index: u32,
+  crtc: [u8; crtc::COUNT],
+  fps: u32,
+  // ticks since the last vertical blank
+  ticks: u64,
+  frame: u64,
+  status: u32,
+  front: u32,
+  palette: [u32; 256],
+  blit: Blitter,
+  tiles: Tiles,
+}
+
+impl Vga {
+  pub const SIZE: u64 = VRAM + 0x1000;
+  /// Graphics mode at power-on, shown once the text mode is left.
+  pub const MODE: Mode = Mode::packed(224, 126, Format::Rgb888);
+  /// Frame rate at power-on.
+  pub const FPS: u32 = 60;
+
+  pub fn new() -> Self {
+    let mut vga = Self {
+      buf: Dram::with_capacity(VRAM as usize),
+      width: 0,
+      height: 0,
+      stride: 0,
+      format: 0,
+      control: 0,
+      index: 0,
+      crtc: [0; crtc::COUNT],
+      fps: 0,
+      ticks: 0,
+      frame: 0,
+      status: 0,
+      front: 0,
+      palette: [0; 256],
+      blit: Blitter::new(),
+      tiles: Tiles::new(),
+    };
+    vga.reset();
+    vga
+  }
+
+  pub fn is_text(&self) -> bool {
+    self.control & control::TEXT != 0
+  }
+
+  /// Graphics mode set by the registers, unless the device is in text mode,
+  /// or the mode is invalid or the front buffer doesn't fit the pixel
+  /// memory.
+  pub fn mode(&self) -> Option<Mode> {
+    if self.is_text() {
+      return None;
+    }
+    self.graphics()
+  }
+
+  /// Graphics mode set by the registers, shown once the text mode is left.
+  pub fn graphics(&self) -> Option<Mode> {
+    let format = Format::from_u32(self.format)?;
+    let mode = Mode {
+      width: self.width,
+      height: self.height,
+      stride: self.stride,
+      format,
+    };
+    let line = mode.width as u64 * format.bytes() as u64;
+    let fits =
+      line <= mode.stride as u64 && self.front as u64 + mode.size() <= VRAM;
+    (mode.width != 0 && mode.height != 0 && fits).then_some(mode)
+  }
+
+  /// Switch to the graphics `mode`.
+  pub fn set_mode(&mut self, mode: Mode) {
+    self.width = mode.width;
+    self.height = mode.height;
+    self.stride = mode.stride;
+    self.format = mode.format as u32;
+    self.control &= !control::TEXT;
+  }
+
+  /// Offset of the displayed buffer in the pixel memory.
+  pub fn front(&self) -> u32 {
+    self.front
+  }
+
+  pub fn palette(&self) -> &[u32; 256] {
+    &self.palette
+  }
+
+  /// Pixels the blitter processed so far.
+  pub fn blitted(&self) -> u64 {
+    self.blit.pixels
+  }
+
+  /// Frames per second, 0 without frames.
+  pub fn fps(&self) -> u32 {
+    self.fps
+  }
+
+  /// Vertical blanks so far. A host that captures the picture on each new
+  /// frame sees what the guest rendered for it.
+  pub fn frame(&self) -> u64 {
+    self.frame
+  }
+
+  /// Width and height of the displayed picture, if any.
+  pub fn resolution(&self) -> Option<(u32, u32)> {
+    if self.is_text() {
+      return Some((text::WIDTH, text::HEIGHT));
+    }
+    self.mode().map(|mode| (mode.width, mode.height))
+  }
+
+  /// Displayed picture as packed 8-bit red, green and blue, empty without a
+  /// valid mode. In graphics modes only the front buffer is read, so a frame
+  /// the guest draws elsewhere shows up whole once it flips.
+  pub fn rgb(&self) -> Vec<u8> {
+    if self.is_text() {
+      let blink = self.control & control::BLINK != 0;
+      return text::render(self.buf.as_slice(), &self.crtc, blink, self.frame);
+    }
+    let Some(mode) = self.mode() else { return Vec::new() };
+    let (width, bytes) = (mode.width as usize, mode.format.bytes() as usize);
+
+    let mut rgb = Vec::with_capacity(3 * width * mode.height as usize);
+    let vram = &self.buf.as_slice()[self.front as usize..];
+    for y in 0..mode.height as usize {
+      let line = &vram[y * mode.stride as usize..][..width * bytes];
+      rgb.extend(
+        line.chunks(bytes).flat_map(|px| mode.format.rgb(px, &self.palette)),
+      );
+    }
+    rgb
+  }
+
+  // Draw the tile map and the sprites into the front buffer.
+  fn compose(&mut self) {
+    let Some(mode) = self.mode() else { return };
+    let (width, bytes) = (mode.width as usize, mode.format.bytes() as usize);
+    let picture =
+      self.tiles.compose(self.buf.as_slice(), width, mode.height as usize);
+
+    let vram = &mut self.buf.as_slice_mut()[self.front as usize..];
+    for (y, indexes) in picture.chunks(width).enumerate() {
+      let line = &mut vram[y * mode.stride as usize..][..width * bytes];
+      for (px, &index) in line.chunks_mut(bytes).zip(indexes) {
+        px.copy_from_slice(&mode.format.pixel(index, &self.palette)[..bytes]);
+      }
+    }
+  }
+
+  fn register(&mut self, offset: u64) -> Option<&mut u32> {
+    match offset {
+      reg::WIDTH => Some(&mut self.width),
+      reg::HEIGHT => Some(&mut self.height),
+      reg::STRIDE => Some(&mut self.stride),
+      reg::FORMAT => Some(&mut self.format),
+      reg::CONTROL => Some(&mut self.control),
+      reg::CRTC_INDEX => Some(&mut self.index),
+      reg::FRONT => Some(&mut self.front),
+      reg::FPS => Some(&mut self.fps),
+      reg::TILE.. if offset < reg::TILE + 0x100 => {
+        self.tiles.register(offset - reg::TILE)
+      }
+      reg::PALETTE.. if offset < reg::PALETTE + 4 * 256 => {
+        Some(&mut self.palette[(offset - reg::PALETTE) as usize / 4])
+      }
+      _ => None,
+    }
+  }
+}
+
+impl Device for Vga {
+  fn load(&mut self, addr: u64, size: u8) -> Result<u64, Exception> {
+    if addr < VRAM {
+      return self.buf.load(addr, size);
+    }
+    if size != WORD || !addr.is_multiple_of(4) {
+      return Err(Exception::LoadAccessFault(addr));
+    }
+    Ok(match addr - VRAM {
+      reg::VRAM_SIZE => VRAM,
+      reg::FRAME => self.frame as u32 as u64,
+      reg::STATUS => self.status as u64,
+      offset if (reg::BLIT..reg::BLIT + 0x100).contains(&offset) => {
+        self.blit.load(offset - reg::BLIT)
+      }
+      reg::CRTC_DATA => {
+        self.crtc.get(self.index as usize).map_or(0, |&data| data as u64)
+      }
+      offset => self.register(offset).map_or(0, |reg| *reg as u64),
+    })
+  }
+
+  fn store(
+    &mut self,
+    addr: u64,
+    value: u64,
+    size: u8,
+  ) -> Result<(), Exception> {
+    if addr < VRAM {
+      return self.buf.store(addr, value, size);
+    }
+    if size != WORD || !addr.is_multiple_of(4) {
+      return Err(Exception::StoreAMOAccessFault(addr));
+    }
+    let offset = addr - VRAM;
+    if offset == reg::STATUS {
+      self.status &= !(value as u32 & !status::BUSY);
+    } else if (reg::BLIT..reg::BLIT + 0x100).contains(&offset) {
+      if self.blit.store(offset - reg::BLIT, value) {
+        self.status |= status::BUSY;
+      }
+    } else if offset == reg::CRTC_DATA {
+      if let Some(data) = self.crtc.get_mut(self.index as usize) {
+        *data = value as u8;
+      }
+    } else if let Some(reg) = self.register(offset) {
+      *reg = value as u32;
+    }
+    Ok(())
+  }
+
+  fn tick(&mut self, dram: &mut Dram) {
+    // a blit draws a bounded number of pixels on each tick
+    if self.status & status::BUSY != 0 {
+      match self.blit.step(self.buf.as_slice_mut(), dram) {
+        Some(false) => {}
+        Some(true) => self.status = self.status & !status::BUSY | status::BLIT,
+        None => {
+          self.status &= !status::BUSY;
+          self.status |= status::BLIT | status::BLIT_ERROR;
+        }
+      }
+    }
+
+    if self.fps == 0 {
+      return;
+    }
+    self.ticks += 1;
+    if self.ticks >= (TIMEBASE_FREQ / self.fps as u64).max(1) {
+      self.ticks = 0;
+      self.frame += 1;
+      self.status |= status::VBLANK;
+      if self.control & control::TILES != 0 {
+        self.compose();
+      }
+    }
+  }
+
+  fn reset(&mut self) {
+    self.buf.as_slice_mut().fill(0);
+    self.set_mode(Self::MODE);
+    self.control = control::TEXT | control::BLINK;
+    self.index = 0;
+    self.crtc = [0; crtc::COUNT];
+    // an underline cursor in the top left cell
+    self.crtc[crtc::CURSOR_START as usize] = 14;
+    self.crtc[crtc::CURSOR_END as usize] = 15;
+    self.fps = Self::FPS;
+    self.ticks = 0;
+    self.frame = 0;
+    self.status = 0;
+    self.front = 0;
+    self.palette = palette();
+    self.blit = Blitter::new();
+    self.tiles = Tiles::new();
+  }
+
+  fn irq(&self) -> bool {
+    let vblank = self.control & control::VBLANK != 0;
+    let blit = self.control & control::BLIT != 0;
+    vblank && self.status & status::VBLANK != 0
+      || blit && self.status & status::BLIT != 0
+  }
+
+  fn fdt(&self, fdt: &mut Fdt, mapping: &Mapping) {
+    // simple-framebuffer has no text mode, nor palette, the guest is given
+    // the graphics mode the device powers on with even while the text shows
+    let Some(mode) = self.graphics() else { return };
+    let Some(format) = mode.format.name() else { return };
+    let base = mapping.base + self.front as u64;
+    fdt.begin_node(&format!("framebuffer@{base:x}"));
+    fdt.prop_str("compatible", "simple-framebuffer");
+    fdt.prop_reg(&[(base, mode.size())]);
+    fdt.prop_u32("width", mode.width);
+    fdt.prop_u32("height", mode.height);
+    fdt.prop_u32("stride", mode.stride);
+    fdt.prop_str("format", format);
+    fdt.end_node();
+  }
+}
@@ -0,0 +1,120 @@
+//! Text mode: cells of a character and an attribute byte, drawn with a
+//! CP437 font like the VGA text mode at 0xb8000.
+
+/// Cells on the screen.
+pub const COLUMNS: u32 = 80;
+pub const ROWS: u32 = 25;
+
+/// Size of the glyphs, in pixels.
+pub const GLYPH_WIDTH: u32 = 8;
+pub const GLYPH_HEIGHT: u32 = 16;
+
+/// Size of the picture in text mode.
+pub const WIDTH: u32 = COLUMNS * GLYPH_WIDTH;
+pub const HEIGHT: u32 = ROWS * GLYPH_HEIGHT;
+
+/// Frames the cursor stays on, then off. Blinking characters go at half the
+/// rate, as on the VGA.
+pub const BLINK: u64 = 16;
+
+/// Indexes of the CRTC registers, as on the VGA.
+pub mod crtc {
+  /// First scanline of the cursor, [`CURSOR_OFF`] hides it.
+  pub const CURSOR_START: u8 = 0x0a;
+  /// Last scanline of the cursor.
+  pub const CURSOR_END: u8 = 0x0b;
+  /// Cell shown at the top left corner, high then low byte.
+  pub const START_HIGH: u8 = 0x0c;
+  pub const START_LOW: u8 = 0x0d;
+  /// Cell of the cursor, high then low byte.
+  pub const CURSOR_HIGH: u8 = 0x0e;
+  pub const CURSOR_LOW: u8 = 0x0f;
+
+  pub const CURSOR_OFF: u8 = 1 << 5;
+
+  /// Number of the registers, the others are kept but unused.
+  pub const COUNT: usize = 0x19;
+}
+
+/// 8x16 glyphs of the code page 437, a byte per line with the leftmost pixel
+/// in the high bit. Rasterized from DejaVu Sans Mono, the box drawing and
+/// block characters are drawn to fill the cell.
+pub static FONT: &[u8; 256 * 16] = include_bytes!("font.bin");
+
+/// Colours of the attributes, those of the VGA.
+pub const PALETTE: [[u8; 3]; 16] = [
+  [0x00, 0x00, 0x00],
+  [0x00, 0x00, 0xaa],
+  [0x00, 0xaa, 0x00],
+  [0x00, 0xaa, 0xaa],
+  [0xaa, 0x00, 0x00],
+  [0xaa, 0x00, 0xaa],
+  [0xaa, 0x55, 0x00],
+  [0xaa, 0xaa, 0xaa],
+  [0x55, 0x55, 0x55],
+  [0x55, 0x55, 0xff],
+  [0x55, 0xff, 0x55],
+  [0x55, 0xff, 0xff],
+  [0xff, 0x55, 0x55],
+  [0xff, 0x55, 0xff],
+  [0xff, 0xff, 0x55],
+  [0xff, 0xff, 0xff],
+];
+
+/// Screen of the cells in `vram` as packed 8-bit red, green and blue.
+///
+/// The attribute has the foreground in the low nibble and the background in
+/// the high one. With `blink`, the high bit of the background makes the
+/// character blink instead of brightening the background.
+pub(super) fn render(
+  vram: &[u8],
+  crtc: &[u8; crtc::COUNT],
+  blink: bool,
+  frame: u64,
+) -> Vec<u8> {
+  let word = |high: u8| {
+    let high = high as usize;
+    u16::from_be_bytes([crtc[high], crtc[high + 1]]) as usize
+  };
+  let start = word(crtc::START_HIGH);
+  let cursor = word(crtc::CURSOR_HIGH);
+  let (first, last) =
+    (crtc[crtc::CURSOR_START as usize], crtc[crtc::CURSOR_END as usize]);
+  let shown = first & crtc::CURSOR_OFF == 0 && frame & BLINK == 0;
+  let lines = (first & 0x1f) as usize..=(last & 0x1f) as usize;
+  let on = frame & (2 * BLINK) == 0;
+
+  let (width, columns) = (WIDTH as usize, COLUMNS as usize);
+  let mut rgb = vec![0; 3 * width * HEIGHT as usize];
+  for row in 0..ROWS as usize {
+    for column in 0..columns {
+      let cell = start + row * columns + column;
+      let at = 2 * cell % vram.len();
+      let (char, attr) = (vram[at], vram[at + 1]);
+      let (fg, mut bg) = (PALETTE[attr as usize & 0xf], attr >> 4);
+      let mut hidden = false;
+      if blink {
+        hidden = bg & 8 != 0 && !on;
+        bg &= 7;
+      }
+      let bg = PALETTE[bg as usize];
+
+      let glyph = &FONT[char as usize * 16..][..16];
+      for (line, &bits) in glyph.iter().enumerate() {
+        let bits = if shown && cell == cursor && lines.contains(&line) {
+          0xff
+        } else if hidden {
+          0
+        } else {
+          bits
+        };
+        let y = row * GLYPH_HEIGHT as usize + line;
+        let px = 3 * (y * width + column * GLYPH_WIDTH as usize);
+        for (x, px) in rgb[px..px + 24].chunks_mut(3).enumerate() {
+          px.copy_from_slice(if bits & 0x80 >> x != 0 { &fg } else { &bg });
+        }
+      }
+    }
+  }
+  rgb
+}
@@ -0,0 +1,159 @@
+//! Tile map and sprites, composed into the front buffer on each vertical
+//! blank. Tiles are 8x8 indexes in the palette, all kept in the pixel memory.
+
+/// Offsets of the tile registers from [`reg::TILE`](super::reg::TILE).
+pub mod reg {
+  /// Offset of the tiles in the pixel memory, 64 bytes each.
+  pub const TILES: u64 = 0x00;
+  /// Offset of the map, a little-endian half of the tile for each cell,
+  /// line by line.
+  pub const MAP: u64 = 0x04;
+  /// Size of the map in tiles, it repeats beyond.
+  pub const MAP_WIDTH: u64 = 0x08;
+  pub const MAP_HEIGHT: u64 = 0x0c;
+  /// Pixel of the map shown at the top left corner.
+  pub const SCROLL_X: u64 = 0x10;
+  pub const SCROLL_Y: u64 = 0x14;
+  /// Offset of the sprite table, 8 bytes for each sprite.
+  pub const SPRITES: u64 = 0x18;
+  /// Sprites of the table shown, up to [`SPRITES`](super::SPRITES).
+  pub const COUNT: u64 = 0x1c;
+}
+
+/// Flags of a sprite.
+pub mod flag {
+  pub const FLIP_X: u16 = 1 << 0;
+  pub const FLIP_Y: u16 = 1 << 1;
+  /// The sprite shows only through the transparent pixels of the map.
+  pub const BEHIND: u16 = 1 << 2;
+}
+
+/// Pixels on the side of a tile.
+pub const TILE: usize = 8;
+/// Most sprites in the table.
+pub const SPRITES: usize = 64;
+
+/// Entry of the sprite table, as laid out in the pixel memory: the position
+/// of the top left corner as signed halves, then the tile and the flags.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sprite {
+  pub x: i16,
+  pub y: i16,
+  pub tile: u16,
+  pub flags: u16,
+}
+
+impl Sprite {
+  pub fn to_bytes(self) -> [u8; 8] {
+    let [x, y] = [self.x, self.y].map(i16::to_le_bytes);
+    let [tile, flags] = [self.tile, self.flags].map(u16::to_le_bytes);
+    [x, y, tile, flags].concat().try_into().unwrap()
+  }
+
+  fn from_bytes(bytes: &[u8]) -> Self {
+    let half = |at: usize| [bytes[at], bytes[at + 1]];
+    Self {
+      x: i16::from_le_bytes(half(0)),
+      y: i16::from_le_bytes(half(2)),
+      tile: u16::from_le_bytes(half(4)),
+      flags: u16::from_le_bytes(half(6)),
+    }
+  }
+}
+
+#[derive(Debug)]
+pub(super) struct Tiles {
+  tiles: u32,
+  map: u32,
+  map_width: u32,
+  map_height: u32,
+  scroll_x: u32,
+  scroll_y: u32,
+  sprites: u32,
+  count: u32,
+}
+
+impl Tiles {
+  pub fn new() -> Self {
+    Self {
+      tiles: 0,
+      map: 0,
+      map_width: 0,
+      map_height: 0,
+      scroll_x: 0,
+      scroll_y: 0,
+      sprites: 0,
+      count: 0,
+    }
+  }
+
+  pub fn register(&mut self, offset: u64) -> Option<&mut u32> {
+    match offset {
+      reg::TILES => Some(&mut self.tiles),
+      reg::MAP => Some(&mut self.map),
+      reg::MAP_WIDTH => Some(&mut self.map_width),
+      reg::MAP_HEIGHT => Some(&mut self.map_height),
+      reg::SCROLL_X => Some(&mut self.scroll_x),
+      reg::SCROLL_Y => Some(&mut self.scroll_y),
+      reg::SPRITES => Some(&mut self.sprites),
+      reg::COUNT => Some(&mut self.count),
+      _ => None,
+    }
+  }
+
+  /// Palette indexes of a `width` by `height` picture of the map and the
+  /// sprites in `vram`. The index 0 is transparent, for the map it shows the
+  /// first colour of the palette.
+  pub fn compose(&self, vram: &[u8], width: usize, height: usize) -> Vec<u8> {
+    let byte = |at: usize| vram.get(at).copied().unwrap_or(0);
+    let half = |at: usize| u16::from_le_bytes([byte(at), byte(at + 1)]);
+    // pixel `x`, `y` of the tile `tile`
+    let px = |tile: u16, x: usize, y: usize| {
+      byte(self.tiles as usize + tile as usize * TILE * TILE + y * TILE + x)
+    };
+
+    let mut picture = vec![0; width * height];
+    let (columns, rows) = (self.map_width as usize, self.map_height as usize);
+    if columns != 0 && rows != 0 {
+      for (y, line) in picture.chunks_mut(width).enumerate() {
+        let y = (y + self.scroll_y as usize) % (rows * TILE);
+        for (x, index) in line.iter_mut().enumerate() {
+          let x = (x + self.scroll_x as usize) % (columns * TILE);
+          let cell = y / TILE * columns + x / TILE;
+          let tile = half(self.map as usize + 2 * cell);
+          *index = px(tile, x % TILE, y % TILE);
+        }
+      }
+    }
+
+    // the first sprites are drawn last, on top of the others
+    let map = picture.clone();
+    let count = (self.count as usize).min(SPRITES);
+    for i in (0..count).rev() {
+      let at = self.sprites as usize + 8 * i;
+      let entry: Vec<_> = (at..at + 8).map(byte).collect();
+      let sprite = Sprite::from_bytes(&entry);
+      let flip =
+        |flag, d| if sprite.flags & flag != 0 { TILE - 1 - d } else { d };
+      for dy in 0..TILE {
+        let y = sprite.y as isize + dy as isize;
+        for dx in 0..TILE {
+          let x = sprite.x as isize + dx as isize;
+          if !(0..width as isize).contains(&x)
+            || !(0..height as isize).contains(&y)
+          {
+            continue;
+          }
+          let index =
+            px(sprite.tile, flip(flag::FLIP_X, dx), flip(flag::FLIP_Y, dy));
+          let at = y as usize * width + x as usize;
+          let hidden = sprite.flags & flag::BEHIND != 0 && map[at] != 0;
+          if index != 0 && !hidden {
+            picture[at] = index;
+          }
+        }
+      }
+    }
+    picture
+  }
+}
@@ -0,0 +1,187 @@
+use {
+  crate::{
+    dev::virtio::{Chain, Queue, Virtio},
+    Dram,
+  },
+  std::{
+    fs::{File, OpenOptions},
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::Path,
+  },
+};
+
+pub const SECTOR: u64 = 512;
+
+/// Feature bits of the block device.
+pub mod feature {
+  pub const BLK_SIZE: u64 = 1 << 6;
+  pub const RO: u64 = 1 << 5;
+  pub const FLUSH: u64 = 1 << 9;
+}
+
+mod req {
+  pub const IN: u32 = 0;
+  pub const OUT: u32 = 1;
+  pub const FLUSH: u32 = 4;
+  pub const GET_ID: u32 = 8;
+}
+
+mod status {
+  pub const OK: u8 = 0;
+  pub const IOERR: u8 = 1;
+  pub const UNSUPP: u8 = 2;
+}
+
+const HEADER: usize = 16;
+const ID: &[u8] = b"vrisc-blk";
+const ID_LEN: usize = 20;
+// bytes moved between the image and the guest at once
+const SEGMENT: usize = 64 << 10;
+
+/// Virtio block device backed by a raw disk image.
+#[derive(Debug)]
+pub struct Blk {
+  file: File,
+  sectors: u64,
+  readonly: bool,
+}
+
+impl Blk {
+  /// Open the image at `path`, a `readonly` image is never written to.
+  pub fn open(path: impl AsRef<Path>, readonly: bool) -> io::Result<Self> {
+    let file = OpenOptions::new().read(true).write(!readonly).open(path)?;
+    let sectors = file.metadata()?.len() / SECTOR;
+    Ok(Self { file, sectors, readonly })
+  }
+
+  pub fn sectors(&self) -> u64 {
+    self.sectors
+  }
+
+  fn in_range(&self, sector: u64, len: usize) -> bool {
+    let end = sector.checked_add((len as u64).div_ceil(SECTOR));
+    end.is_some_and(|end| end <= self.sectors)
+  }
+
+  fn read(&mut self, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+    self.file.seek(SeekFrom::Start(offset))?;
+    self.file.read_exact(buf)
+  }
+
+  fn write(&mut self, offset: u64, data: &[u8]) -> io::Result<()> {
+    self.file.seek(SeekFrom::Start(offset))?;
+    self.file.write_all(data)
+  }
+
+  /// Read `len` bytes from `sector` into the chain a segment at a time.
+  fn read_chain(
+    &mut self,
+    chain: &Chain,
+    dram: &mut Dram,
+    sector: u64,
+    len: usize,
+  ) -> io::Result<()> {
+    let mut buf = vec![0; len.min(SEGMENT)];
+    let mut done = 0;
+    while done < len {
+      let n = (len - done).min(SEGMENT);
+      self.read(sector * SECTOR + done as u64, &mut buf[..n])?;
+      chain.write(dram, done, &buf[..n]);
+      done += n;
+    }
+    Ok(())
+  }
+
+  /// Write `len` bytes following the header of the chain to `sector`.
+  fn write_chain(
+    &mut self,
+    chain: &Chain,
+    dram: &Dram,
+    sector: u64,
+    len: usize,
+  ) -> io::Result<()> {
+    let mut buf = vec![0; len.min(SEGMENT)];
+    let mut done = 0;
+    while done < len {
+      let n = (len - done).min(SEGMENT);
+      chain.read_at(dram, HEADER + done, &mut buf[..n]);
+      self.write(sector * SECTOR + done as u64, &buf[..n])?;
+      done += n;
+    }
+    Ok(())
+  }
+}
+
+impl Virtio for Blk {
+  fn id(&self) -> u32 {
+    2
+  }
+
+  fn features(&self) -> u64 {
+    let ro = if self.readonly { feature::RO } else { 0 };
+    feature::BLK_SIZE | feature::FLUSH | ro
+  }
+
+  fn queues(&self) -> usize {
+    1
+  }
+
+  fn config(&self) -> Vec<u8> {
+    let mut config = vec![0; 24];
+    config[0..8].copy_from_slice(&self.sectors.to_le_bytes());
+    config[20..24].copy_from_slice(&(SECTOR as u32).to_le_bytes());
+    config
+  }
+
+  fn process(&mut self, _index: usize, queue: &mut Queue, dram: &mut Dram) {
+    while let Some(chain) = queue.pop(dram) {
+      let mut header = [0; HEADER];
+      let header_len = chain.read_at(dram, 0, &mut header);
+      // the status byte ends the buffers the device writes into
+      let Some(data_len) = chain.writable().checked_sub(1) else {
+        queue.push(dram, chain.head, 0);
+        continue;
+      };
+      if header_len < HEADER {
+        chain.write(dram, data_len, &[status::IOERR]);
+        queue.push(dram, chain.head, 1);
+        continue;
+      }
+
+      let kind = u32::from_le_bytes(header[0..4].try_into().unwrap());
+      let sector = u64::from_le_bytes(header[8..16].try_into().unwrap());
+      let (status, written) = match kind {
+        req::IN => {
+          if self.in_range(sector, data_len)
+            && self.read_chain(&chain, dram, sector, data_len).is_ok()
+          {
+            (status::OK, data_len)
+          } else {
+            (status::IOERR, 0)
+          }
+        }
+        req::OUT => {
+          let len = chain.readable() - HEADER;
+          let ok = !self.readonly
+            && self.in_range(sector, len)
+            && self.write_chain(&chain, dram, sector, len).is_ok();
+          (if ok { status::OK } else { status::IOERR }, 0)
+        }
+        req::FLUSH => {
+          let ok = self.readonly || self.file.sync_data().is_ok();
+          (if ok { status::OK } else { status::IOERR }, 0)
+        }
+        req::GET_ID => {
+          let mut id = [0; ID_LEN];
+          id[..ID.len()].copy_from_slice(ID);
+          let len = data_len.min(ID_LEN);
+          (status::OK, chain.write(dram, 0, &id[..len]))
+        }
+        _ => (status::UNSUPP, 0),
+      };
+
+      chain.write(dram, data_len, &[status]);
+      queue.push(dram, chain.head, written as u32 + 1);
+    }
+  }
+}
@@ -0,0 +1,234 @@
+use {
+  crate::{
+    dev::{
+      serial::Serial,
+      virtio::{Queue, Virtio},
+    },
+    Dram,
+  },
+  std::collections::VecDeque,
+};
+
+/// Feature bits of the console device.
+pub mod feature {
+  pub const MULTIPORT: u64 = 1 << 1;
+  pub const EMERG_WRITE: u64 = 1 << 2;
+}
+
+/// Events of the control messages.
+mod event {
+  pub const DEVICE_READY: u16 = 0;
+  pub const DEVICE_ADD: u16 = 1;
+  pub const PORT_READY: u16 = 3;
+  pub const CONSOLE_PORT: u16 = 4;
+  pub const PORT_OPEN: u16 = 6;
+  pub const PORT_NAME: u16 = 7;
+}
+
+const CONTROL_RX: usize = 2;
+const CONTROL_TX: usize = 3;
+
+// offset of `emerg_wr` in the configuration space
+const EMERG_WR: u64 = 8;
+// bytes buffered from the host per port
+const INPUT: usize = 4096;
+const POLL: u32 = 1024;
+
+/// Channel of the console device to the host.
+#[derive(Debug)]
+struct Port {
+  name: Option<String>,
+  backend: Box<dyn Serial>,
+  input: VecDeque<u8>,
+  // the guest has the port open
+  open: bool,
+}
+
+/// Virtio console with multiport support. The port 0 is the console of the
+/// guest, the other ports are named channels, such as one to a guest agent.
+#[derive(Debug)]
+pub struct Console {
+  ports: Vec<Port>,
+  // control messages waiting for a buffer of the driver
+  control: VecDeque<Vec<u8>>,
+  poll: u32,
+}
+
+impl Console {
+  pub fn new(console: impl Serial + 'static) -> Self {
+    let port = Port {
+      name: None,
+      backend: Box::new(console),
+      input: VecDeque::new(),
+      open: false,
+    };
+    Self { ports: vec![port], control: VecDeque::new(), poll: 0 }
+  }
+
+  /// Add a port `name` connected to `backend`, the guest finds it as
+  /// `/dev/virtio-ports/<name>`.
+  pub fn with_port(
+    mut self,
+    name: &str,
+    backend: impl Serial + 'static,
+  ) -> Self {
+    self.ports.push(Port {
+      name: Some(name.to_owned()),
+      backend: Box::new(backend),
+      input: VecDeque::new(),
+      open: false,
+    });
+    self
+  }
+
+  /// The guest opened the port `id`.
+  pub fn is_open(&self, id: usize) -> bool {
+    self.ports.get(id).is_some_and(|port| port.open)
+  }
+
+  // Queues of the port `id`, the receive queue first.
+  fn queues(id: usize) -> (usize, usize) {
+    match id {
+      0 => (0, 1),
+      _ => (2 + 2 * id, 3 + 2 * id),
+    }
+  }
+
+  fn send(&mut self, id: usize, event: u16, value: u16, data: &[u8]) {
+    let mut message = Vec::with_capacity(8 + data.len());
+    message.extend((id as u32).to_le_bytes());
+    message.extend(event.to_le_bytes());
+    message.extend(value.to_le_bytes());
+    message.extend(data);
+    self.control.push_back(message);
+  }
+
+  fn handle(&mut self, message: &[u8]) {
+    if message.len() < 8 {
+      return;
+    }
+    let id = u32::from_le_bytes(message[0..4].try_into().unwrap()) as usize;
+    let event = u16::from_le_bytes([message[4], message[5]]);
+    let value = u16::from_le_bytes([message[6], message[7]]);
+
+    match event {
+      event::DEVICE_READY if value == 1 => {
+        for id in 0..self.ports.len() {
+          self.send(id, event::DEVICE_ADD, 0, &[]);
+        }
+      }
+      event::PORT_READY if value == 1 && id < self.ports.len() => {
+        match self.ports[id].name.clone() {
+          Some(name) => self.send(id, event::PORT_NAME, 0, name.as_bytes()),
+          None => self.send(id, event::CONSOLE_PORT, 1, &[]),
+        }
+        // the host end is always connected
+        self.send(id, event::PORT_OPEN, 1, &[]);
+      }
+      event::PORT_OPEN if id < self.ports.len() => {
+        self.ports[id].open = value == 1;
+      }
+      _ => {}
+    }
+  }
+
+  fn deliver(&mut self, queue: &mut Queue, dram: &mut Dram) {
+    while !self.control.is_empty() {
+      let Some(chain) = queue.pop(dram) else { break };
+      let message = self.control.pop_front().unwrap();
+      let written = chain.write(dram, 0, &message);
+      queue.push(dram, chain.head, written as u32);
+    }
+  }
+
+  fn receive(port: &mut Port, queue: &mut Queue, dram: &mut Dram) {
+    while !port.input.is_empty() {
+      let Some(chain) = queue.pop(dram) else { break };
+      let len = chain.writable().min(port.input.len());
+      let data: Vec<_> = port.input.drain(..len).collect();
+      let written = chain.write(dram, 0, &data);
+      queue.push(dram, chain.head, written as u32);
+    }
+  }
+}
+
+impl Virtio for Console {
+  fn id(&self) -> u32 {
+    3
+  }
+
+  fn features(&self) -> u64 {
+    feature::MULTIPORT | feature::EMERG_WRITE
+  }
+
+  fn queues(&self) -> usize {
+    2 + 2 * self.ports.len()
+  }
+
+  fn config(&self) -> Vec<u8> {
+    let mut config = vec![0; 12];
+    // no size for the console, the terminal of the host has its own
+    config[4..8].copy_from_slice(&(self.ports.len() as u32).to_le_bytes());
+    config
+  }
+
+  fn store_config(&mut self, offset: u64, value: u64, _size: u8) {
+    if offset == EMERG_WR {
+      self.ports[0].backend.write(value as u8);
+    }
+  }
+
+  fn process(&mut self, index: usize, queue: &mut Queue, dram: &mut Dram) {
+    match index {
+      CONTROL_RX => self.deliver(queue, dram),
+      CONTROL_TX => {
+        while let Some(chain) = queue.pop(dram) {
+          self.handle(&chain.read(dram));
+          queue.push(dram, chain.head, 0);
+        }
+      }
+      _ => {
+        let port = (0..self.ports.len()).find_map(|id| {
+          let (rx, tx) = Self::queues(id);
+          (index == rx || index == tx).then_some((id, index == rx))
+        });
+        let Some((id, rx)) = port else { return };
+        let port = &mut self.ports[id];
+        if rx {
+          return Self::receive(port, queue, dram);
+        }
+        while let Some(chain) = queue.pop(dram) {
+          for byte in chain.read(dram) {
+            port.backend.write(byte);
+          }
+          queue.push(dram, chain.head, 0);
+        }
+      }
+    }
+  }
+
+  fn poll(&mut self, queues: &mut [Queue], dram: &mut Dram) {
+    self.deliver(&mut queues[CONTROL_RX], dram);
+
+    self.poll += 1;
+    if self.poll < POLL {
+      return;
+    }
+    self.poll = 0;
+    for (id, port) in self.ports.iter_mut().enumerate() {
+      while port.input.len() < INPUT {
+        let Some(byte) = port.backend.read() else { break };
+        port.input.push_back(byte);
+      }
+      let (rx, _) = Self::queues(id);
+      Self::receive(port, &mut queues[rx], dram);
+    }
+  }
+
+  fn reset(&mut self) {
+    self.control.clear();
+    for port in &mut self.ports {
+      port.open = false;
+    }
+  }
+}
@@ -0,0 +1,210 @@
+use {
+  crate::{
+    dev::virtio::{Queue, Virtio},
+    Dram,
+  },
+  std::collections::VecDeque,
+};
+
+/// Selectors of the configuration space, written to its first byte.
+pub mod cfg {
+  pub const ID_NAME: u8 = 0x01;
+  pub const ID_SERIAL: u8 = 0x02;
+  pub const ID_DEVIDS: u8 = 0x03;
+  pub const PROP_BITS: u8 = 0x10;
+  /// Codes of the event type of the subselector.
+  pub const EV_BITS: u8 = 0x11;
+  pub const ABS_INFO: u8 = 0x12;
+}
+
+/// Event types and codes of Linux evdev.
+pub mod ev {
+  pub const SYN: u16 = 0x00;
+  pub const KEY: u16 = 0x01;
+  pub const REL: u16 = 0x02;
+
+  pub const SYN_REPORT: u16 = 0;
+  pub const REL_X: u16 = 0x00;
+  pub const REL_Y: u16 = 0x01;
+  pub const REL_WHEEL: u16 = 0x08;
+  pub const BTN_LEFT: u16 = 0x110;
+  pub const BTN_RIGHT: u16 = 0x111;
+  pub const BTN_MIDDLE: u16 = 0x112;
+  /// Highest key code of a keyboard.
+  pub const KEY_MAX: u16 = 0xff;
+}
+
+const EVENTQ: usize = 0;
+const STATUSQ: usize = 1;
+
+// offset of the union of the configuration space
+const CONFIG_DATA: usize = 8;
+// events buffered for the driver
+const EVENTS: usize = 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Button {
+  Left,
+  Right,
+  Middle,
+}
+
+/// Input from the host.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+  /// Key of the Linux code, like `KEY_A` = 30, pressed or released.
+  Key(u16, bool),
+  Button(Button, bool),
+  /// Relative motion of the pointer.
+  Motion(i32, i32),
+  /// Clicks of the wheel, away from the user when positive.
+  Wheel(i32),
+}
+
+/// Virtio keyboard and mouse, fed with the events of the host.
+#[derive(Debug)]
+pub struct Input {
+  // selector and subselector of the configuration space
+  select: u8,
+  subsel: u8,
+  // events of the evdev protocol waiting for a buffer of the driver
+  events: VecDeque<[u8; 8]>,
+}
+
+impl Input {
+  pub fn new() -> Self {
+    Self { select: 0, subsel: 0, events: VecDeque::new() }
+  }
+
+  /// Queue `event` for the guest, false if the queue of the device is full
+  /// and the event is lost.
+  pub fn push(&mut self, event: Event) -> bool {
+    let events = match event {
+      Event::Key(code, pressed) => vec![(ev::KEY, code, pressed as i32)],
+      Event::Button(button, pressed) => {
+        let code = match button {
+          Button::Left => ev::BTN_LEFT,
+          Button::Right => ev::BTN_RIGHT,
+          Button::Middle => ev::BTN_MIDDLE,
+        };
+        vec![(ev::KEY, code, pressed as i32)]
+      }
+      Event::Motion(dx, dy) => {
+        vec![(ev::REL, ev::REL_X, dx), (ev::REL, ev::REL_Y, dy)]
+      }
+      Event::Wheel(clicks) => vec![(ev::REL, ev::REL_WHEEL, clicks)],
+    };
+    // the whole report or nothing, for the driver to never see half of it
+    if self.events.len() + events.len() + 1 > EVENTS {
+      return false;
+    }
+    let report = (ev::SYN, ev::SYN_REPORT, 0);
+    for (kind, code, value) in events.into_iter().chain([report]) {
+      let mut bytes = [0; 8];
+      bytes[..2].copy_from_slice(&kind.to_le_bytes());
+      bytes[2..4].copy_from_slice(&code.to_le_bytes());
+      bytes[4..].copy_from_slice(&value.to_le_bytes());
+      self.events.push_back(bytes);
+    }
+    true
+  }
+
+  // Codes of the event type `kind`, as a bitmap.
+  fn codes(kind: u16) -> Vec<u8> {
+    let codes = match kind {
+      ev::KEY => {
+        let buttons = [ev::BTN_LEFT, ev::BTN_RIGHT, ev::BTN_MIDDLE];
+        (1..=ev::KEY_MAX).chain(buttons).collect()
+      }
+      ev::REL => vec![ev::REL_X, ev::REL_Y, ev::REL_WHEEL],
+      _ => Vec::new(),
+    };
+    let len = codes.iter().max().map_or(0, |&max| max as usize / 8 + 1);
+    let mut bitmap = vec![0; len];
+    for code in codes {
+      bitmap[code as usize / 8] |= 1 << (code % 8);
+    }
+    bitmap
+  }
+
+  fn deliver(&mut self, queue: &mut Queue, dram: &mut Dram) {
+    while !self.events.is_empty() {
+      let Some(chain) = queue.pop(dram) else { break };
+      let event = self.events.pop_front().unwrap();
+      let written = chain.write(dram, 0, &event);
+      queue.push(dram, chain.head, written as u32);
+    }
+  }
+}
+
+impl Default for Input {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl Virtio for Input {
+  fn id(&self) -> u32 {
+    18
+  }
+
+  fn features(&self) -> u64 {
+    0
+  }
+
+  fn queues(&self) -> usize {
+    2
+  }
+
+  fn config(&self) -> Vec<u8> {
+    let data = match self.select {
+      cfg::ID_NAME => b"vrisc input".to_vec(),
+      cfg::ID_SERIAL => b"0".to_vec(),
+      // virtual bus, then the vendor, the product and the version
+      cfg::ID_DEVIDS => [6u16, 0, 0, 1].map(u16::to_le_bytes).concat(),
+      cfg::EV_BITS => Self::codes(self.subsel as u16),
+      _ => Vec::new(),
+    };
+    let mut config = vec![0; CONFIG_DATA + 128];
+    config[0] = self.select;
+    config[1] = self.subsel;
+    config[2] = data.len() as u8;
+    config[CONFIG_DATA..CONFIG_DATA + data.len()].copy_from_slice(&data);
+    config
+  }
+
+  fn store_config(&mut self, offset: u64, value: u64, size: u8) {
+    let bytes = value.to_le_bytes();
+    for (offset, byte) in (offset..).zip(&bytes[..size as usize / 8]) {
+      match offset {
+        0 => self.select = *byte,
+        1 => self.subsel = *byte,
+        _ => {}
+      }
+    }
+  }
+
+  fn process(&mut self, index: usize, queue: &mut Queue, dram: &mut Dram) {
+    match index {
+      EVENTQ => self.deliver(queue, dram),
+      // the LEDs of the keyboard have nowhere to show
+      STATUSQ => {
+        while let Some(chain) = queue.pop(dram) {
+          queue.push(dram, chain.head, 0);
+        }
+      }
+      _ => {}
+    }
+  }
+
+  fn poll(&mut self, queues: &mut [Queue], dram: &mut Dram) {
+    self.deliver(&mut queues[EVENTQ], dram);
+  }
+
+  // the events were meant for the driver that is gone
+  fn reset(&mut self) {
+    self.select = 0;
+    self.subsel = 0;
+    self.events.clear();
+  }
+}
@@ -0,0 +1,501 @@
+pub mod blk;
+pub mod console;
+pub mod input;
+pub mod net;
+#[cfg(target_os = "linux")]
+pub mod p9;
+pub mod rng;
+pub mod slirp;
+
+use {
+  crate::{
+    bus::dram,
+    cpu::{BYTE, DWORD, HALF, WORD},
+    dev::Device,
+    fdt::{Fdt, Mapping},
+    Dram, Exception,
+  },
+  std::fmt::Debug,
+};
+
+/// Register offsets of the virtio-mmio transport.
+pub mod reg {
+  pub const MAGIC: u64 = 0x000;
+  pub const VERSION: u64 = 0x004;
+  pub const DEVICE_ID: u64 = 0x008;
+  pub const VENDOR_ID: u64 = 0x00c;
+  pub const DEVICE_FEATURES: u64 = 0x010;
+  pub const DEVICE_FEATURES_SEL: u64 = 0x014;
+  pub const DRIVER_FEATURES: u64 = 0x020;
+  pub const DRIVER_FEATURES_SEL: u64 = 0x024;
+  pub const QUEUE_SEL: u64 = 0x030;
+  pub const QUEUE_NUM_MAX: u64 = 0x034;
+  pub const QUEUE_NUM: u64 = 0x038;
+  pub const QUEUE_READY: u64 = 0x044;
+  pub const QUEUE_NOTIFY: u64 = 0x050;
+  pub const INTERRUPT_STATUS: u64 = 0x060;
+  pub const INTERRUPT_ACK: u64 = 0x064;
+  pub const STATUS: u64 = 0x070;
+  pub const QUEUE_DESC_LOW: u64 = 0x080;
+  pub const QUEUE_DESC_HIGH: u64 = 0x084;
+  pub const QUEUE_DRIVER_LOW: u64 = 0x090;
+  pub const QUEUE_DRIVER_HIGH: u64 = 0x094;
+  pub const QUEUE_DEVICE_LOW: u64 = 0x0a0;
+  pub const QUEUE_DEVICE_HIGH: u64 = 0x0a4;
+  pub const CONFIG_GENERATION: u64 = 0x0fc;
+  pub const CONFIG: u64 = 0x100;
+}
+
+/// Bits of `InterruptStatus`.
+pub mod int {
+  pub const USED_BUFFER: u32 = 1 << 0;
+  pub const CONFIG_CHANGE: u32 = 1 << 1;
+}
+
+/// Feature bits common to every device type.
+pub const VIRTIO_F_VERSION_1: u64 = 1 << 32;
+
+/// Size of the register file of a transport.
+pub const MMIO_SIZE: u64 = 0x1000;
+
+const MAGIC: u32 = 0x7472_6976; // "virt"
+const VENDOR: u32 = 0x554d_4551; // "QEMU", as most drivers expect
+const QUEUE_MAX: u16 = 256;
+
+const DESC_F_NEXT: u16 = 1;
+const DESC_F_WRITE: u16 = 2;
+
+/// Device type behind a virtio-mmio transport.
+pub trait Virtio: Debug + 'static {
+  /// Device ID of the virtio specification.
+  fn id(&self) -> u32;
+
+  /// Device-specific feature bits offered to the driver.
+  fn features(&self) -> u64;
+
+  /// Number of virtqueues.
+  fn queues(&self) -> usize;
+
+  /// Contents of the configuration space.
+  fn config(&self) -> Vec<u8>;
+
+  /// Write to the configuration space, read-only by default.
+  fn store_config(&mut self, _offset: u64, _value: u64, _size: u8) {}
+
+  /// Serve the buffers the driver made available in the queue `index`.
+  fn process(&mut self, index: usize, queue: &mut Queue, dram: &mut Dram);
+
+  /// Advance the device, for devices that receive from the host.
+  fn poll(&mut self, _queues: &mut [Queue], _dram: &mut Dram) {}
+
+  /// Return the device to its initial state, when the driver resets it.
+  fn reset(&mut self) {}
+}
+
+/// Buffer described by a descriptor.
+#[derive(Debug, Clone, Copy)]
+pub struct Desc {
+  pub addr: u64,
+  pub len: u32,
+  /// The device writes into the buffer, otherwise it reads from it.
+  pub write: bool,
+}
+
+/// Descriptor chain made available by the driver.
+#[derive(Debug)]
+pub struct Chain {
+  pub head: u16,
+  pub descs: Vec<Desc>,
+}
+
+impl Chain {
+  /// Bytes of the buffers the device reads from, concatenated.
+  pub fn read(&self, dram: &Dram) -> Vec<u8> {
+    let mut data = Vec::new();
+    for desc in self.descs.iter().filter(|desc| !desc.write) {
+      if let Some(mem) = guest(dram.as_slice(), desc.addr, desc.len as usize) {
+        data.extend_from_slice(mem);
+      }
+    }
+    data
+  }
+
+  /// Read into `buf` from `offset` in the buffers the device reads from,
+  /// returning how much of it they hold.
+  pub fn read_at(
+    &self,
+    dram: &Dram,
+    mut offset: usize,
+    buf: &mut [u8],
+  ) -> usize {
+    let mut read = 0;
+    for desc in self.descs.iter().filter(|desc| !desc.write) {
+      let len = desc.len as usize;
+      if offset >= len {
+        offset -= len;
+        continue;
+      }
+
+      let n = (len - offset).min(buf.len() - read);
+      let mem = desc
+        .addr
+        .checked_add(offset as u64)
+        .and_then(|addr| guest(dram.as_slice(), addr, n));
+      if let Some(mem) = mem {
+        buf[read..read + n].copy_from_slice(mem);
+      }
+      read += n;
+      offset = 0;
+      if read == buf.len() {
+        break;
+      }
+    }
+    read
+  }
+
+  /// Total size of the buffers the device reads from.
+  pub fn readable(&self) -> usize {
+    self.descs.iter().filter(|desc| !desc.write).map(|d| d.len as usize).sum()
+  }
+
+  /// Total size of the buffers the device writes into.
+  pub fn writable(&self) -> usize {
+    self.descs.iter().filter(|desc| desc.write).map(|d| d.len as usize).sum()
+  }
+
+  /// Write `data` at `offset` into the buffers the device writes into,
+  /// returning how much of it fits.
+  pub fn write(
+    &self,
+    dram: &mut Dram,
+    mut offset: usize,
+    data: &[u8],
+  ) -> usize {
+    let mut written = 0;
+    for desc in self.descs.iter().filter(|desc| desc.write) {
+      let len = desc.len as usize;
+      if offset >= len {
+        offset -= len;
+        continue;
+      }
+
+      let n = (len - offset).min(data.len() - written);
+      let mem = desc
+        .addr
+        .checked_add(offset as u64)
+        .and_then(|addr| guest_mut(dram.as_slice_mut(), addr, n));
+      if let Some(mem) = mem {
+        mem.copy_from_slice(&data[written..written + n]);
+      }
+      written += n;
+      offset = 0;
+      if written == data.len() {
+        break;
+      }
+    }
+    written
+  }
+}
+
+/// Split virtqueue.
+#[derive(Debug, Default)]
+pub struct Queue {
+  pub num: u16,
+  pub ready: bool,
+  desc: u64,
+  avail: u64,
+  used: u64,
+  last_avail: u16,
+  // notified by the driver, served on the next tick
+  notified: bool,
+  // buffers were used since the last interrupt
+  used_buffers: bool,
+}
+
+impl Queue {
+  /// Take the next descriptor chain made available by the driver.
+  pub fn pop(&mut self, dram: &Dram) -> Option<Chain> {
+    if !self.ready || self.num == 0 {
+      return None;
+    }
+    let mem = dram.as_slice();
+    let idx = read16(mem, self.avail, 2)?;
+    if idx == self.last_avail {
+      return None;
+    }
+
+    let slot = (self.last_avail % self.num) as u64;
+    let head = read16(mem, self.avail, 4 + 2 * slot)?;
+    self.last_avail = self.last_avail.wrapping_add(1);
+
+    let mut descs = Vec::new();
+    let mut i = head;
+    // a looping chain can't be longer than the queue
+    for _ in 0..self.num {
+      let at = self.desc.checked_add(16 * (i % self.num) as u64)?;
+      let flags = read16(mem, at, 12)?;
+      descs.push(Desc {
+        addr: read64(mem, at, 0)?,
+        len: read32(mem, at, 8)?,
+        write: flags & DESC_F_WRITE != 0,
+      });
+      if flags & DESC_F_NEXT == 0 {
+        break;
+      }
+      i = read16(mem, at, 14)?;
+    }
+    Some(Chain { head, descs })
+  }
+
+  /// Return the chain `head` to the driver, with `len` bytes written into it.
+  pub fn push(&mut self, dram: &mut Dram, head: u16, len: u32) {
+    if self.num == 0 {
+      return;
+    }
+    let mem = dram.as_slice_mut();
+    let Some(idx) = read16(mem, self.used, 2) else { return };
+    let Some(at) = self.used.checked_add(8 * (idx % self.num) as u64) else {
+      return;
+    };
+    write(mem, at, 4, &(head as u32).to_le_bytes());
+    write(mem, at, 8, &len.to_le_bytes());
+    write(mem, self.used, 2, &idx.wrapping_add(1).to_le_bytes());
+    self.used_buffers = true;
+  }
+}
+
+/// Virtio-mmio transport of a device.
+#[derive(Debug)]
+pub struct Mmio<D> {
+  pub device: D,
+  queues: Vec<Queue>,
+  device_features_sel: u32,
+  driver_features: u64,
+  driver_features_sel: u32,
+  queue_sel: u32,
+  interrupt: u32,
+  status: u32,
+}
+
+impl<D: Virtio> Mmio<D> {
+  pub fn new(device: D) -> Self {
+    let queues = (0..device.queues()).map(|_| Queue::default()).collect();
+    Self {
+      device,
+      queues,
+      device_features_sel: 0,
+      driver_features: 0,
+      driver_features_sel: 0,
+      queue_sel: 0,
+      interrupt: 0,
+      status: 0,
+    }
+  }
+
+  /// Features negotiated with the driver.
+  pub fn features(&self) -> u64 {
+    self.driver_features & self.device_features()
+  }
+
+  fn device_features(&self) -> u64 {
+    self.device.features() | VIRTIO_F_VERSION_1
+  }
+
+  fn queue(&mut self) -> Option<&mut Queue> {
+    self.queues.get_mut(self.queue_sel as usize)
+  }
+
+  fn reset_transport(&mut self) {
+    self.device.reset();
+    for queue in &mut self.queues {
+      *queue = Queue::default();
+    }
+    self.device_features_sel = 0;
+    self.driver_features = 0;
+    self.driver_features_sel = 0;
+    self.queue_sel = 0;
+    self.interrupt = 0;
+    self.status = 0;
+  }
+
+  fn load_config(&self, offset: u64, size: u8) -> u64 {
+    let config = self.device.config();
+    let at = offset as usize;
+    let mut value = [0; 8];
+    if let Some(bytes) = config.get(at..at + size as usize / 8) {
+      value[..bytes.len()].copy_from_slice(bytes);
+    }
+    u64::from_le_bytes(value)
+  }
+}
+
+impl<D: Virtio> Device for Mmio<D> {
+  fn load(&mut self, addr: u64, size: u8) -> Result<u64, Exception> {
+    if addr >= reg::CONFIG {
+      if !matches!(size, BYTE | HALF | WORD | DWORD) {
+        return Err(Exception::LoadAccessFault(addr));
+      }
+      return Ok(self.load_config(addr - reg::CONFIG, size));
+    }
+    if size != WORD || !addr.is_multiple_of(4) {
+      return Err(Exception::LoadAccessFault(addr));
+    }
+
+    let sel = self.queue_sel as usize;
+    let queue = self.queues.get(sel);
+    Ok(match addr {
+      reg::MAGIC => MAGIC,
+      reg::VERSION => 2,
+      reg::DEVICE_ID => self.device.id(),
+      reg::VENDOR_ID => VENDOR,
+      reg::DEVICE_FEATURES => match self.device_features_sel {
+        0 => self.device_features() as u32,
+        1 => (self.device_features() >> 32) as u32,
+        _ => 0,
+      },
+      reg::QUEUE_NUM_MAX => queue.map_or(0, |_| QUEUE_MAX as u32),
+      reg::QUEUE_READY => queue.map_or(0, |q| q.ready as u32),
+      reg::INTERRUPT_STATUS => self.interrupt,
+      reg::STATUS => self.status,
+      reg::CONFIG_GENERATION => 0,
+      _ => 0,
+    } as u64)
+  }
+
+  fn store(
+    &mut self,
+    addr: u64,
+    value: u64,
+    size: u8,
+  ) -> Result<(), Exception> {
+    if addr >= reg::CONFIG {
+      self.device.store_config(addr - reg::CONFIG, value, size);
+      return Ok(());
+    }
+    if size != WORD || !addr.is_multiple_of(4) {
+      return Err(Exception::StoreAMOAccessFault(addr));
+    }
+
+    let value = value as u32;
+    let low = |reg: u64| (reg & !0xffff_ffff) | value as u64;
+    let high = |reg: u64| (reg & 0xffff_ffff) | (value as u64) << 32;
+    match addr {
+      reg::DEVICE_FEATURES_SEL => self.device_features_sel = value,
+      reg::DRIVER_FEATURES => match self.driver_features_sel {
+        0 => self.driver_features = low(self.driver_features),
+        1 => self.driver_features = high(self.driver_features),
+        _ => {}
+      },
+      reg::DRIVER_FEATURES_SEL => self.driver_features_sel = value,
+      reg::QUEUE_SEL => self.queue_sel = value,
+      reg::QUEUE_NUM => {
+        if let Some(queue) = self.queue() {
+          queue.num = (value as u16).min(QUEUE_MAX);
+        }
+      }
+      reg::QUEUE_READY => {
+        if let Some(queue) = self.queue() {
+          queue.ready = value & 1 == 1;
+        }
+      }
+      reg::QUEUE_NOTIFY => {
+        if let Some(queue) = self.queues.get_mut(value as usize) {
+          queue.notified = true;
+        }
+      }
+      reg::INTERRUPT_ACK => self.interrupt &= !value,
+      reg::STATUS => match value {
+        0 => self.reset_transport(),
+        _ => self.status = value,
+      },
+      reg::QUEUE_DESC_LOW..=reg::QUEUE_DEVICE_HIGH => {
+        let Some(queue) = self.queue() else { return Ok(()) };
+        match addr {
+          reg::QUEUE_DESC_LOW => queue.desc = low(queue.desc),
+          reg::QUEUE_DESC_HIGH => queue.desc = high(queue.desc),
+          reg::QUEUE_DRIVER_LOW => queue.avail = low(queue.avail),
+          reg::QUEUE_DRIVER_HIGH => queue.avail = high(queue.avail),
+          reg::QUEUE_DEVICE_LOW => queue.used = low(queue.used),
+          reg::QUEUE_DEVICE_HIGH => queue.used = high(queue.used),
+          _ => {}
+        }
+      }
+      _ => {}
+    }
+    Ok(())
+  }
+
+  fn tick(&mut self, dram: &mut Dram) {
+    // the device only runs once the driver is done setting it up
+    const DRIVER_OK: u32 = 4;
+    if self.status & DRIVER_OK == 0 {
+      return;
+    }
+
+    for (index, queue) in self.queues.iter_mut().enumerate() {
+      if std::mem::take(&mut queue.notified) {
+        self.device.process(index, queue, dram);
+      }
+    }
+    self.device.poll(&mut self.queues, dram);
+
+    for queue in &mut self.queues {
+      if std::mem::take(&mut queue.used_buffers) {
+        self.interrupt |= int::USED_BUFFER;
+      }
+    }
+  }
+
+  fn reset(&mut self) {
+    self.reset_transport();
+  }
+
+  fn irq(&self) -> bool {
+    self.interrupt != 0
+  }
+
+  fn fdt(&self, fdt: &mut Fdt, mapping: &Mapping) {
+    fdt.begin_node(&format!("virtio_mmio@{:x}", mapping.base));
+    fdt.prop_str("compatible", "virtio,mmio");
+    fdt.prop_reg(&[(mapping.base, mapping.size)]);
+    if let Some(irq) = mapping.irq {
+      fdt.prop_interrupts(irq);
+    }
+    fdt.end_node();
+  }
+}
+
+// Guest memory at the physical address `addr`.
+fn guest(mem: &[u8], addr: u64, len: usize) -> Option<&[u8]> {
+  let start = addr.checked_sub(dram::ADDR)? as usize;
+  mem.get(start..start.checked_add(len)?)
+}
+
+fn guest_mut(mem: &mut [u8], addr: u64, len: usize) -> Option<&mut [u8]> {
+  let start = addr.checked_sub(dram::ADDR)? as usize;
+  mem.get_mut(start..start.checked_add(len)?)
+}
+
+// The rings are at addresses the driver programs, a field past the end of
+// the address space is out of the guest memory like any other.
+fn read16(mem: &[u8], base: u64, offset: u64) -> Option<u16> {
+  let bytes = guest(mem, base.checked_add(offset)?, 2)?;
+  Some(u16::from_le_bytes(bytes.try_into().ok()?))
+}
+
+fn read32(mem: &[u8], base: u64, offset: u64) -> Option<u32> {
+  let bytes = guest(mem, base.checked_add(offset)?, 4)?;
+  Some(u32::from_le_bytes(bytes.try_into().ok()?))
+}
+
+fn read64(mem: &[u8], base: u64, offset: u64) -> Option<u64> {
+  let bytes = guest(mem, base.checked_add(offset)?, 8)?;
+  Some(u64::from_le_bytes(bytes.try_into().ok()?))
+}
+
+fn write(mem: &mut [u8], base: u64, offset: u64, data: &[u8]) {
+  let addr = base.checked_add(offset);
+  if let Some(mem) = addr.and_then(|addr| guest_mut(mem, addr, data.len())) {
+    mem.copy_from_slice(data);
+  }
+}
@@ -0,0 +1,203 @@
+use {
+  crate::{
+    dev::virtio::{Queue, Virtio},
+    Dram,
+  },
+  std::{
+    collections::VecDeque,
+    fmt::Debug,
+    fs::File,
+    io::{self, Write},
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+  },
+};
+
+/// Feature bits of the network device.
+pub mod feature {
+  pub const MAC: u64 = 1 << 5;
+  pub const STATUS: u64 = 1 << 16;
+}
+
+/// Locally administered MAC address given to the guest by default.
+pub const MAC: [u8; 6] = [0x52, 0x54, 0x00, 0x12, 0x34, 0x56];
+
+const RX: usize = 0;
+const TX: usize = 1;
+
+// `virtio_net_hdr` with the `num_buffers` field of VIRTIO_F_VERSION_1
+const HDR_LEN: usize = 12;
+const LINK_UP: u16 = 1;
+
+/// Host side of the network link, exchanging ethernet frames.
+pub trait Backend: Debug {
+  /// Take a frame sent by the guest.
+  fn send(&mut self, frame: &[u8]);
+
+  /// Next frame for the guest, without blocking.
+  fn recv(&mut self) -> Option<Vec<u8>>;
+}
+
+/// Link that returns every frame to the guest.
+#[derive(Debug)]
+pub struct Loopback {
+  frames: VecDeque<Vec<u8>>,
+}
+
+impl Loopback {
+  pub fn new() -> Self {
+    Self { frames: VecDeque::new() }
+  }
+}
+
+impl Default for Loopback {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl Backend for Loopback {
+  fn send(&mut self, frame: &[u8]) {
+    self.frames.push_back(frame.to_vec());
+  }
+
+  fn recv(&mut self) -> Option<Vec<u8>> {
+    self.frames.pop_front()
+  }
+}
+
+/// Capture of the frames in the pcap format, readable by Wireshark.
+#[derive(Debug)]
+pub struct Pcap {
+  file: File,
+}
+
+impl Pcap {
+  const MAGIC: u32 = 0xa1b2_c3d4;
+  const SNAPLEN: u32 = 65535;
+  const LINKTYPE_ETHERNET: u32 = 1;
+
+  pub fn create(path: impl AsRef<Path>) -> io::Result<Self> {
+    let mut file = File::create(path)?;
+    let mut header = Vec::with_capacity(24);
+    header.extend(Self::MAGIC.to_le_bytes());
+    header.extend(2u16.to_le_bytes());
+    header.extend(4u16.to_le_bytes());
+    header.extend([0; 8]); // UTC offset and timestamp accuracy
+    header.extend(Self::SNAPLEN.to_le_bytes());
+    header.extend(Self::LINKTYPE_ETHERNET.to_le_bytes());
+    file.write_all(&header)?;
+    Ok(Self { file })
+  }
+
+  /// Append a frame, stamped with the host time.
+  pub fn record(&mut self, frame: &[u8]) -> io::Result<()> {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    let len = frame.len().min(Self::SNAPLEN as usize);
+
+    let mut record = Vec::with_capacity(16 + len);
+    record.extend((now.as_secs() as u32).to_le_bytes());
+    record.extend(now.subsec_micros().to_le_bytes());
+    record.extend((len as u32).to_le_bytes());
+    record.extend((frame.len() as u32).to_le_bytes());
+    record.extend(&frame[..len]);
+    // a single write, so the capture stays readable if the emulator dies
+    self.file.write_all(&record)
+  }
+}
+
+/// Virtio network device, the link of the guest to a [`Backend`].
+#[derive(Debug)]
+pub struct Net {
+  pub mac: [u8; 6],
+  pub backend: Box<dyn Backend>,
+  pcap: Option<Pcap>,
+  // frame taken from the backend, waiting for a receive buffer
+  rx: Option<Vec<u8>>,
+}
+
+impl Net {
+  pub fn new(backend: impl Backend + 'static) -> Self {
+    Self { mac: MAC, backend: Box::new(backend), pcap: None, rx: None }
+  }
+
+  /// Record the frames in both directions to `pcap`.
+  pub fn with_pcap(mut self, pcap: Pcap) -> Self {
+    self.pcap = Some(pcap);
+    self
+  }
+
+  fn record(&mut self, frame: &[u8]) {
+    if let Some(pcap) = &mut self.pcap {
+      // a capture that can't be written is not worth stopping the guest
+      if pcap.record(frame).is_err() {
+        self.pcap = None;
+      }
+    }
+  }
+
+  fn transmit(&mut self, queue: &mut Queue, dram: &mut Dram) {
+    while let Some(chain) = queue.pop(dram) {
+      let packet = chain.read(dram);
+      if let Some(frame) = packet.get(HDR_LEN..) {
+        self.record(frame);
+        self.backend.send(frame);
+      }
+      queue.push(dram, chain.head, 0);
+    }
+  }
+
+  // Frames from the backend are only taken as the driver provides buffers,
+  // so that a slow guest throttles the backend instead of losing frames.
+  fn receive(&mut self, queue: &mut Queue, dram: &mut Dram) {
+    while let Some(frame) = self.rx.take().or_else(|| self.backend.recv()) {
+      let Some(chain) = queue.pop(dram) else {
+        self.rx = Some(frame);
+        break;
+      };
+
+      let mut hdr = [0; HDR_LEN];
+      hdr[10..12].copy_from_slice(&1u16.to_le_bytes()); // num_buffers
+      let written =
+        chain.write(dram, 0, &hdr) + chain.write(dram, HDR_LEN, &frame);
+      self.record(&frame);
+      queue.push(dram, chain.head, written as u32);
+    }
+  }
+}
+
+impl Virtio for Net {
+  fn id(&self) -> u32 {
+    1
+  }
+
+  fn features(&self) -> u64 {
+    feature::MAC | feature::STATUS
+  }
+
+  fn queues(&self) -> usize {
+    2
+  }
+
+  fn config(&self) -> Vec<u8> {
+    let mut config = self.mac.to_vec();
+    config.extend(LINK_UP.to_le_bytes());
+    config
+  }
+
+  fn process(&mut self, index: usize, queue: &mut Queue, dram: &mut Dram) {
+    match index {
+      RX => self.receive(queue, dram),
+      TX => self.transmit(queue, dram),
+      _ => {}
+    }
+  }
+
+  fn poll(&mut self, queues: &mut [Queue], dram: &mut Dram) {
+    self.receive(&mut queues[RX], dram);
+  }
+
+  fn reset(&mut self) {
+    self.rx = None;
+  }
+}
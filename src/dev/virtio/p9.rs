@@ -0,0 +1,788 @@
+use {
+  crate::{
+    dev::virtio::{Queue, Virtio},
+    Dram,
+  },
+  std::{
+    collections::HashMap,
+    ffi::{CStr, CString, OsStr},
+    fs::{File, OpenOptions},
+    io,
+    os::{
+      fd::{AsRawFd, FromRawFd},
+      unix::{
+        ffi::OsStrExt,
+        fs::{FileExt, OpenOptionsExt},
+      },
+    },
+    path::{Path, PathBuf},
+  },
+};
+
+/// Feature bits of the 9P transport.
+pub mod feature {
+  /// The configuration space holds the tag the guest mounts the export by.
+  pub const MOUNT_TAG: u64 = 1 << 0;
+}
+
+/// Types of the 9P2000.L requests, the reply to a request is the next type.
+pub mod msg {
+  pub const RLERROR: u8 = 7;
+  pub const TSTATFS: u8 = 8;
+  pub const TLOPEN: u8 = 12;
+  pub const TLCREATE: u8 = 14;
+  pub const TSYMLINK: u8 = 16;
+  pub const TRENAME: u8 = 20;
+  pub const TREADLINK: u8 = 22;
+  pub const TGETATTR: u8 = 24;
+  pub const TSETATTR: u8 = 26;
+  pub const TREADDIR: u8 = 40;
+  pub const TFSYNC: u8 = 50;
+  pub const TLOCK: u8 = 52;
+  pub const TGETLOCK: u8 = 54;
+  pub const TLINK: u8 = 70;
+  pub const TMKDIR: u8 = 72;
+  pub const TRENAMEAT: u8 = 74;
+  pub const TUNLINKAT: u8 = 76;
+  pub const TVERSION: u8 = 100;
+  pub const TATTACH: u8 = 104;
+  pub const TFLUSH: u8 = 108;
+  pub const TWALK: u8 = 110;
+  pub const TREAD: u8 = 116;
+  pub const TWRITE: u8 = 118;
+  pub const TCLUNK: u8 = 120;
+  pub const TREMOVE: u8 = 122;
+}
+
+/// Bits of `valid` in `Tsetattr`.
+mod setattr {
+  pub const MODE: u32 = 1 << 0;
+  pub const UID: u32 = 1 << 1;
+  pub const GID: u32 = 1 << 2;
+  pub const SIZE: u32 = 1 << 3;
+  pub const ATIME: u32 = 1 << 4;
+  pub const MTIME: u32 = 1 << 5;
+  pub const ATIME_SET: u32 = 1 << 7;
+  pub const MTIME_SET: u32 = 1 << 8;
+}
+
+pub const VERSION: &str = "9P2000.L";
+/// Largest message, the driver may ask for less.
+pub const MSIZE: u32 = 0x2_0000;
+
+// fields of `Rgetattr` filled in, the basic ones
+const GETATTR_BASIC: u64 = 0x7ff;
+// header of the replies to reads, before the data
+const IOHDR: u32 = 11;
+// magic of the file system in `Rstatfs`
+const V9FS_MAGIC: u32 = 0x0102_1997;
+const AT_REMOVEDIR: u32 = 0x200;
+const F_UNLCK: u8 = 2;
+
+// Errors are the codes of the host, the ones of Linux the protocol carries,
+// which is why the device is only there on Linux hosts.
+type Result<T> = std::result::Result<T, i32>;
+
+fn errno(err: io::Error) -> i32 {
+  err.raw_os_error().unwrap_or(libc::EIO)
+}
+
+// Return value of a call to the host, failing with its errno.
+fn check(ret: libc::c_int) -> Result<libc::c_int> {
+  if ret < 0 {
+    return Err(errno(io::Error::last_os_error()));
+  }
+  Ok(ret)
+}
+
+fn cstring(name: &OsStr) -> Result<CString> {
+  CString::new(name.as_bytes()).map_err(|_| libc::EINVAL)
+}
+
+// Open `name` in the directory `dir`, never following a symlink.
+fn open_at(dir: &File, name: &CStr, flags: i32, mode: u32) -> Result<File> {
+  let flags = flags | libc::O_NOFOLLOW | libc::O_CLOEXEC;
+  let fd = check(unsafe {
+    libc::openat(dir.as_raw_fd(), name.as_ptr(), flags, mode as libc::c_uint)
+  })?;
+  Ok(unsafe { File::from_raw_fd(fd) })
+}
+
+fn stat_at(dir: &File, name: &CStr) -> Result<Stat> {
+  let mut stat = unsafe { std::mem::zeroed() };
+  check(unsafe {
+    libc::fstatat(
+      dir.as_raw_fd(),
+      name.as_ptr(),
+      &mut stat,
+      libc::AT_SYMLINK_NOFOLLOW,
+    )
+  })?;
+  Ok(Stat(stat))
+}
+
+/// Attributes of a file of the export, of the symlink itself for a symlink.
+#[derive(Clone, Copy)]
+struct Stat(libc::stat);
+
+impl Stat {
+  fn kind(&self) -> libc::mode_t {
+    self.0.st_mode & libc::S_IFMT
+  }
+
+  fn is_dir(&self) -> bool {
+    self.kind() == libc::S_IFDIR
+  }
+
+  fn is_symlink(&self) -> bool {
+    self.kind() == libc::S_IFLNK
+  }
+
+  fn is_file(&self) -> bool {
+    self.kind() == libc::S_IFREG
+  }
+}
+
+/// File of the export the driver refers to by a number.
+#[derive(Debug)]
+struct Fid {
+  // relative to the root, only the last component may be a symlink
+  path: PathBuf,
+  file: Option<File>,
+  // entries of the directory as the first read of it saw them
+  entries: Vec<Vec<u8>>,
+}
+
+impl Fid {
+  fn new(path: PathBuf) -> Self {
+    Self { path, file: None, entries: Vec::new() }
+  }
+}
+
+/// Virtio 9P device exporting a directory of the host with 9P2000.L, which
+/// Linux mounts with `mount -t 9p -o trans=virtio TAG DIR`. The guest never
+/// reaches out of the directory: every access is resolved from the root one
+/// component at a time and never goes through symlinks, which the guest
+/// resolves itself, even when a directory a fid is in has been replaced.
+#[derive(Debug)]
+pub struct P9 {
+  root: File,
+  tag: String,
+  readonly: bool,
+  msize: u32,
+  fids: HashMap<u32, Fid>,
+}
+
+impl P9 {
+  /// Export the directory `root` under `tag`, a `readonly` export is never
+  /// written to.
+  pub fn open(
+    root: impl AsRef<Path>,
+    tag: &str,
+    readonly: bool,
+  ) -> io::Result<Self> {
+    let root = OpenOptions::new()
+      .read(true)
+      .custom_flags(libc::O_DIRECTORY | libc::O_CLOEXEC)
+      .open(root)?;
+    let tag = tag.to_owned();
+    Ok(Self { root, tag, readonly, msize: MSIZE, fids: HashMap::new() })
+  }
+
+  // Directory holding `path` and the name of it in there, the root being
+  // `.` in itself.
+  fn at(&self, path: &Path) -> Result<(File, CString)> {
+    let mut dir = self.root.try_clone().map_err(errno)?;
+    let Some(name) = path.file_name() else {
+      return Ok((dir, c".".into()));
+    };
+    for part in path.parent().into_iter().flat_map(Path::components) {
+      let part = cstring(part.as_os_str())?;
+      dir = open_at(&dir, &part, libc::O_RDONLY | libc::O_DIRECTORY, 0)?;
+    }
+    Ok((dir, cstring(name)?))
+  }
+
+  fn fid(&mut self, fid: u32) -> Result<&mut Fid> {
+    self.fids.get_mut(&fid).ok_or(libc::EBADF)
+  }
+
+  fn stat(&self, path: &Path) -> Result<Stat> {
+    let (dir, name) = self.at(path)?;
+    stat_at(&dir, &name)
+  }
+
+  fn path(&self, fid: u32) -> Result<PathBuf> {
+    Ok(self.fids.get(&fid).ok_or(libc::EBADF)?.path.clone())
+  }
+
+  // Path of the directory `fid`, which isn't a symlink.
+  fn dir(&self, fid: u32) -> Result<PathBuf> {
+    let path = self.path(fid)?;
+    if !self.stat(&path)?.is_dir() {
+      return Err(libc::ENOTDIR);
+    }
+    Ok(path)
+  }
+
+  fn writable(&self) -> Result<()> {
+    if self.readonly {
+      return Err(libc::EROFS);
+    }
+    Ok(())
+  }
+
+  /// Reply to the request `request`.
+  pub fn serve(&mut self, request: &[u8]) -> Vec<u8> {
+    let mut r = Reader(request);
+    let header =
+      (|| Some((r.u32().ok()? as usize, r.u8().ok()?, r.u16().ok()?)))();
+    let Some((size, kind, tag)) = header else { return Vec::new() };
+    r = Reader(request.get(7..size).unwrap_or_default());
+
+    let (kind, body) = match self.handle(kind, &mut r) {
+      Ok(body) => (kind + 1, body),
+      Err(code) => (msg::RLERROR, (code as u32).to_le_bytes().to_vec()),
+    };
+    let mut reply = Vec::with_capacity(7 + body.len());
+    reply.extend((7 + body.len() as u32).to_le_bytes());
+    reply.push(kind);
+    reply.extend(tag.to_le_bytes());
+    reply.extend(body);
+    reply
+  }
+
+  fn handle(&mut self, kind: u8, r: &mut Reader) -> Result<Vec<u8>> {
+    let mut reply = Writer(Vec::new());
+    match kind {
+      msg::TVERSION => {
+        let (msize, version) = (r.u32()?, r.bytes()?);
+        self.msize = msize.min(MSIZE);
+        self.fids.clear();
+        let version =
+          if version == VERSION.as_bytes() { VERSION } else { "unknown" };
+        reply.u32(self.msize).bytes(version.as_bytes());
+      }
+      msg::TATTACH => {
+        let fid = r.u32()?;
+        let root = self.stat(Path::new(""))?;
+        self.fids.insert(fid, Fid::new(PathBuf::new()));
+        reply.qid(&root);
+      }
+      msg::TFLUSH => {}
+      msg::TWALK => self.walk(r, &mut reply)?,
+      msg::TCLUNK => {
+        self.fids.remove(&r.u32()?).ok_or(libc::EBADF)?;
+      }
+      msg::TLOPEN => {
+        let (fid, flags) = (r.u32()?, r.u32()?);
+        let path = self.path(fid)?;
+        let stat = self.stat(&path)?;
+        let write = flags & libc::O_ACCMODE as u32 != libc::O_RDONLY as u32;
+        let truncate = flags & libc::O_TRUNC as u32 != 0;
+        if write || truncate {
+          self.writable()?;
+        }
+        let file = if stat.is_symlink() {
+          return Err(libc::ELOOP);
+        } else if stat.is_dir() {
+          if write {
+            return Err(libc::EISDIR);
+          }
+          None
+        } else {
+          let flags = flags as i32 & (libc::O_ACCMODE | libc::O_TRUNC);
+          let (dir, name) = self.at(&path)?;
+          Some(open_at(&dir, &name, flags, 0)?)
+        };
+        let fid = self.fid(fid)?;
+        (fid.file, fid.entries) = (file, Vec::new());
+        reply.qid(&stat).u32(0);
+      }
+      msg::TLCREATE => {
+        let (fid, name, flags, mode) =
+          (r.u32()?, r.name()?, r.u32()?, r.u32()?);
+        self.writable()?;
+        let path = self.dir(fid)?.join(name);
+        let read = flags & libc::O_ACCMODE as u32 != libc::O_WRONLY as u32;
+        let access = if read { libc::O_RDWR } else { libc::O_WRONLY };
+        let (dir, name) = self.at(&path)?;
+        let flags = access | libc::O_CREAT | libc::O_EXCL;
+        let file = open_at(&dir, &name, flags, mode & 0o7777)?;
+        let stat = stat_at(&dir, &name)?;
+        *self.fid(fid)? = Fid { file: Some(file), ..Fid::new(path) };
+        reply.qid(&stat).u32(0);
+      }
+      msg::TREAD => {
+        let (fid, offset, count) = (r.u32()?, r.u64()?, r.u32()?);
+        let count = count.min(self.msize.saturating_sub(IOHDR));
+        let file = self.fid(fid)?.file.as_ref().ok_or(libc::EBADF)?;
+        let mut data = vec![0; count as usize];
+        let len = file.read_at(&mut data, offset).map_err(errno)?;
+        reply.u32(len as u32).raw(&data[..len]);
+      }
+      msg::TWRITE => {
+        let (fid, offset, count) = (r.u32()?, r.u64()?, r.u32()?);
+        let data = r.take(count as usize)?;
+        self.writable()?;
+        let file = self.fid(fid)?.file.as_ref().ok_or(libc::EBADF)?;
+        let len = file.write_at(data, offset).map_err(errno)?;
+        reply.u32(len as u32);
+      }
+      msg::TREMOVE => {
+        // the fid goes away even if the file stays
+        let fid = self.fids.remove(&r.u32()?).ok_or(libc::EBADF)?;
+        self.writable()?;
+        self.unlink(&fid.path)?;
+      }
+      msg::TGETATTR => {
+        let fid = r.u32()?;
+        let path = self.path(fid)?;
+        let stat = self.stat(&path)?;
+        let st = &stat.0;
+        reply.u64(GETATTR_BASIC).qid(&stat);
+        reply.u32(st.st_mode).u32(st.st_uid).u32(st.st_gid);
+        reply.u64(st.st_nlink).u64(st.st_rdev);
+        reply.u64(st.st_size as u64).u64(st.st_blksize as u64);
+        reply.u64(st.st_blocks as u64);
+        reply.u64(st.st_atime as u64).u64(st.st_atime_nsec as u64);
+        reply.u64(st.st_mtime as u64).u64(st.st_mtime_nsec as u64);
+        reply.u64(st.st_ctime as u64).u64(st.st_ctime_nsec as u64);
+        // birth time, generation and data version
+        reply.u64(0).u64(0).u64(0).u64(0);
+      }
+      msg::TSETATTR => self.setattr(r)?,
+      msg::TREADDIR => {
+        let (fid, offset, count) = (r.u32()?, r.u64()?, r.u32()?);
+        let path = self.dir(fid)?;
+        if offset == 0 || self.fid(fid)?.entries.is_empty() {
+          let entries = self.entries(&path)?;
+          self.fid(fid)?.entries = entries;
+        }
+        let count = count.min(self.msize.saturating_sub(IOHDR)) as usize;
+        let mut data = Vec::new();
+        let entries = &self.fid(fid)?.entries;
+        for entry in entries.iter().skip(offset as usize) {
+          if data.len() + entry.len() > count {
+            break;
+          }
+          data.extend(entry);
+        }
+        reply.u32(data.len() as u32).raw(&data);
+      }
+      msg::TSTATFS => {
+        let fid = r.u32()?;
+        // the directory of the file is on its file system
+        let (dir, _) = self.at(&self.path(fid)?)?;
+        let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+        check(unsafe { libc::fstatvfs(dir.as_raw_fd(), &mut stat) })?;
+        reply.u32(V9FS_MAGIC).u32(stat.f_bsize as u32);
+        reply.u64(stat.f_blocks as u64).u64(stat.f_bfree as u64);
+        reply.u64(stat.f_bavail as u64).u64(stat.f_files as u64);
+        reply.u64(stat.f_ffree as u64).u64(stat.f_fsid as u64);
+        reply.u32(stat.f_namemax as u32);
+      }
+      msg::TFSYNC => {
+        let (fid, datasync) = (r.u32()?, r.u32()?);
+        if let Some(file) = &self.fid(fid)?.file {
+          let sync =
+            if datasync != 0 { file.sync_data() } else { file.sync_all() };
+          sync.map_err(errno)?;
+        }
+      }
+      // a single client, the locks are always granted
+      msg::TLOCK => {
+        reply.u8(0);
+      }
+      msg::TGETLOCK => {
+        let (_fid, _kind) = (r.u32()?, r.u8()?);
+        let (start, length, proc_id) = (r.u64()?, r.u64()?, r.u32()?);
+        let client = r.bytes()?;
+        reply.u8(F_UNLCK).u64(start).u64(length).u32(proc_id).bytes(client);
+      }
+      msg::TMKDIR => {
+        let (fid, name, mode) = (r.u32()?, r.name()?, r.u32()?);
+        self.writable()?;
+        let (dir, name) = self.at(&self.dir(fid)?.join(name))?;
+        let mode = (mode & 0o7777) as libc::mode_t;
+        check(unsafe { libc::mkdirat(dir.as_raw_fd(), name.as_ptr(), mode) })?;
+        reply.qid(&stat_at(&dir, &name)?);
+      }
+      msg::TSYMLINK => {
+        let (fid, name, target) = (r.u32()?, r.name()?, r.bytes()?);
+        self.writable()?;
+        let (dir, name) = self.at(&self.dir(fid)?.join(name))?;
+        let target = CString::new(target).map_err(|_| libc::EINVAL)?;
+        check(unsafe {
+          libc::symlinkat(target.as_ptr(), dir.as_raw_fd(), name.as_ptr())
+        })?;
+        reply.qid(&stat_at(&dir, &name)?);
+      }
+      msg::TREADLINK => {
+        let fid = r.u32()?;
+        let (dir, name) = self.at(&self.path(fid)?)?;
+        let mut target = vec![0u8; libc::PATH_MAX as usize];
+        let len = unsafe {
+          libc::readlinkat(
+            dir.as_raw_fd(),
+            name.as_ptr(),
+            target.as_mut_ptr().cast(),
+            target.len(),
+          )
+        };
+        if len < 0 {
+          return Err(errno(io::Error::last_os_error()));
+        }
+        reply.bytes(&target[..len as usize]);
+      }
+      msg::TLINK => {
+        let (dfid, fid, name) = (r.u32()?, r.u32()?, r.name()?);
+        self.writable()?;
+        let (to, to_name) = self.at(&self.dir(dfid)?.join(name))?;
+        let (from, from_name) = self.at(&self.path(fid)?)?;
+        check(unsafe {
+          libc::linkat(
+            from.as_raw_fd(),
+            from_name.as_ptr(),
+            to.as_raw_fd(),
+            to_name.as_ptr(),
+            0,
+          )
+        })?;
+      }
+      msg::TUNLINKAT => {
+        let (fid, name, flags) = (r.u32()?, r.name()?, r.u32()?);
+        self.writable()?;
+        let path = self.dir(fid)?.join(name);
+        let dir = self.stat(&path)?.is_dir();
+        if dir != (flags & AT_REMOVEDIR != 0) {
+          return Err(if dir { libc::EISDIR } else { libc::ENOTDIR });
+        }
+        self.unlink(&path)?;
+      }
+      msg::TRENAME => {
+        let (fid, dfid, name) = (r.u32()?, r.u32()?, r.name()?);
+        self.writable()?;
+        let from = self.path(fid)?;
+        let to = self.dir(dfid)?.join(name);
+        self.rename(&from, &to)?;
+      }
+      msg::TRENAMEAT => {
+        let (fid, from) = (r.u32()?, r.name()?);
+        let (dfid, to) = (r.u32()?, r.name()?);
+        self.writable()?;
+        let from = self.dir(fid)?.join(from);
+        let to = self.dir(dfid)?.join(to);
+        self.rename(&from, &to)?;
+      }
+      _ => return Err(libc::EOPNOTSUPP),
+    }
+    Ok(reply.0)
+  }
+
+  // Walk the names from a fid to a new one, stopping at the first name that
+  // is missing.
+  fn walk(&mut self, r: &mut Reader, reply: &mut Writer) -> Result<()> {
+    let (fid, newfid, names) = (r.u32()?, r.u32()?, r.u16()?);
+    let mut path = self.path(fid)?;
+    if newfid != fid && self.fids.contains_key(&newfid) {
+      return Err(libc::EBADF);
+    }
+
+    let mut qids = Vec::new();
+    for i in 0..names {
+      let name = r.bytes()?;
+      let step = || {
+        // only directories are walked through, not the symlinks to them
+        if !self.stat(&path)?.is_dir() {
+          return Err(libc::ENOTDIR);
+        }
+        let next = match name {
+          b".." => path.parent().unwrap_or(&path).to_owned(),
+          name => path.join(name_of(name)?),
+        };
+        Ok((self.stat(&next)?, next))
+      };
+      match step() {
+        Ok((stat, next)) => {
+          qids.push(stat);
+          path = next;
+        }
+        Err(code) if i == 0 => return Err(code),
+        Err(_) => break,
+      }
+    }
+
+    if qids.len() == names as usize {
+      self.fids.insert(newfid, Fid::new(path));
+    }
+    reply.u16(qids.len() as u16);
+    for stat in &qids {
+      reply.qid(stat);
+    }
+    Ok(())
+  }
+
+  fn setattr(&mut self, r: &mut Reader) -> Result<()> {
+    let (fid, valid, mode) = (r.u32()?, r.u32()?, r.u32()?);
+    let (uid, gid, size) = (r.u32()?, r.u32()?, r.u64()?);
+    let atime = (r.u64()?, r.u64()?);
+    let mtime = (r.u64()?, r.u64()?);
+    self.writable()?;
+    let (dir, name) = self.at(&self.path(fid)?)?;
+    if stat_at(&dir, &name)?.is_symlink() {
+      return Err(libc::ELOOP);
+    }
+    let (fd, path) = (dir.as_raw_fd(), name.as_ptr());
+
+    if valid & setattr::MODE != 0 {
+      let mode = (mode & 0o7777) as libc::mode_t;
+      check(unsafe { libc::fchmodat(fd, path, mode, 0) })?;
+    }
+    if valid & (setattr::UID | setattr::GID) != 0 {
+      // ids of -1 are left as they are
+      let uid = if valid & setattr::UID != 0 { uid } else { !0 };
+      let gid = if valid & setattr::GID != 0 { gid } else { !0 };
+      let nofollow = libc::AT_SYMLINK_NOFOLLOW;
+      check(unsafe { libc::fchownat(fd, path, uid, gid, nofollow) })?;
+    }
+    if valid & setattr::SIZE != 0 {
+      let file = open_at(&dir, &name, libc::O_WRONLY, 0)?;
+      file.set_len(size).map_err(errno)?;
+    }
+    if valid & (setattr::ATIME | setattr::MTIME) != 0 {
+      // the given time, the time of the host, or the time as it is
+      let time = |bit, set, (secs, nanos): (u64, u64)| {
+        let mut time: libc::timespec = unsafe { std::mem::zeroed() };
+        time.tv_nsec = match valid {
+          _ if valid & bit == 0 => libc::UTIME_OMIT,
+          _ if valid & set == 0 => libc::UTIME_NOW,
+          _ => {
+            time.tv_sec = secs as libc::time_t;
+            nanos as _
+          }
+        };
+        time
+      };
+      let times = [
+        time(setattr::ATIME, setattr::ATIME_SET, atime),
+        time(setattr::MTIME, setattr::MTIME_SET, mtime),
+      ];
+      let nofollow = libc::AT_SYMLINK_NOFOLLOW;
+      check(unsafe { libc::utimensat(fd, path, times.as_ptr(), nofollow) })?;
+    }
+    Ok(())
+  }
+
+  // Entries of the directory `path` as laid out in `Rreaddir`, each with
+  // the offset of the next one.
+  fn entries(&self, path: &Path) -> Result<Vec<Vec<u8>>> {
+    let parent = path.parent().unwrap_or(path);
+    let mut names = vec![
+      (CString::from(c"."), self.stat(path)?),
+      (CString::from(c".."), self.stat(parent)?),
+    ];
+
+    let (dir, name) = self.at(path)?;
+    let dir = open_at(&dir, &name, libc::O_RDONLY | libc::O_DIRECTORY, 0)?;
+    let stream = unsafe { libc::fdopendir(dir.as_raw_fd()) };
+    if stream.is_null() {
+      return Err(errno(io::Error::last_os_error()));
+    }
+    let mut children = Vec::new();
+    loop {
+      let entry = unsafe { libc::readdir(stream) };
+      if entry.is_null() {
+        break;
+      }
+      let name = unsafe { CStr::from_ptr((*entry).d_name.as_ptr()) };
+      if name == c"." || name == c".." {
+        continue;
+      }
+      // entries removed in the meantime are left out
+      if let Ok(stat) = stat_at(&dir, name) {
+        children.push((name.to_owned(), stat));
+      }
+    }
+    // the stream owns the descriptor from now on
+    unsafe { libc::closedir(stream) };
+    std::mem::forget(dir);
+    children.sort_by(|(a, _), (b, _)| a.cmp(b));
+    names.extend(children);
+
+    let entries = names.iter().enumerate().map(|(i, (name, stat))| {
+      let kind = match stat {
+        stat if stat.is_dir() => libc::DT_DIR,
+        stat if stat.is_symlink() => libc::DT_LNK,
+        stat if stat.is_file() => libc::DT_REG,
+        _ => libc::DT_UNKNOWN,
+      };
+      let mut entry = Writer(Vec::new());
+      entry.qid(stat).u64(i as u64 + 1).u8(kind).bytes(name.to_bytes());
+      entry.0
+    });
+    Ok(entries.collect())
+  }
+
+  fn unlink(&self, path: &Path) -> Result<()> {
+    if path.as_os_str().is_empty() {
+      return Err(libc::EBUSY);
+    }
+    let (dir, name) = self.at(path)?;
+    let flags = match stat_at(&dir, &name)?.is_dir() {
+      true => libc::AT_REMOVEDIR,
+      false => 0,
+    };
+    check(unsafe { libc::unlinkat(dir.as_raw_fd(), name.as_ptr(), flags) })?;
+    Ok(())
+  }
+
+  // Rename `from` to `to`, along with the fids below it.
+  fn rename(&mut self, from: &Path, to: &Path) -> Result<()> {
+    if from.as_os_str().is_empty() {
+      return Err(libc::EBUSY);
+    }
+    let (from_dir, from_name) = self.at(from)?;
+    let (to_dir, to_name) = self.at(to)?;
+    check(unsafe {
+      libc::renameat(
+        from_dir.as_raw_fd(),
+        from_name.as_ptr(),
+        to_dir.as_raw_fd(),
+        to_name.as_ptr(),
+      )
+    })?;
+    for fid in self.fids.values_mut() {
+      // joining an empty rest would leave a trailing slash
+      match fid.path.strip_prefix(from) {
+        Ok(rest) if rest.as_os_str().is_empty() => fid.path = to.to_owned(),
+        Ok(rest) => fid.path = to.join(rest),
+        Err(_) => {}
+      }
+    }
+    Ok(())
+  }
+}
+
+// Name of a single entry of a directory.
+fn name_of(name: &[u8]) -> Result<&OsStr> {
+  let special = name.is_empty() || name == b"." || name == b"..";
+  if special || name.contains(&b'/') || name.contains(&0) {
+    return Err(libc::EINVAL);
+  }
+  Ok(OsStr::from_bytes(name))
+}
+
+// Fields of a request, little-endian.
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+  fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+    if self.0.len() < len {
+      return Err(libc::EPROTO);
+    }
+    let (bytes, rest) = self.0.split_at(len);
+    self.0 = rest;
+    Ok(bytes)
+  }
+
+  fn u8(&mut self) -> Result<u8> {
+    Ok(self.take(1)?[0])
+  }
+
+  fn u16(&mut self) -> Result<u16> {
+    Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+  }
+
+  fn u32(&mut self) -> Result<u32> {
+    Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+  }
+
+  fn u64(&mut self) -> Result<u64> {
+    Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+  }
+
+  // String prefixed by its length.
+  fn bytes(&mut self) -> Result<&'a [u8]> {
+    let len = self.u16()?;
+    self.take(len as usize)
+  }
+
+  fn name(&mut self) -> Result<&'a OsStr> {
+    name_of(self.bytes()?)
+  }
+}
+
+// Fields of a reply, little-endian.
+struct Writer(Vec<u8>);
+
+impl Writer {
+  fn raw(&mut self, bytes: &[u8]) -> &mut Self {
+    self.0.extend(bytes);
+    self
+  }
+
+  fn u8(&mut self, value: u8) -> &mut Self {
+    self.raw(&[value])
+  }
+
+  fn u16(&mut self, value: u16) -> &mut Self {
+    self.raw(&value.to_le_bytes())
+  }
+
+  fn u32(&mut self, value: u32) -> &mut Self {
+    self.raw(&value.to_le_bytes())
+  }
+
+  fn u64(&mut self, value: u64) -> &mut Self {
+    self.raw(&value.to_le_bytes())
+  }
+
+  fn bytes(&mut self, bytes: &[u8]) -> &mut Self {
+    self.u16(bytes.len() as u16).raw(bytes)
+  }
+
+  // Unique identity of a file: its type, version and inode.
+  fn qid(&mut self, stat: &Stat) -> &mut Self {
+    let kind = match stat {
+      stat if stat.is_dir() => 0x80,
+      stat if stat.is_symlink() => 0x02,
+      _ => 0,
+    };
+    self.u8(kind).u32(0).u64(stat.0.st_ino)
+  }
+}
+
+impl Virtio for P9 {
+  fn id(&self) -> u32 {
+    9
+  }
+
+  fn features(&self) -> u64 {
+    feature::MOUNT_TAG
+  }
+
+  fn queues(&self) -> usize {
+    1
+  }
+
+  fn config(&self) -> Vec<u8> {
+    let mut config = (self.tag.len() as u16).to_le_bytes().to_vec();
+    config.extend(self.tag.as_bytes());
+    config
+  }
+
+  fn process(&mut self, _index: usize, queue: &mut Queue, dram: &mut Dram) {
+    while let Some(chain) = queue.pop(dram) {
+      let reply = self.serve(&chain.read(dram));
+      let written = chain.write(dram, 0, &reply);
+      queue.push(dram, chain.head, written as u32);
+    }
+  }
+
+  // the fids belonged to the driver that is gone
+  fn reset(&mut self) {
+    self.msize = MSIZE;
+    self.fids.clear();
+  }
+}
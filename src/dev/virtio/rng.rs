@@ -0,0 +1,55 @@
+use {
+  crate::{
+    dev::virtio::{Queue, Virtio},
+    Dram,
+  },
+  rand::{rngs::StdRng, RngCore, SeedableRng},
+};
+
+// bytes given per request at most, the driver asks again for more
+const REQUEST: usize = 4096;
+
+/// Virtio entropy source. A seeded device gives the same bytes on every run,
+/// for deterministic runs of the guest.
+#[derive(Debug)]
+pub struct Rng {
+  rng: StdRng,
+}
+
+impl Rng {
+  /// Entropy from the host, or the stream of `seed`.
+  pub fn new(seed: Option<u64>) -> Self {
+    let rng = match seed {
+      Some(seed) => StdRng::seed_from_u64(seed),
+      None => StdRng::from_entropy(),
+    };
+    Self { rng }
+  }
+}
+
+impl Virtio for Rng {
+  fn id(&self) -> u32 {
+    4
+  }
+
+  fn features(&self) -> u64 {
+    0
+  }
+
+  fn queues(&self) -> usize {
+    1
+  }
+
+  fn config(&self) -> Vec<u8> {
+    Vec::new()
+  }
+
+  fn process(&mut self, _index: usize, queue: &mut Queue, dram: &mut Dram) {
+    while let Some(chain) = queue.pop(dram) {
+      let mut bytes = vec![0; chain.writable().min(REQUEST)];
+      self.rng.fill_bytes(&mut bytes);
+      let written = chain.write(dram, 0, &bytes);
+      queue.push(dram, chain.head, written as u32);
+    }
+  }
+}
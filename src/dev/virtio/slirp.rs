@@ -0,0 +1,667 @@
+//! User-mode network in the manner of slirp: the backend plays the gateway
+//! of a private network, answers ARP, ICMP echo and DHCP itself, and
+//! forwards the TCP and UDP traffic sent to the gateway to the same ports on
+//! the localhost of the host, through ordinary sockets.
+
+use {
+  crate::dev::virtio::net::Backend,
+  std::{
+    collections::{hash_map::Entry, HashMap, VecDeque},
+    io::{self, Read, Write},
+    net::{Ipv4Addr, Shutdown, TcpStream, UdpSocket},
+    thread::{self, JoinHandle},
+  },
+};
+
+pub const GATEWAY: Ipv4Addr = Ipv4Addr::new(10, 0, 2, 2);
+/// Address leased to the guest over DHCP.
+pub const GUEST: Ipv4Addr = Ipv4Addr::new(10, 0, 2, 15);
+pub const NETMASK: Ipv4Addr = Ipv4Addr::new(255, 255, 255, 0);
+pub const GATEWAY_MAC: [u8; 6] = [0x52, 0x55, 0x0a, 0x00, 0x02, 0x02];
+
+const BROADCAST_MAC: [u8; 6] = [0xff; 6];
+
+mod ethertype {
+  pub const IPV4: u16 = 0x0800;
+  pub const ARP: u16 = 0x0806;
+}
+
+mod proto {
+  pub const ICMP: u8 = 1;
+  pub const TCP: u8 = 6;
+  pub const UDP: u8 = 17;
+}
+
+mod tcp {
+  pub const FIN: u8 = 1 << 0;
+  pub const SYN: u8 = 1 << 1;
+  pub const RST: u8 = 1 << 2;
+  pub const PSH: u8 = 1 << 3;
+  pub const ACK: u8 = 1 << 4;
+}
+
+mod dhcp {
+  pub const SERVER_PORT: u16 = 67;
+  pub const CLIENT_PORT: u16 = 68;
+  pub const MAGIC: [u8; 4] = [99, 130, 83, 99];
+
+  pub const DISCOVER: u8 = 1;
+  pub const OFFER: u8 = 2;
+  pub const REQUEST: u8 = 3;
+  pub const ACK: u8 = 5;
+
+  pub const SUBNET_MASK: u8 = 1;
+  pub const ROUTER: u8 = 3;
+  pub const LEASE_TIME: u8 = 51;
+  pub const MESSAGE_TYPE: u8 = 53;
+  pub const SERVER_ID: u8 = 54;
+  pub const END: u8 = 255;
+
+  pub const LEASE: u32 = 24 * 60 * 60;
+}
+
+const MTU: usize = 1500;
+const MSS: usize = MTU - 40;
+// the whole segment can be buffered towards the host, so the window is fixed
+const WINDOW: u16 = 65535;
+// host sockets are polled once in this many calls of `recv`
+const POLL_INTERVAL: u32 = 1024;
+// frames waiting for the guest, more are dropped as by a congested link
+const FRAMES: usize = 256;
+// polls without an acknowledgement before the data in flight is sent again
+const RTO: u32 = 16;
+
+fn be16(data: &[u8], at: usize) -> u16 {
+  u16::from_be_bytes([data[at], data[at + 1]])
+}
+
+fn be32(data: &[u8], at: usize) -> u32 {
+  u32::from_be_bytes(data[at..at + 4].try_into().unwrap())
+}
+
+fn ipv4(data: &[u8], at: usize) -> Ipv4Addr {
+  Ipv4Addr::from(be32(data, at))
+}
+
+// Internet checksum of `data`, continuing the one's complement `sum`.
+fn checksum(mut sum: u32, data: &[u8]) -> u16 {
+  for chunk in data.chunks(2) {
+    let word = match *chunk {
+      [hi, lo] => u16::from_be_bytes([hi, lo]),
+      [hi] => u16::from_be_bytes([hi, 0]),
+      _ => unreachable!(),
+    };
+    sum += word as u32;
+  }
+  while sum >> 16 != 0 {
+    sum = (sum & 0xffff) + (sum >> 16);
+  }
+  !(sum as u16)
+}
+
+// Sum of the pseudo header of TCP and UDP.
+fn pseudo(src: Ipv4Addr, dst: Ipv4Addr, proto: u8, len: usize) -> u32 {
+  let mut header = Vec::with_capacity(12);
+  header.extend(src.octets());
+  header.extend(dst.octets());
+  header.extend([0, proto]);
+  header.extend((len as u16).to_be_bytes());
+  !checksum(0, &header) as u32
+}
+
+/// Guest end of the link: frames are built here, addressed to the guest.
+#[derive(Debug)]
+struct Link {
+  mac: [u8; 6],
+  ip: Ipv4Addr,
+  id: u16,
+  frames: VecDeque<Vec<u8>>,
+}
+
+impl Link {
+  fn ethernet(&mut self, dst: [u8; 6], ethertype: u16, payload: &[u8]) {
+    let mut frame = Vec::with_capacity(14 + payload.len());
+    frame.extend(dst);
+    frame.extend(GATEWAY_MAC);
+    frame.extend(ethertype.to_be_bytes());
+    frame.extend(payload);
+    if self.frames.len() < FRAMES {
+      self.frames.push_back(frame);
+    }
+  }
+
+  fn ipv4(&mut self, src: Ipv4Addr, dst: Ipv4Addr, proto: u8, data: &[u8]) {
+    const DONT_FRAGMENT: u16 = 0x4000;
+    self.id = self.id.wrapping_add(1);
+
+    let mut packet = Vec::with_capacity(20 + data.len());
+    packet.extend([0x45, 0]);
+    packet.extend(((20 + data.len()) as u16).to_be_bytes());
+    packet.extend(self.id.to_be_bytes());
+    packet.extend(DONT_FRAGMENT.to_be_bytes());
+    packet.extend([64, proto, 0, 0]);
+    packet.extend(src.octets());
+    packet.extend(dst.octets());
+    let sum = checksum(0, &packet);
+    packet[10..12].copy_from_slice(&sum.to_be_bytes());
+    packet.extend(data);
+
+    let mac = if dst.is_broadcast() { BROADCAST_MAC } else { self.mac };
+    self.ethernet(mac, ethertype::IPV4, &packet);
+  }
+
+  fn udp(&mut self, src: (Ipv4Addr, u16), dst: (Ipv4Addr, u16), data: &[u8]) {
+    let len = 8 + data.len();
+    let mut datagram = Vec::with_capacity(len);
+    datagram.extend(src.1.to_be_bytes());
+    datagram.extend(dst.1.to_be_bytes());
+    datagram.extend((len as u16).to_be_bytes());
+    datagram.extend([0, 0]);
+    datagram.extend(data);
+
+    let sum = checksum(pseudo(src.0, dst.0, proto::UDP, len), &datagram);
+    // a zero checksum means none for UDP
+    let sum = if sum == 0 { 0xffff } else { sum };
+    datagram[6..8].copy_from_slice(&sum.to_be_bytes());
+    self.ipv4(src.0, dst.0, proto::UDP, &datagram);
+  }
+
+  fn tcp(
+    &mut self,
+    ports: (u16, u16),
+    seq: u32,
+    ack: u32,
+    flags: u8,
+    data: &[u8],
+  ) {
+    // the only option is the MSS, on SYN
+    let options: &[u8] = if flags & tcp::SYN != 0 {
+      &[2, 4, (MSS >> 8) as u8, MSS as u8]
+    } else {
+      &[]
+    };
+    let header = 20 + options.len();
+
+    let mut segment = Vec::with_capacity(header + data.len());
+    segment.extend(ports.0.to_be_bytes());
+    segment.extend(ports.1.to_be_bytes());
+    segment.extend(seq.to_be_bytes());
+    segment.extend(ack.to_be_bytes());
+    segment.extend([((header / 4) << 4) as u8, flags]);
+    segment.extend(WINDOW.to_be_bytes());
+    segment.extend([0; 4]); // checksum and urgent pointer
+    segment.extend(options);
+    segment.extend(data);
+
+    let sum = pseudo(GATEWAY, self.ip, proto::TCP, segment.len());
+    let sum = checksum(sum, &segment);
+    segment[16..18].copy_from_slice(&sum.to_be_bytes());
+    self.ipv4(GATEWAY, self.ip, proto::TCP, &segment);
+  }
+}
+
+/// Connection of the guest to a host port, relayed by the gateway.
+#[derive(Debug)]
+struct Tcp {
+  // the host socket, once the connection made aside is there
+  stream: Option<TcpStream>,
+  connect: Option<JoinHandle<io::Result<TcpStream>>>,
+  // sequence number of the SYN, oldest unacknowledged and next one sent to
+  // the guest
+  iss: u32,
+  snd_una: u32,
+  snd_nxt: u32,
+  // next sequence number expected from the guest
+  rcv_nxt: u32,
+  window: u32,
+  // data sent to the guest and not acknowledged yet, kept to send it again
+  unacked: VecDeque<u8>,
+  // polls since the guest acknowledged something
+  idle: u32,
+  // data of the guest not yet taken by the host
+  pending: Vec<u8>,
+  fin_sent: bool,
+  fin_received: bool,
+  shut: bool,
+}
+
+impl Tcp {
+  fn in_flight(&self) -> u32 {
+    self.snd_nxt.wrapping_sub(self.snd_una)
+  }
+
+  fn closed(&self) -> bool {
+    self.fin_sent && self.fin_received && self.in_flight() == 0
+  }
+
+  // The guest acknowledged the numbers up to `ack`, which is in flight.
+  fn acknowledge(&mut self, ack: u32) {
+    let mut acked = ack.wrapping_sub(self.snd_una);
+    if self.snd_una == self.iss {
+      acked -= 1; // the SYN
+    }
+    let acked = (acked as usize).min(self.unacked.len());
+    self.unacked.drain(..acked);
+    self.snd_una = ack;
+    self.idle = 0;
+  }
+
+  // Send what is in flight again once the guest went quiet for too long, as
+  // its acknowledgements tell nothing of the segments it lost.
+  fn retransmit(&mut self, link: &mut Link, ports: (u16, u16)) {
+    if self.in_flight() == 0 {
+      self.idle = 0;
+      return;
+    }
+    self.idle += 1;
+    if self.idle < RTO {
+      return;
+    }
+    self.idle = 0;
+
+    let mut seq = self.snd_una;
+    if seq == self.iss {
+      link.tcp(ports, seq, self.rcv_nxt, tcp::SYN | tcp::ACK, &[]);
+      seq = seq.wrapping_add(1);
+    }
+    for data in self.unacked.make_contiguous().chunks(MSS) {
+      link.tcp(ports, seq, self.rcv_nxt, tcp::PSH | tcp::ACK, data);
+      seq = seq.wrapping_add(data.len() as u32);
+    }
+    if self.fin_sent {
+      link.tcp(ports, seq, self.rcv_nxt, tcp::FIN | tcp::ACK, &[]);
+    }
+  }
+}
+
+/// User-mode network: the guest reaches the ports of the host's localhost
+/// through the gateway, without any privilege on the host.
+#[derive(Debug)]
+pub struct User {
+  link: Link,
+  // sockets by guest port and gateway port
+  udp: HashMap<(u16, u16), UdpSocket>,
+  tcp: HashMap<(u16, u16), Tcp>,
+  iss: u32,
+  calls: u32,
+}
+
+impl User {
+  pub fn new() -> Self {
+    Self {
+      link: Link {
+        mac: BROADCAST_MAC,
+        ip: GUEST,
+        id: 0,
+        frames: VecDeque::new(),
+      },
+      udp: HashMap::new(),
+      tcp: HashMap::new(),
+      iss: 0x1000_0000,
+      calls: 0,
+    }
+  }
+
+  fn arp(&mut self, packet: &[u8]) {
+    const REQUEST: u16 = 1;
+    const REPLY: u16 = 2;
+    if packet.len() < 28 || be16(packet, 6) != REQUEST {
+      return;
+    }
+    // the gateway stands for every other host of the network
+    let target = ipv4(packet, 24);
+    if target == self.link.ip || target.octets()[..3] != GATEWAY.octets()[..3] {
+      return;
+    }
+
+    let mut reply = packet[..8].to_vec();
+    reply[6..8].copy_from_slice(&REPLY.to_be_bytes());
+    reply.extend(GATEWAY_MAC);
+    reply.extend(target.octets());
+    reply.extend(&packet[8..18]); // sender of the request
+    self.link.ethernet(self.link.mac, ethertype::ARP, &reply);
+  }
+
+  fn ip(&mut self, packet: &[u8]) {
+    if packet.len() < 20 || packet[0] >> 4 != 4 {
+      return;
+    }
+    let ihl = (packet[0] & 0xf) as usize * 4;
+    let len = (be16(packet, 2) as usize).min(packet.len());
+    // fragments are not reassembled
+    if ihl < 20 || len < ihl || be16(packet, 6) & 0x3fff != 0 {
+      return;
+    }
+
+    let (src, dst) = (ipv4(packet, 12), ipv4(packet, 16));
+    if !src.is_unspecified() {
+      self.link.ip = src;
+    }
+    let data = &packet[ihl..len];
+    match packet[9] {
+      proto::ICMP => self.icmp(dst, data),
+      proto::UDP => self.udp(src, dst, data),
+      proto::TCP => self.tcp(dst, data),
+      _ => {}
+    }
+  }
+
+  fn icmp(&mut self, dst: Ipv4Addr, data: &[u8]) {
+    const ECHO_REPLY: u8 = 0;
+    const ECHO_REQUEST: u8 = 8;
+    if dst != GATEWAY || data.len() < 8 || data[0] != ECHO_REQUEST {
+      return;
+    }
+    let mut reply = data.to_vec();
+    reply[0] = ECHO_REPLY;
+    reply[2..4].fill(0);
+    let sum = checksum(0, &reply);
+    reply[2..4].copy_from_slice(&sum.to_be_bytes());
+    self.link.ipv4(GATEWAY, self.link.ip, proto::ICMP, &reply);
+  }
+
+  fn udp(&mut self, src: Ipv4Addr, dst: Ipv4Addr, data: &[u8]) {
+    if data.len() < 8 {
+      return;
+    }
+    let (sport, dport) = (be16(data, 0), be16(data, 2));
+    let len = (be16(data, 4) as usize).clamp(8, data.len());
+    let payload = &data[8..len];
+
+    if dport == dhcp::SERVER_PORT {
+      return self.dhcp(payload);
+    }
+    if dst != GATEWAY || src.is_unspecified() {
+      return;
+    }
+
+    let socket = match self.udp.entry((sport, dport)) {
+      Entry::Occupied(entry) => entry.into_mut(),
+      Entry::Vacant(entry) => match connect_udp(dport) {
+        Ok(socket) => entry.insert(socket),
+        Err(_) => return,
+      },
+    };
+    // datagrams may be lost, as on any network
+    let _ = socket.send(payload);
+  }
+
+  fn dhcp(&mut self, message: &[u8]) {
+    if message.len() < 240
+      || message[0] != 1
+      || message[236..240] != dhcp::MAGIC
+    {
+      return;
+    }
+
+    let mut kind = None;
+    let mut options = &message[240..];
+    while let [code, rest @ ..] = options {
+      match *code {
+        0 => options = rest,
+        dhcp::END => break,
+        _ => {
+          let Some((&len, rest)) = rest.split_first() else { break };
+          let Some(value) = rest.get(..len as usize) else { break };
+          if *code == dhcp::MESSAGE_TYPE && len == 1 {
+            kind = Some(value[0]);
+          }
+          options = &rest[len as usize..];
+        }
+      }
+    }
+    let reply = match kind {
+      Some(dhcp::DISCOVER) => dhcp::OFFER,
+      Some(dhcp::REQUEST) => dhcp::ACK,
+      _ => return,
+    };
+
+    let mut answer = vec![0; 240];
+    answer[0] = 2; // boot reply
+    answer[1..3].copy_from_slice(&message[1..3]); // hardware type and length
+    answer[4..8].copy_from_slice(&message[4..8]); // transaction
+    answer[10..12].copy_from_slice(&message[10..12]); // flags
+    answer[16..20].copy_from_slice(&GUEST.octets());
+    answer[20..24].copy_from_slice(&GATEWAY.octets());
+    answer[28..44].copy_from_slice(&message[28..44]); // client hardware
+    answer[236..240].copy_from_slice(&dhcp::MAGIC);
+
+    answer.extend([dhcp::MESSAGE_TYPE, 1, reply]);
+    answer.extend([dhcp::SERVER_ID, 4]);
+    answer.extend(GATEWAY.octets());
+    answer.extend([dhcp::LEASE_TIME, 4]);
+    answer.extend(dhcp::LEASE.to_be_bytes());
+    answer.extend([dhcp::SUBNET_MASK, 4]);
+    answer.extend(NETMASK.octets());
+    answer.extend([dhcp::ROUTER, 4]);
+    answer.extend(GATEWAY.octets());
+    answer.push(dhcp::END);
+    // the minimal BOOTP message
+    answer.resize(answer.len().max(300), 0);
+
+    let server = (GATEWAY, dhcp::SERVER_PORT);
+    let client = (Ipv4Addr::BROADCAST, dhcp::CLIENT_PORT);
+    self.link.udp(server, client, &answer);
+  }
+
+  fn tcp(&mut self, dst: Ipv4Addr, segment: &[u8]) {
+    if segment.len() < 20 {
+      return;
+    }
+    let (sport, dport) = (be16(segment, 0), be16(segment, 2));
+    let (seq, ack) = (be32(segment, 4), be32(segment, 8));
+    let offset = (segment[12] >> 4) as usize * 4;
+    let flags = segment[13];
+    let window = be16(segment, 14) as u32;
+    let Some(data) = segment.get(offset.max(20)..) else { return };
+
+    let key = (sport, dport);
+    let ports = (dport, sport);
+    if flags & tcp::RST != 0 {
+      self.tcp.remove(&key);
+      return;
+    }
+    let reset = |link: &mut Link| {
+      let len = data.len() as u32 + (flags & (tcp::SYN | tcp::FIN) != 0) as u32;
+      match flags & tcp::ACK {
+        0 => {
+          link.tcp(ports, 0, seq.wrapping_add(len), tcp::RST | tcp::ACK, &[])
+        }
+        _ => link.tcp(ports, ack, 0, tcp::RST, &[]),
+      }
+    };
+
+    if flags & (tcp::SYN | tcp::ACK) == tcp::SYN {
+      if let Some(conn) = self.tcp.get(&key) {
+        // the SYN-ACK was lost, the connection is still being set up
+        if conn.stream.is_some() && conn.snd_una == conn.iss {
+          let (iss, ack) = (conn.iss, conn.rcv_nxt);
+          self.link.tcp(ports, iss, ack, tcp::SYN | tcp::ACK, &[]);
+        }
+        return;
+      }
+      if dst != GATEWAY {
+        return reset(&mut self.link);
+      }
+
+      // the emulation goes on while the host connects, the SYN-ACK waits
+      // for the connection on a later poll
+      let host = (Ipv4Addr::LOCALHOST, dport);
+      let connect = thread::spawn(move || TcpStream::connect(host));
+      let iss = self.iss;
+      self.iss = self.iss.wrapping_add(0x0001_0000);
+      let conn = Tcp {
+        stream: None,
+        connect: Some(connect),
+        iss,
+        snd_una: iss,
+        snd_nxt: iss.wrapping_add(1),
+        rcv_nxt: seq.wrapping_add(1),
+        window,
+        unacked: VecDeque::new(),
+        idle: 0,
+        pending: Vec::new(),
+        fin_sent: false,
+        fin_received: false,
+        shut: false,
+      };
+      self.tcp.insert(key, conn);
+      return;
+    }
+
+    let Some(conn) = self.tcp.get_mut(&key) else {
+      return reset(&mut self.link);
+    };
+    if flags & tcp::ACK != 0 {
+      let acked = ack.wrapping_sub(conn.snd_una);
+      if acked as i32 > 0 && acked <= conn.in_flight() {
+        conn.acknowledge(ack);
+      }
+      conn.window = window;
+    }
+
+    if !data.is_empty() || flags & tcp::FIN != 0 {
+      // segments out of order are dropped, the guest sends them again
+      if seq == conn.rcv_nxt && !conn.fin_received {
+        conn.pending.extend(data);
+        conn.rcv_nxt = conn.rcv_nxt.wrapping_add(data.len() as u32);
+        if flags & tcp::FIN != 0 {
+          conn.rcv_nxt = conn.rcv_nxt.wrapping_add(1);
+          conn.fin_received = true;
+        }
+      }
+      self.link.tcp(ports, conn.snd_nxt, conn.rcv_nxt, tcp::ACK, &[]);
+    }
+    if conn.closed() {
+      self.tcp.remove(&key);
+    }
+  }
+
+  // Move the data waiting on the host sockets to the guest.
+  fn poll(&mut self) {
+    let mut buf = vec![0; 65536];
+    for (&(gport, hport), socket) in &self.udp {
+      while self.link.frames.len() < FRAMES {
+        let Ok(len) = socket.recv(&mut buf) else { break };
+        self.link.udp((GATEWAY, hport), (self.link.ip, gport), &buf[..len]);
+      }
+    }
+
+    let link = &mut self.link;
+    self.tcp.retain(|&(gport, hport), conn| {
+      let ports = (hport, gport);
+      let result = match conn.connect.take_if(|c| c.is_finished()) {
+        Some(connect) => connected(link, ports, conn, connect),
+        None if conn.stream.is_none() => return true,
+        None => relay(link, ports, conn, &mut buf),
+      };
+      if result.is_err() {
+        link.tcp(ports, conn.snd_nxt, conn.rcv_nxt, tcp::RST | tcp::ACK, &[]);
+      }
+      result.is_ok() && !conn.closed()
+    });
+  }
+}
+
+impl Default for User {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+// Answer the SYN of the guest once the host accepted the connection.
+fn connected(
+  link: &mut Link,
+  ports: (u16, u16),
+  conn: &mut Tcp,
+  connect: JoinHandle<io::Result<TcpStream>>,
+) -> io::Result<()> {
+  let stream = connect.join().map_err(|_| io::ErrorKind::Other)??;
+  stream.set_nonblocking(true)?;
+  let _ = stream.set_nodelay(true);
+  conn.stream = Some(stream);
+  link.tcp(ports, conn.iss, conn.rcv_nxt, tcp::SYN | tcp::ACK, &[]);
+  Ok(())
+}
+
+fn connect_udp(port: u16) -> io::Result<UdpSocket> {
+  let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0))?;
+  socket.connect((Ipv4Addr::LOCALHOST, port))?;
+  socket.set_nonblocking(true)?;
+  Ok(socket)
+}
+
+// Exchange the data of a connection with its host socket.
+fn relay(
+  link: &mut Link,
+  ports: (u16, u16),
+  conn: &mut Tcp,
+  buf: &mut [u8],
+) -> io::Result<()> {
+  let would_block = |err: &io::Error| err.kind() == io::ErrorKind::WouldBlock;
+  let Some(stream) = &mut conn.stream else { return Ok(()) };
+
+  while !conn.pending.is_empty() {
+    match stream.write(&conn.pending) {
+      Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+      Ok(n) => drop(conn.pending.drain(..n)),
+      Err(err) if would_block(&err) => break,
+      Err(err) => return Err(err),
+    }
+  }
+  if conn.fin_received && conn.pending.is_empty() && !conn.shut {
+    conn.shut = true;
+    let _ = stream.shutdown(Shutdown::Write);
+  }
+
+  // what is in flight is worked out by hand, the stream borrows `conn`
+  loop {
+    let in_flight = conn.snd_nxt.wrapping_sub(conn.snd_una);
+    if conn.fin_sent || in_flight >= conn.window || link.frames.len() >= FRAMES
+    {
+      break;
+    }
+    let room = (conn.window - in_flight) as usize;
+    let len = room.min(MSS).min(buf.len());
+    match stream.read(&mut buf[..len]) {
+      Ok(0) => {
+        let flags = tcp::FIN | tcp::ACK;
+        link.tcp(ports, conn.snd_nxt, conn.rcv_nxt, flags, &[]);
+        conn.snd_nxt = conn.snd_nxt.wrapping_add(1);
+        conn.fin_sent = true;
+      }
+      Ok(n) => {
+        let flags = tcp::PSH | tcp::ACK;
+        link.tcp(ports, conn.snd_nxt, conn.rcv_nxt, flags, &buf[..n]);
+        conn.snd_nxt = conn.snd_nxt.wrapping_add(n as u32);
+        conn.unacked.extend(&buf[..n]);
+      }
+      Err(err) if would_block(&err) => break,
+      Err(err) => return Err(err),
+    }
+  }
+  conn.retransmit(link, ports);
+  Ok(())
+}
+
+impl Backend for User {
+  fn send(&mut self, frame: &[u8]) {
+    if frame.len() < 14 {
+      return;
+    }
+    self.link.mac.copy_from_slice(&frame[6..12]);
+    match be16(frame, 12) {
+      ethertype::ARP => self.arp(&frame[14..]),
+      ethertype::IPV4 => self.ip(&frame[14..]),
+      _ => {}
+    }
+  }
+
+  fn recv(&mut self) -> Option<Vec<u8>> {
+    self.calls = self.calls.wrapping_add(1);
+    if self.link.frames.is_empty() && self.calls.is_multiple_of(POLL_INTERVAL) {
+      self.poll();
+    }
+    self.link.frames.pop_front()
+  }
+}
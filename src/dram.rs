@@ -1,7 +1,7 @@
 use crate::{
-  Exception,
   bus::dram,
   cpu::{BYTE, DWORD, HALF, WORD},
+  Exception,
 };
 
 pub const DRAM_SIZE: u64 = 1024 * 1024 * 1024;
@@ -33,12 +33,16 @@ impl Dram {
   }
 
   pub fn load(&self, addr: u64, size: u8) -> Result<u64, Exception> {
+    if !self.contains(addr, size) {
+      return Err(Exception::LoadAccessFault(addr));
+    }
+
     Ok(match size {
       BYTE => self.load8(addr),
       HALF => self.load16(addr),
       WORD => self.load32(addr),
       DWORD => self.load64(addr),
-      _ => return Err(Exception::StoreAMOAccessFault),
+      _ => return Err(Exception::LoadAccessFault(addr)),
     })
   }
 
@@ -48,15 +52,24 @@ impl Dram {
     value: u64,
     size: u8,
   ) -> Result<(), Exception> {
+    if !self.contains(addr, size) {
+      return Err(Exception::StoreAMOAccessFault(addr));
+    }
+
     Ok(match size {
       BYTE => self.store8(addr, value),
       HALF => self.store16(addr, value),
       WORD => self.store32(addr, value),
       DWORD => self.store64(addr, value),
-      _ => return Err(Exception::StoreAMOAccessFault),
+      _ => return Err(Exception::StoreAMOAccessFault(addr)),
     })
   }
 
+  // The backing memory may be smaller than the mapped region.
+  fn contains(&self, addr: u64, size: u8) -> bool {
+    addr.saturating_add(size as u64 / 8) <= self.dram.len() as u64
+  }
+
   fn load8(&self, addr: u64) -> u64 {
     self.dram[addr as usize] as u64
   }
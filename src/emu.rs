@@ -1,12 +1,68 @@
-use crate::{Cpu, Exception};
+use {
+  crate::{
+    bus::{dram, rom},
+    dev::{
+      rom::Rom,
+      serial::{Null, Serial},
+      syscon::{Request, Syscon},
+      uart::Uart,
+      virtio::{
+        input::{Event, Input},
+        Mmio,
+      },
+    },
+    fdt,
+    htif::Htif,
+    Cpu, Exception, Misaligned, Mode, State, Xregs, POINTER_TO_DTB,
+    RESET_VECTOR,
+  },
+  object::{
+    read::elf::{ElfFile64, ProgramHeader},
+    Endianness, Object, ObjectSegment, ObjectSymbol,
+  },
+};
+
+/// How the guest ended the emulation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Exit {
+  Pass,
+  /// The guest exited with a non-zero code, for the riscv-tests it is the
+  /// number of the failed test.
+  Fail(u64),
+}
+
+/// Error of [`Emu::load_elf`].
+#[derive(Debug)]
+pub enum ElfError {
+  Parse(object::Error),
+  /// The segment at this address doesn't fit in the main memory.
+  Segment(u64),
+}
+
+impl From<object::Error> for ElfError {
+  fn from(err: object::Error) -> Self {
+    Self::Parse(err)
+  }
+}
+
+/// Error of [`Emu::with_fdt`].
+#[derive(Debug)]
+pub enum FdtError {
+  /// The bus has no boot ROM to hold the device tree.
+  NoRom,
+  /// The device tree of this size doesn't fit in the boot ROM.
+  TooLarge(usize),
+}
 
 pub struct Emu {
   pub cpu: Cpu,
+  pub htif: Option<Htif>,
+  exit: Option<Exit>,
 }
 
 impl Emu {
   pub fn new(ram: usize) -> Self {
-    Self { cpu: Cpu::new(ram) }
+    Self { cpu: Cpu::new(ram), htif: None, exit: None }
   }
 
   pub fn with_dram(&mut self, dram: &[u8]) -> &mut Self {
@@ -14,15 +70,140 @@ impl Emu {
     self
   }
 
+  /// Start the hart right at `pc`, past the reset stub of the boot ROM.
   pub fn with_pc(&mut self, pc: u64) -> &mut Self {
     self.cpu.pc = pc;
     self
   }
 
+  /// Boot at `entry` through the reset stub of the boot ROM, now and after a
+  /// reset. The default entry is the start of the main memory.
+  pub fn with_entry(&mut self, entry: u64) -> &mut Self {
+    let rom = self.cpu.bus.device_mut::<Rom>().expect("no boot ROM");
+    rom.set_entry(entry);
+    self.with_pc(RESET_VECTOR)
+  }
+
+  pub fn with_misaligned(&mut self, misaligned: Misaligned) -> &mut Self {
+    self.cpu.misaligned = misaligned;
+    self
+  }
+
+  /// Connect the UART of the machine to `serial`.
+  pub fn with_serial(&mut self, serial: impl Serial + 'static) -> &mut Self {
+    if let Some(uart) = self.cpu.bus.device_mut::<Uart>() {
+      uart.backend = Box::new(serial);
+    }
+    self
+  }
+
+  /// Send `event` to the guest through the first virtio input device, false
+  /// if there is none or it is full.
+  pub fn input(&mut self, event: Event) -> bool {
+    let input = self.cpu.bus.device_mut::<Mmio<Input>>();
+    input.is_some_and(|mmio| mmio.device.push(event))
+  }
+
+  /// Serve the HTIF at the configured `tohost` and `fromhost` addresses.
+  pub fn with_htif(&mut self, htif: Htif) -> &mut Self {
+    self.htif = Some(htif);
+    self
+  }
+
+  /// Generate the device tree of the machine as it is configured now and
+  /// place it in the boot ROM at [`POINTER_TO_DTB`].
+  pub fn with_fdt(&mut self) -> Result<&mut Self, FdtError> {
+    let blob = fdt::generate(&self.cpu);
+    let rom = self.cpu.bus.device_mut::<Rom>().ok_or(FdtError::NoRom)?;
+    let at = (POINTER_TO_DTB - rom::ADDR) as usize;
+    let Some(mem) = rom.as_slice_mut().get_mut(at..at + blob.len()) else {
+      return Err(FdtError::TooLarge(blob.len()));
+    };
+    mem.copy_from_slice(&blob);
+    Ok(self)
+  }
+
+  /// Load the segments of a 64-bit ELF image into the main memory at their
+  /// physical addresses and start at its entry point. Without a configured
+  /// HTIF, an HTIF is set up at the `tohost` and `fromhost` symbols of the
+  /// image.
+  pub fn load_elf(&mut self, elf: &[u8]) -> Result<&mut Self, ElfError> {
+    let elf = ElfFile64::<Endianness>::parse(elf)?;
+
+    let ram = self.cpu.bus.dram.as_slice_mut();
+    for segment in elf.segments() {
+      // a kernel linked to run translated is still loaded in memory
+      let addr = segment.elf_program_header().p_paddr(elf.endian());
+      let size = segment.size();
+      let data = segment.data()?;
+
+      let start = addr.wrapping_sub(dram::ADDR) as usize;
+      let Some(mem) = start
+        .checked_add(size as usize)
+        .and_then(|end| ram.get_mut(start..end))
+        .filter(|mem| mem.len() >= data.len())
+      else {
+        return Err(ElfError::Segment(addr));
+      };
+      // the rest of the segment is zero-filled
+      let (file, bss) = mem.split_at_mut(data.len());
+      file.copy_from_slice(data);
+      bss.fill(0);
+    }
+
+    if self.htif.is_none() {
+      let symbol = |name| {
+        elf.symbols().find(|s| s.name() == Ok(name)).map(|s| s.address())
+      };
+      if let Some(tohost) = symbol("tohost") {
+        self.htif = Some(Htif::new(tohost, symbol("fromhost"), Null));
+      }
+    }
+    Ok(self.with_entry(elf.entry()))
+  }
+
+  /// Result of the emulation once the guest ended it.
+  pub fn exit(&self) -> Option<Exit> {
+    self.exit
+  }
+
+  /// Return the hart and the devices to their power-on state, the main
+  /// memory and the boot ROM are kept, and start again at the reset vector.
+  pub fn reset(&mut self) {
+    let cpu = &mut self.cpu;
+    cpu.bus.reset();
+    cpu.pc = RESET_VECTOR;
+    cpu.mode = Mode::Machine;
+    cpu.xregs = Xregs::new();
+    cpu.state = State::new();
+    cpu.reservation = None;
+  }
+
   pub fn cycle(&mut self) -> Result<u64, Exception> {
-    match self.cpu.execute() {
-      Ok(inst) => Ok(inst),
-      Err(ex) => Err(ex),
+    let result = self.cpu.execute();
+    if let Some(htif) = &mut self.htif {
+      if let Some(code) = htif.poll(&mut self.cpu.bus) {
+        self.exit = Some(if code == 0 { Exit::Pass } else { Exit::Fail(code) });
+      }
+    }
+    match self.cpu.bus.syscon_mut().and_then(Syscon::take) {
+      Some(Request::Poweroff(exit)) => self.exit = Some(exit),
+      Some(Request::Reset) => self.reset(),
+      None => {}
+    }
+    result
+  }
+
+  /// Run until the guest ends the emulation, taking the traps of the
+  /// exceptions on the way.
+  pub fn run(&mut self) -> Exit {
+    loop {
+      if let Some(exit) = self.exit {
+        return exit;
+      }
+      if let Err(ex) = self.cycle() {
+        self.cpu.catch_exception(ex);
+      }
     }
   }
 }
@@ -0,0 +1,290 @@
+use {
+  crate::{
+    bus::dram,
+    csr::MISA,
+    dev::{clint::TIMEBASE_FREQ, plic::Plic},
+    Cpu,
+  },
+  std::collections::HashMap,
+};
+
+const MAGIC: u32 = 0xd00d_feed;
+const VERSION: u32 = 17;
+const LAST_COMP_VERSION: u32 = 16;
+
+const BEGIN_NODE: u32 = 1;
+const END_NODE: u32 = 2;
+const PROP: u32 = 3;
+const END: u32 = 9;
+
+// Single-letter extensions implemented by the instruction decoder.
+const DECODED: &str = "ia";
+
+/// Phandles of the interrupt controllers.
+pub mod phandle {
+  pub const CPU_INTC: u32 = 1;
+  pub const PLIC: u32 = 2;
+  pub const APLIC: u32 = 3;
+  pub const IMSIC_M: u32 = 4;
+  pub const IMSIC_S: u32 = 5;
+  /// Of the system controller, for the poweroff and reboot nodes.
+  pub const SYSCON: u32 = 6;
+}
+
+/// Interrupt causes of the hart, as numbered by the cpu interrupt controller.
+pub mod irq {
+  pub const SSI: u32 = 1;
+  pub const MSI: u32 = 3;
+  pub const STI: u32 = 5;
+  pub const MTI: u32 = 7;
+  pub const SEI: u32 = 9;
+  pub const MEI: u32 = 11;
+}
+
+/// Where a device is mapped, for its node in the device tree.
+#[derive(Debug, Clone, Copy)]
+pub struct Mapping {
+  pub base: u64,
+  pub size: u64,
+  /// Interrupt source the device is wired to.
+  pub irq: Option<usize>,
+}
+
+/// Builder of a flattened device tree blob. Nodes and properties are written
+/// in order, cells are 32-bit and addresses and sizes take two cells.
+#[derive(Debug)]
+pub struct Fdt {
+  structure: Vec<u8>,
+  strings: Vec<u8>,
+  offsets: HashMap<String, u32>,
+  /// Number of cells of an interrupt specifier of the interrupt parent.
+  pub irq_cells: u32,
+}
+
+impl Fdt {
+  pub fn new() -> Self {
+    Self {
+      structure: Vec::new(),
+      strings: Vec::new(),
+      offsets: HashMap::new(),
+      irq_cells: 1,
+    }
+  }
+
+  pub fn begin_node(&mut self, name: &str) {
+    self.token(BEGIN_NODE);
+    self.structure.extend(name.as_bytes());
+    self.structure.push(0);
+    self.align();
+  }
+
+  pub fn end_node(&mut self) {
+    self.token(END_NODE);
+  }
+
+  pub fn prop(&mut self, name: &str, value: &[u8]) {
+    let offset = self.string(name);
+    self.token(PROP);
+    self.token(value.len() as u32);
+    self.token(offset);
+    self.structure.extend(value);
+    self.align();
+  }
+
+  pub fn prop_empty(&mut self, name: &str) {
+    self.prop(name, &[]);
+  }
+
+  pub fn prop_u32(&mut self, name: &str, value: u32) {
+    self.prop_cells(name, &[value]);
+  }
+
+  pub fn prop_cells(&mut self, name: &str, cells: &[u32]) {
+    let value: Vec<_> = cells.iter().flat_map(|c| c.to_be_bytes()).collect();
+    self.prop(name, &value);
+  }
+
+  /// `reg` property of `(address, size)` pairs.
+  pub fn prop_reg(&mut self, regs: &[(u64, u64)]) {
+    let value: Vec<_> = regs
+      .iter()
+      .flat_map(|&(addr, size)| [addr, size])
+      .flat_map(|x| x.to_be_bytes())
+      .collect();
+    self.prop("reg", &value);
+  }
+
+  pub fn prop_str(&mut self, name: &str, value: &str) {
+    self.prop_strs(name, &[value]);
+  }
+
+  pub fn prop_strs(&mut self, name: &str, values: &[&str]) {
+    let mut value = Vec::new();
+    for s in values {
+      value.extend(s.as_bytes());
+      value.push(0);
+    }
+    self.prop(name, &value);
+  }
+
+  /// `interrupts` property for the source `irq` of the interrupt parent,
+  /// level-triggered where the parent takes a trigger type.
+  pub fn prop_interrupts(&mut self, irq: usize) {
+    match self.irq_cells {
+      1 => self.prop_u32("interrupts", irq as u32),
+      _ => self.prop_cells("interrupts", &[irq as u32, 4]),
+    }
+  }
+
+  /// Serialize the tree, whose nodes must all be closed.
+  pub fn finish(mut self) -> Vec<u8> {
+    self.token(END);
+
+    const HEADER: usize = 40;
+    // a single empty entry terminates the memory reservation block
+    const RSVMAP: usize = 16;
+    let off_struct = HEADER + RSVMAP;
+    let off_strings = off_struct + self.structure.len();
+    let total = off_strings + self.strings.len();
+
+    let header = [
+      MAGIC,
+      total as u32,
+      off_struct as u32,
+      off_strings as u32,
+      HEADER as u32,
+      VERSION,
+      LAST_COMP_VERSION,
+      0, // boot hart
+      self.strings.len() as u32,
+      self.structure.len() as u32,
+    ];
+
+    let mut blob = Vec::with_capacity(total);
+    blob.extend(header.iter().flat_map(|x| x.to_be_bytes()));
+    blob.extend([0; RSVMAP]);
+    blob.extend(self.structure);
+    blob.extend(self.strings);
+    blob
+  }
+
+  fn token(&mut self, token: u32) {
+    self.structure.extend(token.to_be_bytes());
+  }
+
+  fn align(&mut self) {
+    let len = self.structure.len().next_multiple_of(4);
+    self.structure.resize(len, 0);
+  }
+
+  fn string(&mut self, name: &str) -> u32 {
+    if let Some(&offset) = self.offsets.get(name) {
+      return offset;
+    }
+    let offset = self.strings.len() as u32;
+    self.strings.extend(name.as_bytes());
+    self.strings.push(0);
+    self.offsets.insert(name.to_owned(), offset);
+    offset
+  }
+}
+
+impl Default for Fdt {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+/// Device tree of the machine as it is configured: the main memory, the
+/// hart and every device on the bus.
+pub fn generate(cpu: &Cpu) -> Vec<u8> {
+  let mut fdt = Fdt::new();
+  let plic = cpu.bus.device::<Plic>().is_some();
+
+  fdt.begin_node("");
+  fdt.prop_u32("#address-cells", 2);
+  fdt.prop_u32("#size-cells", 2);
+  fdt.prop_str("compatible", "vrisc,virt");
+  fdt.prop_str("model", "vrisc");
+  if plic {
+    fdt.prop_u32("interrupt-parent", phandle::PLIC);
+  } else {
+    fdt.prop_u32("interrupt-parent", phandle::APLIC);
+    fdt.irq_cells = 2;
+  }
+
+  fdt.begin_node("chosen");
+  if let Some(path) = cpu.bus.stdout_path() {
+    fdt.prop_str("stdout-path", &path);
+  }
+  fdt.end_node();
+
+  fdt.begin_node(&format!("memory@{:x}", dram::ADDR));
+  fdt.prop_str("device_type", "memory");
+  fdt.prop_reg(&[(dram::ADDR, cpu.bus.dram.as_slice().len() as u64)]);
+  fdt.end_node();
+
+  cpus(&mut fdt, cpu);
+
+  fdt.begin_node("soc");
+  fdt.prop_u32("#address-cells", 2);
+  fdt.prop_u32("#size-cells", 2);
+  fdt.prop_str("compatible", "simple-bus");
+  fdt.prop_empty("ranges");
+  cpu.bus.describe(&mut fdt, plic);
+  fdt.end_node();
+
+  fdt.end_node();
+  fdt.finish()
+}
+
+fn cpus(fdt: &mut Fdt, cpu: &Cpu) {
+  let misa = cpu.state.load(MISA);
+  let has = |ext: char| misa >> (ext as u8 - b'a') & 1 == 1;
+
+  // single-letter extensions in their canonical order, `misa` also reports
+  // extensions the hart doesn't decode, like C, which the guest must not use
+  let letters: Vec<_> = DECODED.chars().filter(|&ext| has(ext)).collect();
+  let mut extensions: Vec<String> =
+    letters.iter().map(|ext| ext.to_string()).collect();
+  extensions.extend(["zicsr", "zifencei"].map(String::from));
+  // the supervisor timer needs a supervisor mode
+  if has('s') {
+    extensions.push("sstc".into());
+  }
+  if cpu.bus.imsic(crate::Mode::Machine).is_some() {
+    extensions.extend(["smaia", "ssaia"].map(String::from));
+  }
+
+  let mut isa = format!("rv64{}", letters.iter().collect::<String>());
+  for ext in extensions.iter().skip(letters.len()) {
+    isa += "_";
+    isa += ext;
+  }
+
+  fdt.begin_node("cpus");
+  fdt.prop_u32("#address-cells", 1);
+  fdt.prop_u32("#size-cells", 0);
+  fdt.prop_u32("timebase-frequency", TIMEBASE_FREQ as u32);
+
+  fdt.begin_node("cpu@0");
+  fdt.prop_str("device_type", "cpu");
+  fdt.prop_u32("reg", 0);
+  fdt.prop_str("status", "okay");
+  fdt.prop_str("compatible", "riscv");
+  fdt.prop_str("riscv,isa", &isa);
+  fdt.prop_str("riscv,isa-base", "rv64i");
+  let extensions: Vec<_> = extensions.iter().map(String::as_str).collect();
+  fdt.prop_strs("riscv,isa-extensions", &extensions);
+  fdt.prop_str("mmu-type", Cpu::MMU_TYPE);
+
+  fdt.begin_node("interrupt-controller");
+  fdt.prop_str("compatible", "riscv,cpu-intc");
+  fdt.prop_u32("#interrupt-cells", 1);
+  fdt.prop_empty("interrupt-controller");
+  fdt.prop_u32("phandle", phandle::CPU_INTC);
+  fdt.end_node();
+
+  fdt.end_node();
+  fdt.end_node();
+}
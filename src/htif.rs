@@ -0,0 +1,133 @@
+use crate::{
+  cpu::{BYTE, DWORD},
+  dev::serial::Serial,
+  Bus,
+};
+
+/// Devices of the host-target interface.
+mod dev {
+  pub const SYSCALL: u64 = 0;
+  pub const CONSOLE: u64 = 1;
+}
+
+/// Proxied system calls, by their RISC-V Linux numbers.
+mod sys {
+  pub const READ: u64 = 63;
+  pub const WRITE: u64 = 64;
+  pub const EXIT: u64 = 93;
+}
+
+const EBADF: u64 = -9i64 as u64;
+const EFAULT: u64 = -14i64 as u64;
+const ENOSYS: u64 = -38i64 as u64;
+
+/// Host-target interface of the riscv-tests and the proxy kernel. The target
+/// writes commands to `tohost` and the host answers through `fromhost`.
+///
+/// A command holds the device in bits 63:56, the command in bits 55:48 and
+/// a payload in the remaining bits.
+#[derive(Debug)]
+pub struct Htif {
+  pub tohost: u64,
+  pub fromhost: Option<u64>,
+  pub console: Box<dyn Serial>,
+  // a `getchar` is waiting for the console
+  reading: bool,
+}
+
+impl Htif {
+  pub fn new(
+    tohost: u64,
+    fromhost: Option<u64>,
+    console: impl Serial + 'static,
+  ) -> Self {
+    Self { tohost, fromhost, console: Box::new(console), reading: false }
+  }
+
+  /// Serve the command written to `tohost`, if any. Returns the exit code
+  /// once the target exits.
+  pub fn poll(&mut self, bus: &mut Bus) -> Option<u64> {
+    if self.reading {
+      self.getchar(bus);
+    }
+
+    let cmd = bus.load(self.tohost, DWORD).ok().filter(|&cmd| cmd != 0)?;
+    let _ = bus.store(self.tohost, 0, DWORD);
+
+    let payload = cmd & ((1 << 48) - 1);
+    match (cmd >> 56, cmd >> 48 & 0xff) {
+      (dev::SYSCALL, 0) if payload & 1 == 1 => return Some(payload >> 1),
+      (dev::SYSCALL, 0) => {
+        let exit = self.syscall(bus, payload);
+        if exit.is_some() {
+          return exit;
+        }
+        self.respond(bus, 1);
+      }
+      (dev::CONSOLE, 0) => self.reading = true,
+      (dev::CONSOLE, 1) => {
+        self.console.write(payload as u8);
+        self.respond(bus, dev::CONSOLE << 56 | 1 << 48);
+      }
+      // commands of devices that don't exist are dropped
+      _ => {}
+    }
+    None
+  }
+
+  fn respond(&mut self, bus: &mut Bus, value: u64) {
+    if let Some(fromhost) = self.fromhost {
+      let _ = bus.store(fromhost, value, DWORD);
+    }
+  }
+
+  // Answer the pending `getchar` once the console has a byte and the target
+  // took the previous answer.
+  fn getchar(&mut self, bus: &mut Bus) {
+    let Some(fromhost) = self.fromhost else { return };
+    if bus.load(fromhost, DWORD) != Ok(0) {
+      return;
+    }
+    if let Some(byte) = self.console.read() {
+      self.reading = false;
+      self.respond(bus, dev::CONSOLE << 56 | byte as u64);
+    }
+  }
+
+  // `magic` points to the system call number followed by its arguments, the
+  // result replaces the number.
+  fn syscall(&mut self, bus: &mut Bus, magic: u64) -> Option<u64> {
+    let mut args = [0; 4];
+    for (i, arg) in args.iter_mut().enumerate() {
+      let addr = magic.checked_add(8 * i as u64);
+      *arg = addr.and_then(|addr| bus.load(addr, DWORD).ok()).unwrap_or(0);
+    }
+
+    let [num, fd, buf, len] = args;
+    let ret = match num {
+      sys::EXIT => return Some(fd),
+      // a buffer past the end of the address space isn't the target's
+      sys::READ | sys::WRITE if buf.checked_add(len).is_none() => EFAULT,
+      sys::WRITE if fd == 1 || fd == 2 => {
+        for addr in buf..buf + len {
+          let Ok(byte) = bus.load(addr, BYTE) else { break };
+          self.console.write(byte as u8);
+        }
+        len
+      }
+      sys::READ if fd == 0 => {
+        let mut read = 0;
+        while read < len {
+          let Some(byte) = self.console.read() else { break };
+          let _ = bus.store(buf + read, byte as u64, BYTE);
+          read += 1;
+        }
+        read
+      }
+      sys::READ | sys::WRITE => EBADF,
+      _ => ENOSYS,
+    };
+    let _ = bus.store(magic, ret, DWORD);
+    None
+  }
+}
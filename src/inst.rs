@@ -1,6 +1,7 @@
 use {
   crate::{
-    cpu::{Mode, BYTE, DWORD, HALF, WORD},
+    cpu::{aligned, Mode, BYTE, DWORD, HALF, WORD},
+    csr::{x, MEPC, MIP, SEPC},
     Cpu, Exception,
   },
   macros::{imm, slice},
@@ -24,6 +25,41 @@ impl Cpu {
     );
 
     match opcode {
+      0x03 => {
+        let imm = ((inst as i32 as i64) >> 20) as u64;
+        let addr = self.xregs.load(rs1).wrapping_add(imm);
+        match funct3 {
+          0x0 => inst!("lb" => {
+            let val = self.load(addr, BYTE)?;
+            self.xregs.store(rd, val as i8 as i64 as u64);
+          }),
+          0x1 => inst!("lh" => {
+            let val = self.load(addr, HALF)?;
+            self.xregs.store(rd, val as i16 as i64 as u64);
+          }),
+          0x2 => inst!("lw" => {
+            let val = self.load(addr, WORD)?;
+            self.xregs.store(rd, val as i32 as i64 as u64);
+          }),
+          0x3 => inst!("ld" => {
+            let val = self.load(addr, DWORD)?;
+            self.xregs.store(rd, val);
+          }),
+          0x4 => inst!("lbu" => {
+            let val = self.load(addr, BYTE)?;
+            self.xregs.store(rd, val);
+          }),
+          0x5 => inst!("lhu" => {
+            let val = self.load(addr, HALF)?;
+            self.xregs.store(rd, val);
+          }),
+          0x6 => inst!("lwu" => {
+            let val = self.load(addr, WORD)?;
+            self.xregs.store(rd, val);
+          }),
+          _ => return Err(Exception::IllegalInst(inst)),
+        }
+      }
       // fences unimplemented because of single-threading and seq execution
       0x0f => match funct3 {
         0x0 => inst!("fence" => {
@@ -99,6 +135,72 @@ impl Cpu {
           _ => return Err(Exception::IllegalInst(inst)),
         }
       }
+      0x2f => {
+        let funct5 = funct7 >> 2;
+        let size = match funct3 {
+          0x2 => WORD,
+          0x3 => DWORD,
+          _ => return Err(Exception::IllegalInst(inst)),
+        };
+        let sext =
+          |x: u64| if size == WORD { x as i32 as i64 as u64 } else { x };
+
+        // atomics always trap when misaligned, whatever the policy is
+        let addr = self.xregs.load(rs1);
+        if !aligned(addr, size) {
+          return Err(match funct5 {
+            0x02 => Exception::LoadAddrMisalign(addr),
+            _ => Exception::StoreAMOAddrMisalign(addr),
+          });
+        }
+
+        match funct5 {
+          0x02 => inst!("lr" => {
+            let val = self.load(addr, size)?;
+            self.reservation = Some(addr);
+            self.xregs.store(rd, sext(val));
+          }),
+          0x03 => inst!("sc" => {
+            let reserved = self.reservation.take() == Some(addr);
+            if reserved {
+              self.store(addr, self.xregs.load(rs2), size)?;
+            }
+            self.xregs.store(rd, !reserved as u64);
+          }),
+          _ => {
+            // operands are sign-extended words for `.w`, which keeps both
+            // the signed and the unsigned order of the 32-bit values
+            let (name, op): (_, fn(u64, u64) -> u64) = match funct5 {
+              0x00 => ("amoadd", u64::wrapping_add),
+              0x01 => ("amoswap", |_, b| b),
+              0x04 => ("amoxor", |a, b| a ^ b),
+              0x08 => ("amoor", |a, b| a | b),
+              0x0c => ("amoand", |a, b| a & b),
+              0x10 => ("amomin", |a, b| (a as i64).min(b as i64) as u64),
+              0x14 => ("amomax", |a, b| (a as i64).max(b as i64) as u64),
+              0x18 => ("amominu", u64::min),
+              0x1c => ("amomaxu", u64::max),
+              _ => return Err(Exception::IllegalInst(inst)),
+            };
+            inst!(name => {
+              // the read of an AMO reports store/AMO faults
+              let t = match self.load(addr, size) {
+                Ok(val) => sext(val),
+                Err(Exception::LoadAccessFault(addr)) => {
+                  return Err(Exception::StoreAMOAccessFault(addr))
+                }
+                Err(Exception::LoadPageFault(addr)) => {
+                  return Err(Exception::StoreAMOPageFault(addr))
+                }
+                Err(ex) => return Err(ex),
+              };
+              let value = op(t, sext(self.xregs.load(rs2)));
+              self.store(addr, value, size)?;
+              self.xregs.store(rd, t);
+            })
+          }
+        }
+      }
       0x33 => match (funct3, funct7) {
         (0x0, 0x00) => inst!("add" => {
           self.xregs.store(rd, self.xregs.load(rs1).wrapping_add(self.xregs.load(rs2)));
@@ -134,32 +236,38 @@ impl Cpu {
         match funct3 {
           0x0 => inst!("beq" =>
             if self.xregs.load(rs1) == self.xregs.load(rs2) {
-              self.pc = self.pc.wrapping_add(imm).wrapping_sub(4);
+              let target = self.jump_target(self.pc.wrapping_add(imm))?;
+              self.pc = target.wrapping_sub(4);
             }
           ),
           0x1 => inst!("bne" =>
             if self.xregs.load(rs1) != self.xregs.load(rs2) {
-              self.pc = self.pc.wrapping_add(imm).wrapping_sub(4);
+              let target = self.jump_target(self.pc.wrapping_add(imm))?;
+              self.pc = target.wrapping_sub(4);
             }
           ),
           0x4 => inst!("blt" =>
             if (self.xregs.load(rs1) as i64) < self.xregs.load(rs2) as i64 {
-              self.pc = self.pc.wrapping_add(imm).wrapping_sub(4);
+              let target = self.jump_target(self.pc.wrapping_add(imm))?;
+              self.pc = target.wrapping_sub(4);
             }
           ),
           0x5 => inst!("bge" =>
             if self.xregs.load(rs1) as i64 >= self.xregs.load(rs2) as i64 {
-              self.pc = self.pc.wrapping_add(imm).wrapping_sub(4);
+              let target = self.jump_target(self.pc.wrapping_add(imm))?;
+              self.pc = target.wrapping_sub(4);
             }
           ),
           0x6 => inst!("bltu" =>
             if self.xregs.load(rs1) < self.xregs.load(rs2) {
-              self.pc = self.pc.wrapping_add(imm).wrapping_sub(4);
+              let target = self.jump_target(self.pc.wrapping_add(imm))?;
+              self.pc = target.wrapping_sub(4);
             }
           ),
           0x7 => inst!("bgeu" =>
             if self.xregs.load(rs1) >= self.xregs.load(rs2) {
-              self.pc = self.pc.wrapping_add(imm).wrapping_sub(4);
+              let target = self.jump_target(self.pc.wrapping_add(imm))?;
+              self.pc = target.wrapping_sub(4);
             }
           ),
           _ => return Err(Exception::IllegalInst(inst)),
@@ -171,17 +279,20 @@ impl Cpu {
         let imm = inst as i32 as i64 >> 20;
         let target = (self.xregs.load(rs1) as i64).wrapping_add(imm) & !1;
 
-        self.pc = (target as u64).wrapping_sub(4);
+        self.pc = self.jump_target(target as u64)?.wrapping_sub(4);
         self.xregs.store(rd, t);
       }),
       0x6f => inst!("jal" => {
-        self.xregs.store(rd, self.pc.wrapping_add(4));
+        let t = self.pc.wrapping_add(4);
 
         let imm = (((inst & 0x80000000) as i32 as i64 >> 11) as u64)
           | (inst & 0xff000)
           | ((inst >> 9) & 0x800)
           | ((inst >> 20) & 0x7fe);
-        self.pc = self.pc.wrapping_add(imm).wrapping_sub(4);
+        let target = self.jump_target(self.pc.wrapping_add(imm))?;
+
+        self.pc = target.wrapping_sub(4);
+        self.xregs.store(rd, t);
       }),
       0x73 => {
         let csr = (inst >> 20 & 0xfff) as u16;
@@ -197,25 +308,60 @@ impl Cpu {
             }),
             (0x1, 0x0) => inst!("ebreak" => return Err(Exception::Breakpoint)),
             (0x2, 0x0) => inst!("uret" => todo!()),
-            (0x2, 0x8) => inst!("sret" => todo!()),
-            (0x2, 0x18) => inst!("mret" => todo!()),
+            (0x2, 0x8) => inst!("sret" => {
+              if self.mode == Mode::User {
+                return Err(Exception::IllegalInst(inst));
+              }
+              let spp = self.state.load_sstatus(x::SPP);
+              self.state.store_sstatus(x::SIE, self.state.load_sstatus(x::SPIE));
+              self.state.store_sstatus(x::SPIE, 1);
+              self.state.store_sstatus(x::SPP, 0);
+
+              self.mode = if spp == 1 { Mode::Supervisor } else { Mode::User };
+              self.pc = self.state.load(SEPC).wrapping_sub(4);
+            }),
+            (0x2, 0x18) => inst!("mret" => {
+              if self.mode != Mode::Machine {
+                return Err(Exception::IllegalInst(inst));
+              }
+              let mpp = self.state.load_mstatus(x::MPP);
+              self.state.store_mstatus(x::MIE, self.state.load_mstatus(x::MPIE));
+              self.state.store_mstatus(x::MPIE, 1);
+              self.state.store_mstatus(x::MPP, Mode::User as u64);
+
+              self.mode = match mpp {
+                0b00 => Mode::User,
+                0b01 => Mode::Supervisor,
+                _ => Mode::Machine,
+              };
+              self.pc = self.state.load(MEPC).wrapping_sub(4);
+            }),
             _ => return Err(Exception::IllegalInst(inst)),
           },
           op @ (0x1 | 0x2 | 0x3 | 0x5 | 0x6 | 0x7) => {
             let imm = rs1;
-            let t = self.state.load(csr);
+            let t = self.load_csr(csr).ok_or(Exception::IllegalInst(inst))?;
             let r1 = self.xregs.load(rs1);
+            // `mip.SEIP` reads with the external line ORed in, but only its
+            // software-writable bit is set or cleared
+            let old =
+              if csr == MIP { self.state.load_software_mip() } else { t };
             let (name, reg) = match op {
               0x1 => ("csrrw", r1),
-              0x2 => ("csrrs", t | r1),
-              0x3 => ("csrrc", t & !r1),
+              0x2 => ("csrrs", old | r1),
+              0x3 => ("csrrc", old & !r1),
               0x5 => ("csrrwi", imm),
-              0x6 => ("csrrsi", t | imm),
-              0x7 => ("csrrci", t & !imm),
+              0x6 => ("csrrsi", old | imm),
+              0x7 => ("csrrci", old & !imm),
               _ => unreachable!(),
             };
+            // set and clear forms don't write the CSR when `rs1`/`uimm` is
+            // zero, which matters for CSRs with side effects like `*topei`
+            let write = matches!(op, 0x1 | 0x5) || rs1 != 0;
             inst!(name => {
-              self.state.store(csr, reg);
+              if write {
+                self.store_csr(csr, reg).ok_or(Exception::IllegalInst(inst))?;
+              }
               self.xregs.store(rd, t);
             })
           }
@@ -6,15 +6,20 @@ pub mod csr;
 pub mod dev;
 mod dram;
 mod emu;
+pub mod fdt;
+mod htif;
 mod inst;
 mod trap;
 pub mod utils;
 
 pub use {
   bus::Bus,
-  cpu::{Cpu, Mode, Xregs, POINTER_TO_DTB, REG_COUNT},
+  cpu::{
+    Cpu, Misaligned, Mode, Xregs, POINTER_TO_DTB, REG_COUNT, RESET_VECTOR,
+  },
   csr::State,
   dram::{Dram, DRAM_SIZE},
-  emu::Emu,
-  trap::{Exception, Trap},
+  emu::{ElfError, Emu, Exit, FdtError},
+  htif::Htif,
+  trap::{Exception, Trap, INTERRUPT},
 };
@@ -1,13 +1,15 @@
+/// Synchronous exceptions. Faults carry the faulting virtual address, and
+/// `IllegalInst` the instruction bits, which are reported in `*tval`.
 #[derive(Debug, PartialEq)]
 pub enum Exception {
-  InstAddrMisalign,
-  InstAccessFault,
+  InstAddrMisalign(u64),
+  InstAccessFault(u64),
   IllegalInst(u64),
   Breakpoint,
-  LoadAddrMisalign,
-  LoadAccessFault,
-  StoreAMOAddrMisalign,
-  StoreAMOAccessFault,
+  LoadAddrMisalign(u64),
+  LoadAccessFault(u64),
+  StoreAMOAddrMisalign(u64),
+  StoreAMOAccessFault(u64),
   ECallUser,
   ECallSuper,
   ECallMachine,
@@ -17,26 +19,16 @@ pub enum Exception {
 }
 
 impl Exception {
-  pub fn epc(&self, pc: u64) -> u64 {
-    match self {
-      Exception::Breakpoint
-      | Exception::ECallUser
-      | Exception::ECallSuper
-      | Exception::ECallMachine => pc,
-      _ => pc.wrapping_add(4),
-    }
-  }
-
   pub fn cause(&self) -> u64 {
     match self {
-      Self::InstAddrMisalign => 0,
-      Self::InstAccessFault => 1,
+      Self::InstAddrMisalign(_) => 0,
+      Self::InstAccessFault(_) => 1,
       Self::IllegalInst(_) => 2,
       Self::Breakpoint => 3,
-      Self::LoadAddrMisalign => 4,
-      Self::LoadAccessFault => 5,
-      Self::StoreAMOAddrMisalign => 6,
-      Self::StoreAMOAccessFault => 7,
+      Self::LoadAddrMisalign(_) => 4,
+      Self::LoadAccessFault(_) => 5,
+      Self::StoreAMOAddrMisalign(_) => 6,
+      Self::StoreAMOAccessFault(_) => 7,
       Self::ECallUser => 8,
       Self::ECallSuper => 9,
       Self::ECallMachine => 11,
@@ -46,24 +38,31 @@ impl Exception {
     }
   }
 
-  pub fn mtval(&self, pc: u64) -> u64 {
+  /// Value written to `*tval`, `pc` is the address of the trapping
+  /// instruction.
+  pub fn tval(&self, pc: u64) -> u64 {
     match *self {
-      Exception::InstAddrMisalign
-      | Exception::InstAccessFault
-      | Exception::Breakpoint
-      | Exception::LoadAddrMisalign
-      | Exception::LoadAccessFault
-      | Exception::StoreAMOAddrMisalign
-      | Exception::StoreAMOAccessFault => pc,
-      Exception::InstPageFault(x)
+      Exception::Breakpoint => pc,
+      Exception::InstAddrMisalign(x)
+      | Exception::InstAccessFault(x)
+      | Exception::LoadAddrMisalign(x)
+      | Exception::LoadAccessFault(x)
+      | Exception::StoreAMOAddrMisalign(x)
+      | Exception::StoreAMOAccessFault(x)
+      | Exception::InstPageFault(x)
       | Exception::LoadPageFault(x)
       | Exception::StoreAMOPageFault(x)
       | Exception::IllegalInst(x) => x,
-      _ => 0,
+      Exception::ECallUser
+      | Exception::ECallSuper
+      | Exception::ECallMachine => 0,
     }
   }
 }
 
+/// Bit of `*cause` set for the traps of interrupts.
+pub const INTERRUPT: u64 = 1 << 63;
+
 #[derive(Debug)]
 pub enum Trap {
   Contained,
@@ -83,12 +82,12 @@ impl Trap {
       | Exception::InstPageFault(_)
       | Exception::LoadPageFault(_)
       | Exception::StoreAMOPageFault(_) => Trap::Invisible,
-      Exception::InstAddrMisalign
-      | Exception::InstAccessFault
-      | Exception::LoadAddrMisalign
-      | Exception::LoadAccessFault
-      | Exception::StoreAMOAddrMisalign
-      | Exception::StoreAMOAccessFault => Trap::Fatal,
+      Exception::InstAddrMisalign(_)
+      | Exception::InstAccessFault(_)
+      | Exception::LoadAddrMisalign(_)
+      | Exception::LoadAccessFault(_)
+      | Exception::StoreAMOAddrMisalign(_)
+      | Exception::StoreAMOAccessFault(_) => Trap::Fatal,
     }
   }
 }
@@ -0,0 +1,104 @@
+use vrisc::{
+  bus::{aplic, imsic_m},
+  dev::{
+    aplic::{idc, reg, Aplic},
+    imsic::iselect,
+  },
+  Cpu, Mode,
+};
+
+const WORD: u8 = 32;
+
+#[test]
+fn imsic() {
+  let mut cpu = Cpu::new(0);
+  let imsic = cpu.bus.imsic_mut(Mode::Machine).unwrap();
+
+  imsic.store_indirect(iselect::EIDELIVERY, 1).unwrap();
+  imsic.store_indirect(iselect::EIE0, !0).unwrap();
+  assert_eq!(None, imsic.store_indirect(iselect::EIE0 + 1, !0));
+
+  cpu.bus.store(imsic_m::ADDR, 9, WORD).unwrap();
+  cpu.bus.store(imsic_m::ADDR, 5, WORD).unwrap();
+
+  let imsic = cpu.bus.imsic_mut(Mode::Machine).unwrap();
+  assert!(imsic.pending());
+  assert_eq!(5 << 16 | 5, imsic.claim());
+  assert_eq!(9 << 16 | 9, imsic.topei());
+
+  imsic.store_indirect(iselect::EITHRESHOLD, 9).unwrap();
+  assert!(!imsic.pending());
+}
+
+#[test]
+fn aplic_direct() {
+  let mut cpu = Cpu::new(0);
+  let bus = &mut cpu.bus;
+
+  bus.store(aplic::ADDR + reg::DOMAINCFG, 1 << 8, WORD).unwrap();
+  bus.store(aplic::ADDR + reg::SOURCECFG + 4 * 2, 6, WORD).unwrap(); // level1
+  bus.store(aplic::ADDR + reg::TARGET + 4 * 2, 3, WORD).unwrap();
+  bus.store(aplic::ADDR + reg::SETIENUM, 3, WORD).unwrap();
+  bus.store(aplic::ADDR + reg::IDC + idc::IDELIVERY, 1, WORD).unwrap();
+
+  bus.set_irq(3, true);
+  let aplic = bus.device::<Aplic>().unwrap();
+  assert!(aplic.irq());
+  assert_eq!(3 << 16 | 3, aplic.topi());
+
+  // the claim leaves an asserted level-sensitive source pending
+  let claimi = aplic::ADDR + reg::IDC + idc::CLAIMI;
+  assert_eq!(3 << 16 | 3, bus.load(claimi, WORD).unwrap());
+  assert_eq!(3 << 16 | 3, bus.device::<Aplic>().unwrap().topi());
+
+  bus.set_irq(3, false);
+  assert!(!bus.device::<Aplic>().unwrap().irq());
+}
+
+#[test]
+fn aplic_msi() {
+  let mut cpu = Cpu::new(0);
+  let bus = &mut cpu.bus;
+
+  let imsic = bus.imsic_mut(Mode::Machine).unwrap();
+  imsic.store_indirect(iselect::EIDELIVERY, 1).unwrap();
+  imsic.store_indirect(iselect::EIE0, !0).unwrap();
+
+  bus.store(aplic::ADDR + reg::DOMAINCFG, 1 << 8 | 1 << 2, WORD).unwrap();
+  bus.store(aplic::ADDR + reg::SOURCECFG + 4 * 6, 4, WORD).unwrap(); // edge1
+  bus.store(aplic::ADDR + reg::TARGET + 4 * 6, 42, WORD).unwrap();
+  bus.store(aplic::ADDR + reg::SETIENUM, 7, WORD).unwrap();
+
+  bus.set_irq(7, true);
+  assert_eq!(42 << 16 | 42, bus.imsic(Mode::Machine).unwrap().topei());
+}
+
+#[test]
+fn aplic_msi_level() {
+  let mut cpu = Cpu::new(0);
+  let bus = &mut cpu.bus;
+
+  let imsic = bus.imsic_mut(Mode::Machine).unwrap();
+  imsic.store_indirect(iselect::EIDELIVERY, 1).unwrap();
+  imsic.store_indirect(iselect::EIE0, !0).unwrap();
+
+  bus.store(aplic::ADDR + reg::DOMAINCFG, 1 << 8 | 1 << 2, WORD).unwrap();
+  bus.store(aplic::ADDR + reg::SOURCECFG + 4 * 4, 6, WORD).unwrap(); // level1
+  bus.store(aplic::ADDR + reg::TARGET + 4 * 4, 42, WORD).unwrap();
+  bus.store(aplic::ADDR + reg::SETIENUM, 5, WORD).unwrap();
+
+  bus.set_irq(5, true);
+  let imsic = bus.imsic_mut(Mode::Machine).unwrap();
+  assert_eq!(42 << 16 | 42, imsic.claim());
+  assert_eq!(0, imsic.topei());
+
+  // the source stays pending while its input is asserted and is forwarded
+  // again by the next write to the domain
+  assert_eq!(1 << 5, bus.load(aplic::ADDR + reg::SETIP, WORD).unwrap());
+  bus.store(aplic::ADDR + reg::CLRIPNUM, 5, WORD).unwrap();
+  bus.tick();
+  assert_eq!(42 << 16 | 42, bus.imsic(Mode::Machine).unwrap().topei());
+
+  bus.set_irq(5, false);
+  assert_eq!(0, bus.load(aplic::ADDR + reg::SETIP, WORD).unwrap());
+}
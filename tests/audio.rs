@@ -0,0 +1,161 @@
+use {
+  std::fs,
+  vrisc::{
+    bus::{audio, dram, plic, AUDIO_IRQ},
+    dev::{
+      audio::{control, reg, status, Audio, Format, Wav},
+      clint::TIMEBASE_FREQ,
+      plic::reg as plic_reg,
+    },
+    Emu,
+  },
+};
+
+const WORD: u8 = 32;
+const LOOP: u32 = 0x0000006f; // jal x0, 0
+const RING: u64 = dram::ADDR + 0x100;
+
+// Emulator playing a frame on each tick from a ring of `size` bytes.
+fn emu(size: u64) -> Emu {
+  let mut emu = Emu::new(0x1000);
+  emu.with_dram(&LOOP.to_le_bytes()).with_pc(dram::ADDR);
+  set(
+    &mut emu,
+    &[
+      (reg::RATE, TIMEBASE_FREQ),
+      (reg::RING_LOW, RING),
+      (reg::RING_HIGH, RING >> 32),
+      (reg::RING_SIZE, size),
+    ],
+  );
+  emu
+}
+
+fn set(emu: &mut Emu, regs: &[(u64, u64)]) {
+  for &(reg, value) in regs {
+    emu.cpu.bus.store(audio::ADDR + reg, value, WORD).unwrap();
+  }
+}
+
+fn get(emu: &mut Emu, reg: u64) -> u64 {
+  emu.cpu.bus.load(audio::ADDR + reg, WORD).unwrap()
+}
+
+fn cycles(emu: &mut Emu, n: usize) {
+  for _ in 0..n {
+    emu.cycle().unwrap();
+  }
+}
+
+fn take(emu: &mut Emu) -> Vec<[i16; 2]> {
+  emu.cpu.bus.device_mut::<Audio>().unwrap().take()
+}
+
+#[test]
+fn play() {
+  let mut emu = emu(16);
+  let samples = [1i16, -1, 0x100, -0x100, 7, 8];
+  for (i, sample) in samples.iter().enumerate() {
+    let at = RING + 2 * i as u64;
+    emu.cpu.bus.store(at, *sample as u16 as u64, 16).unwrap();
+  }
+  set(&mut emu, &[(reg::TAIL, 12), (reg::CONTROL, control::RUN as u64)]);
+
+  cycles(&mut emu, 2);
+  assert_eq!(vec![[1, -1], [0x100, -0x100]], take(&mut emu));
+  assert_eq!(8, get(&mut emu, reg::HEAD));
+
+  // silence once the ring is empty
+  cycles(&mut emu, 2);
+  assert_eq!(vec![[7, 8], [0, 0]], take(&mut emu));
+  assert_eq!(4, get(&mut emu, reg::PLAYED_LOW));
+  assert_eq!(status::EMPTY as u64, get(&mut emu, reg::STATUS));
+}
+
+#[test]
+fn mono() {
+  let mut emu = emu(3);
+  emu.cpu.bus.store(RING, 0x00_ff80, 32).unwrap();
+  emu.cpu.bus.store(RING + 2, 0x40, 8).unwrap();
+  set(
+    &mut emu,
+    &[
+      (reg::FORMAT, Format::U8 as u64),
+      (reg::CHANNELS, 1),
+      (reg::HEAD, 1),
+      // the frames wrap around the end of the ring
+      (reg::TAIL, 0),
+      (reg::CONTROL, control::RUN as u64),
+    ],
+  );
+
+  cycles(&mut emu, 2);
+  assert_eq!(vec![[0x7f00, 0x7f00], [-0x4000, -0x4000]], take(&mut emu));
+  assert_eq!(0, get(&mut emu, reg::HEAD));
+}
+
+#[test]
+fn empty() {
+  let mut emu = emu(12);
+  let pending = |emu: &mut Emu| {
+    let pending = emu.cpu.bus.load(plic::ADDR + plic_reg::PENDING, WORD);
+    pending.unwrap() & 1 << AUDIO_IRQ != 0
+  };
+  let run = (control::RUN | control::EMPTY) as u64;
+  set(&mut emu, &[(reg::TAIL, 8), (reg::CONTROL, run)]);
+
+  cycles(&mut emu, 1);
+  assert!(!pending(&mut emu));
+  cycles(&mut emu, 2);
+  assert!(pending(&mut emu));
+
+  // the guest queues more frames and acknowledges the interrupt
+  set(&mut emu, &[(reg::TAIL, 4), (reg::STATUS, status::EMPTY as u64)]);
+  cycles(&mut emu, 1);
+  assert!(!pending(&mut emu));
+}
+
+#[test]
+fn error() {
+  let mut emu = emu(0x10000);
+  set(&mut emu, &[(reg::TAIL, 4), (reg::CONTROL, control::RUN as u64)]);
+  cycles(&mut emu, 2);
+  assert_eq!(status::ERROR as u64, get(&mut emu, reg::STATUS));
+  assert_eq!(0, get(&mut emu, reg::CONTROL));
+  assert!(take(&mut emu).is_empty());
+}
+
+#[test]
+fn wav() {
+  let path = std::env::temp_dir().join("vrisc-audio.wav");
+  let mut emu = emu(8);
+  emu.cpu.bus.store(RING, 0x0002_0001, 32).unwrap();
+  emu.cpu.bus.device_mut::<Audio>().unwrap().wav =
+    Some(Wav::create(&path).unwrap());
+  set(&mut emu, &[(reg::TAIL, 4), (reg::CONTROL, control::RUN as u64)]);
+  cycles(&mut emu, 2);
+  drop(emu);
+
+  let wav = fs::read(&path).unwrap();
+  fs::remove_file(&path).unwrap();
+  assert_eq!(44 + 8, wav.len());
+  assert_eq!(b"RIFF", &wav[..4]);
+  assert_eq!(44u32.to_le_bytes(), wav[4..8]);
+  assert_eq!((TIMEBASE_FREQ as u32).to_le_bytes(), wav[24..28]);
+  assert_eq!(8u32.to_le_bytes(), wav[40..44]);
+  assert_eq!([1, 0, 2, 0, 0, 0, 0, 0], wav[44..]);
+}
+
+#[test]
+fn wav_rate() {
+  // the byte rate of a rate the header can't hold saturates
+  let path = std::env::temp_dir().join("vrisc-audio-rate.wav");
+  let mut wav = Wav::create(&path).unwrap();
+  wav.write(u32::MAX, [1, 2]).unwrap();
+  drop(wav);
+
+  let wav = fs::read(&path).unwrap();
+  fs::remove_file(&path).unwrap();
+  assert_eq!(u32::MAX.to_le_bytes(), wav[24..28]);
+  assert_eq!(u32::MAX.to_le_bytes(), wav[28..32]);
+}
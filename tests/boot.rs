@@ -0,0 +1,65 @@
+use vrisc::{
+  bus::{dram, rom},
+  dev::rom::{Rom, STUB},
+  Emu, Exception, POINTER_TO_DTB, RESET_VECTOR,
+};
+
+const LOOP: u32 = 0x0000006f; // jal x0, 0
+
+// Run the reset stub, five instructions.
+fn boot(emu: &mut Emu) {
+  for _ in 0..5 {
+    emu.cycle().unwrap();
+  }
+}
+
+#[test]
+fn reset_vector() {
+  let mut emu = Emu::new(0x10000);
+  emu.with_dram(&LOOP.to_le_bytes()).with_fdt().unwrap();
+  assert_eq!(RESET_VECTOR, emu.cpu.pc);
+
+  // the hart ID in a0, the device tree in a1
+  emu.cpu.xregs.store(10, 0xff);
+  emu.cpu.xregs.store(11, 0);
+  boot(&mut emu);
+  assert_eq!(dram::ADDR, emu.cpu.pc);
+  assert_eq!(0, emu.cpu.xregs.load(10));
+  assert_eq!(POINTER_TO_DTB, emu.cpu.xregs.load(11));
+  let magic = emu.cpu.bus.load(POINTER_TO_DTB, 32).unwrap() as u32;
+  assert_eq!(0xd00dfeed, magic.swap_bytes());
+}
+
+#[test]
+fn entry() {
+  let mut emu = Emu::new(0x10000);
+  let entry = dram::ADDR + 0x100;
+  emu.with_entry(entry);
+  let code = [[0; 0x100].as_slice(), &LOOP.to_le_bytes()].concat();
+  emu.with_dram(&code);
+  boot(&mut emu);
+  assert_eq!(entry, emu.cpu.pc);
+
+  // a reset boots through the stub again
+  emu.cycle().unwrap();
+  emu.reset();
+  assert_eq!(RESET_VECTOR, emu.cpu.pc);
+  boot(&mut emu);
+  assert_eq!(entry, emu.cpu.pc);
+
+  let rom = emu.cpu.bus.device::<Rom>().unwrap();
+  assert_eq!(entry.to_le_bytes(), rom.as_slice()[STUB - 8..STUB]);
+}
+
+#[test]
+fn readonly() {
+  let mut emu = Emu::new(0x10000);
+  for (addr, size) in [(RESET_VECTOR, 32), (rom::ADDR + 0x100, 8)] {
+    assert_eq!(
+      Err(Exception::StoreAMOAccessFault(addr)),
+      emu.cpu.bus.store(addr, 0, size)
+    );
+  }
+  // the stub stays intact
+  assert_eq!(0x00000297, emu.cpu.bus.load(RESET_VECTOR, 32).unwrap());
+}
@@ -0,0 +1,92 @@
+use vrisc::{
+  bus::{dram, MapError},
+  dev::Device,
+  Bus, Dram, Exception,
+};
+
+/// Device that remembers the last value written to it.
+#[derive(Debug, Default)]
+struct Latch(u64);
+
+impl Device for Latch {
+  fn load(&mut self, _addr: u64, _size: u8) -> Result<u64, Exception> {
+    Ok(self.0)
+  }
+
+  fn store(
+    &mut self,
+    addr: u64,
+    value: u64,
+    _size: u8,
+  ) -> Result<(), Exception> {
+    self.0 = value + addr;
+    Ok(())
+  }
+}
+
+#[test]
+fn attach() {
+  let mut bus = Bus::with_dram(Dram::with_capacity(0));
+  bus.attach(0x1000, 0x100, Latch::default()).unwrap();
+  bus.attach(0x2000, 0x100, Latch::default()).unwrap();
+
+  bus.store(0x2008, 42, 32).unwrap();
+  assert_eq!(50, bus.load(0x2000, 64).unwrap());
+  assert_eq!(0, bus.load(0x1000, 64).unwrap());
+  assert_eq!(2, bus.devices::<Latch>().count());
+
+  // accesses crossing the end of a mapping fault as a whole
+  assert_eq!(Err(Exception::LoadAccessFault(0x10fc)), bus.load(0x10fc, 64));
+  assert_eq!(Err(Exception::LoadAccessFault(0x3000)), bus.load(0x3000, 8));
+}
+
+#[test]
+fn overlap() {
+  let mut bus = Bus::with_dram(Dram::with_capacity(0));
+  bus.attach(0x1000, 0x100, Latch::default()).unwrap();
+
+  assert_eq!(
+    Err(MapError::Overlap { base: 0x1000, size: 0x100 }),
+    bus.attach(0x10ff, 0x10, Latch::default())
+  );
+  assert_eq!(
+    Err(MapError::Overlap { base: dram::ADDR, size: dram::SIZE }),
+    bus.attach(dram::ADDR - 1, 2, Latch::default())
+  );
+  bus.attach(0x1100, 0x10, Latch::default()).unwrap();
+
+  assert!(bus.detach(0x1000).is_some());
+  bus.attach(0x10ff, 0x1, Latch::default()).unwrap();
+
+  // regions that are empty or wrap around are refused
+  assert_eq!(
+    Err(MapError::Invalid { base: 0x4000, size: 0 }),
+    bus.attach(0x4000, 0, Latch::default())
+  );
+  assert_eq!(
+    Err(MapError::Invalid { base: !0xfff, size: 0x2000 }),
+    bus.attach(!0xfff, 0x2000, Latch::default())
+  );
+}
+
+#[test]
+fn remap() {
+  let mut bus = Bus::with_dram(Dram::with_capacity(0));
+  bus.attach(0x1000, 0x100, Latch::default()).unwrap();
+  bus.attach(0x2000, 0x100, Latch::default()).unwrap();
+  bus.store(0x1000, 1, 32).unwrap();
+
+  assert_eq!(
+    Err(MapError::Overlap { base: 0x2000, size: 0x100 }),
+    bus.remap(0x1000, 0x1f80)
+  );
+  bus.remap(0x1000, 0x3000).unwrap();
+  assert_eq!(1, bus.load(0x3000, 64).unwrap());
+  assert_eq!(Err(Exception::LoadAccessFault(0x1000)), bus.load(0x1000, 64));
+  // the map stays sorted
+  assert_eq!(0, bus.load(0x2000, 64).unwrap());
+  assert_eq!(
+    Err(MapError::Unmapped { base: 0x1000 }),
+    bus.remap(0x1000, 0x4000)
+  );
+}
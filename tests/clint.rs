@@ -0,0 +1,68 @@
+use vrisc::{
+  bus::{clint, dram},
+  csr::{MIP, MSIP_BIT, MTIP_BIT, TIME},
+  dev::clint::{reg, Clint},
+  Emu,
+};
+
+const WORD: u8 = 32;
+const DWORD: u8 = 64;
+const NOP: u32 = 0x00000013; // addi x0, x0, 0
+
+fn emu() -> Emu {
+  let mut emu = Emu::new(0x1000);
+  emu
+    .with_dram(&[NOP; 0x10].map(u32::to_le_bytes).concat())
+    .with_pc(dram::ADDR);
+  emu
+}
+
+#[test]
+fn timer() {
+  let mut emu = emu();
+  emu.cpu.bus.store(clint::ADDR + reg::MTIMECMP, 3, DWORD).unwrap();
+
+  emu.cycle().unwrap();
+  emu.cycle().unwrap();
+  assert_eq!(2, emu.cpu.state.load(TIME));
+  assert_eq!(0, emu.cpu.state.load(MIP) & MTIP_BIT);
+
+  emu.cycle().unwrap();
+  assert_eq!(3, emu.cpu.bus.load(clint::ADDR + reg::MTIME, DWORD).unwrap());
+  assert_eq!(MTIP_BIT, emu.cpu.state.load(MIP) & MTIP_BIT);
+
+  // the upper half alone pushes the deadline away
+  emu.cpu.bus.store(clint::ADDR + reg::MTIMECMP + 4, 1, WORD).unwrap();
+  emu.cycle().unwrap();
+  assert_eq!(0, emu.cpu.state.load(MIP) & MTIP_BIT);
+}
+
+#[test]
+fn software() {
+  let mut emu = emu();
+  emu.cpu.bus.store(clint::ADDR + reg::MSIP, 1, WORD).unwrap();
+  emu.cycle().unwrap();
+  assert_eq!(MSIP_BIT, emu.cpu.state.load(MIP) & MSIP_BIT);
+
+  emu.cpu.bus.store(clint::ADDR + reg::MSIP, 0, WORD).unwrap();
+  emu.cycle().unwrap();
+  assert_eq!(0, emu.cpu.state.load(MIP) & MSIP_BIT);
+}
+
+#[test]
+fn time() {
+  let mut emu = emu();
+  emu.cpu.bus.store(clint::ADDR + reg::MTIME, 100, DWORD).unwrap();
+  emu.cycle().unwrap();
+  assert_eq!(101, emu.cpu.state.load(TIME));
+}
+
+#[test]
+fn no_harts() {
+  let mut emu = emu();
+  emu.cpu.bus.detach(clint::ADDR);
+  emu.cpu.bus.attach(clint::ADDR, Clint::SIZE, Clint::new(0)).unwrap();
+  emu.cycle().unwrap();
+  emu.cycle().unwrap();
+  assert_eq!(0, emu.cpu.state.load(MIP) & (MSIP_BIT | MTIP_BIT));
+}
@@ -0,0 +1,93 @@
+use vrisc::{
+  bus::{dram, plic},
+  csr::{MENVCFG, MIP, SEIP_BIT, STIMECMP, STIP_BIT, TIME},
+  dev::plic::reg,
+  Emu, Exception, Mode, State,
+};
+
+#[test]
+fn sstc() {
+  let mut state = State::new();
+  state.store(STIMECMP, 2);
+
+  // without `menvcfg.STCE` the timer is left to the firmware
+  state.cycle_time();
+  state.cycle_time();
+  assert_eq!(0, state.load(MIP) & STIP_BIT);
+
+  state.store(MENVCFG, 1 << 63);
+  assert_eq!(2, state.load(TIME));
+  assert_eq!(STIP_BIT, state.load(MIP) & STIP_BIT);
+
+  // software can't clear the pending timer interrupt
+  state.store(MIP, 0);
+  assert_eq!(STIP_BIT, state.load(MIP) & STIP_BIT);
+
+  state.store(STIMECMP, 3);
+  assert_eq!(0, state.load(MIP) & STIP_BIT);
+  state.cycle_time();
+  assert_eq!(STIP_BIT, state.load(MIP) & STIP_BIT);
+}
+
+#[test]
+fn stimecmp_access() {
+  let program: [u32; 2] = [
+    0x14d020f3, // csrrs x1, stimecmp, x0
+    0x14d09073, // csrrw x0, stimecmp, x1
+  ];
+  let mut emu = Emu::new(0x1000);
+  emu.with_dram(&program.map(u32::to_le_bytes).concat()).with_pc(dram::ADDR);
+
+  // below M-mode the register is illegal until `menvcfg.STCE` is set
+  emu.cpu.mode = Mode::Supervisor;
+  for (i, inst) in program.into_iter().enumerate() {
+    emu.cpu.pc = dram::ADDR + 4 * i as u64;
+    assert_eq!(Err(Exception::IllegalInst(inst as u64)), emu.cycle());
+  }
+
+  emu.cpu.pc = dram::ADDR;
+  emu.cpu.state.store(MENVCFG, 1 << 63);
+  emu.cycle().unwrap();
+  assert_eq!(u64::MAX, emu.cpu.xregs.load(1));
+  emu.cycle().unwrap();
+
+  // M-mode always reaches it
+  emu.cpu.pc = dram::ADDR;
+  emu.cpu.mode = Mode::Machine;
+  emu.cpu.state.store(MENVCFG, 0);
+  emu.cycle().unwrap();
+  emu.cycle().unwrap();
+}
+
+#[test]
+fn seip() {
+  let program: [u32; 5] = [
+    0x20000093, // addi x1, x0, 0x200
+    0x3440a073, // csrrs x0, mip, x1
+    0x00000013, // nop
+    0x3440b173, // csrrc x2, mip, x1
+    0x00000013, // nop
+  ];
+  let mut emu = Emu::new(0x1000);
+  emu.with_dram(&program.map(u32::to_le_bytes).concat()).with_pc(dram::ADDR);
+  let bus = &mut emu.cpu.bus;
+  bus.store(plic::ADDR + reg::PRIORITY + 4 * 3, 1, 32).unwrap();
+  bus.store(plic::ADDR + reg::ENABLE + 0x80, 1 << 3, 32).unwrap();
+
+  // the devices don't overwrite the bit software sets
+  for _ in 0..3 {
+    emu.cycle().unwrap();
+  }
+  assert_eq!(SEIP_BIT, emu.cpu.state.load(MIP) & SEIP_BIT);
+
+  // the external line is ORed in, and outlives the software bit
+  emu.cpu.bus.set_irq(3, true);
+  emu.cycle().unwrap();
+  assert_eq!(SEIP_BIT, emu.cpu.xregs.load(2) & SEIP_BIT);
+  assert_eq!(0, emu.cpu.state.load_software_mip() & SEIP_BIT);
+  assert_eq!(SEIP_BIT, emu.cpu.state.load(MIP) & SEIP_BIT);
+
+  emu.cpu.bus.set_irq(3, false);
+  emu.cycle().unwrap();
+  assert_eq!(0, emu.cpu.state.load(MIP) & SEIP_BIT);
+}
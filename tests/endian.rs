@@ -0,0 +1,43 @@
+use vrisc::{bus::dram, csr::x, Emu};
+
+const PROGRAM: [u32; 3] = [
+  0x00312023, // sw x3, 0(x2)
+  0x00012203, // lw x4, 0(x2)
+  0x00015283, // lhu x5, 0(x2)
+];
+
+fn run(big_endian: bool) -> Emu {
+  let mut emu = Emu::new(0x1000);
+  let bytes: Vec<u8> = PROGRAM.iter().flat_map(|x| x.to_le_bytes()).collect();
+  emu.with_dram(&bytes).with_pc(dram::ADDR);
+
+  emu.cpu.state.store_mstatus(x::MBE, big_endian as u64);
+  emu.cpu.xregs.store(2, dram::ADDR + 0x100);
+  emu.cpu.xregs.store(3, 0x11223344);
+  for _ in PROGRAM {
+    emu.cycle().unwrap();
+  }
+  emu
+}
+
+#[test]
+fn little() {
+  let emu = run(false);
+  assert_eq!(
+    [0x44, 0x33, 0x22, 0x11],
+    emu.cpu.bus.dram.as_slice()[0x100..0x104]
+  );
+  assert_eq!(0x11223344, emu.cpu.xregs.load(4));
+  assert_eq!(0x3344, emu.cpu.xregs.load(5));
+}
+
+#[test]
+fn big() {
+  let emu = run(true);
+  assert_eq!(
+    [0x11, 0x22, 0x33, 0x44],
+    emu.cpu.bus.dram.as_slice()[0x100..0x104]
+  );
+  assert_eq!(0x11223344, emu.cpu.xregs.load(4));
+  assert_eq!(0x1122, emu.cpu.xregs.load(5));
+}
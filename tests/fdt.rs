@@ -0,0 +1,203 @@
+use {
+  std::collections::HashMap,
+  vrisc::{
+    bus::{
+      dram, plic, rom, rtc, syscon, uart, vga, virtio, RTC_IRQ, UART_IRQ,
+      VIRTIO_IRQ,
+    },
+    dev::{
+      rom::Rom,
+      serial::Null,
+      vga::{Format, Mode, Vga},
+      virtio::{console::Console, rng::Rng, MMIO_SIZE},
+    },
+    Emu, Exception, FdtError, POINTER_TO_DTB,
+  },
+};
+
+type Props = HashMap<String, Vec<u8>>;
+
+fn be32(blob: &[u8], at: usize) -> u32 {
+  u32::from_be_bytes(blob[at..at + 4].try_into().unwrap())
+}
+
+fn cstr(blob: &[u8], at: usize) -> &str {
+  let len = blob[at..].iter().position(|&b| b == 0).unwrap();
+  std::str::from_utf8(&blob[at..at + len]).unwrap()
+}
+
+// Properties of every node by path.
+fn parse(blob: &[u8]) -> HashMap<String, Props> {
+  assert_eq!(0xd00dfeed, be32(blob, 0));
+  let (structure, strings) = (be32(blob, 8) as usize, be32(blob, 12) as usize);
+
+  let mut nodes = HashMap::new();
+  let mut path = Vec::new();
+  let mut at = structure;
+  loop {
+    let token = be32(blob, at);
+    at += 4;
+    match token {
+      1 => {
+        let name = cstr(blob, at);
+        at = (at + name.len() + 1).next_multiple_of(4);
+        path.push(name.to_owned());
+        nodes.insert(path.join("/"), Props::new());
+      }
+      2 => {
+        path.pop();
+      }
+      3 => {
+        let (len, name) = (be32(blob, at) as usize, be32(blob, at + 4));
+        let value = blob[at + 8..at + 8 + len].to_vec();
+        at = (at + 8 + len).next_multiple_of(4);
+        let name = cstr(blob, strings + name as usize).to_owned();
+        nodes.get_mut(&path.join("/")).unwrap().insert(name, value);
+      }
+      9 => return nodes,
+      _ => panic!("unexpected token {token}"),
+    }
+  }
+}
+
+fn dtb(emu: &mut Emu) -> Vec<u8> {
+  let load = |emu: &mut Emu, at| {
+    let word = emu.cpu.bus.load(POINTER_TO_DTB + at, 32).unwrap() as u32;
+    word.swap_bytes()
+  };
+  let total = load(emu, 4) as u64;
+  (0..total)
+    .map(|at| emu.cpu.bus.load(POINTER_TO_DTB + at, 8).unwrap() as u8)
+    .collect()
+}
+
+#[test]
+fn machine() {
+  let mut emu = Emu::new(0x10000);
+  emu.with_fdt().unwrap();
+  let nodes = parse(&dtb(&mut emu));
+
+  let memory = &nodes[&format!("/memory@{:x}", dram::ADDR)];
+  let reg = [dram::ADDR, 0x10000].map(u64::to_be_bytes).concat();
+  assert_eq!(reg, memory["reg"]);
+
+  let cpu = &nodes["/cpus/cpu@0"];
+  // only the extensions the hart decodes, never C
+  assert!(cpu["riscv,isa"].starts_with(b"rv64ia_zicsr_zifencei_sstc"));
+  let extensions = &cpu["riscv,isa-extensions"];
+  assert!(!extensions.split(|&b| b == 0).any(|ext| ext == b"c"));
+  assert!(nodes["/cpus"].contains_key("timebase-frequency"));
+
+  let serial = format!("/soc/serial@{:x}", uart::ADDR);
+  assert_eq!(
+    (UART_IRQ as u32).to_be_bytes(),
+    &nodes[&serial]["interrupts"][..]
+  );
+  assert_eq!(format!("{serial}\0").as_bytes(), nodes["/chosen"]["stdout-path"]);
+
+  // the PLIC takes the external interrupts, the AIA is left out
+  let plic = &nodes[&format!("/soc/plic@{:x}", plic::ADDR)];
+  assert_eq!(plic["phandle"], nodes[""]["interrupt-parent"]);
+  assert!(!nodes.keys().any(|path| path.contains("aplic")));
+}
+
+#[test]
+fn rom() {
+  let mut emu = Emu::new(0);
+  emu.with_fdt().unwrap();
+  assert_eq!(
+    Err(Exception::StoreAMOAccessFault(POINTER_TO_DTB)),
+    emu.cpu.bus.store(POINTER_TO_DTB, 0, 32)
+  );
+
+  // without a PLIC the devices hang off the APLIC
+  emu.cpu.bus.detach(plic::ADDR);
+  emu.with_fdt().unwrap();
+  let nodes = parse(&dtb(&mut emu));
+  let serial = &nodes[&format!("/soc/serial@{:x}", uart::ADDR)];
+  assert_eq!(8, serial["interrupts"].len());
+  assert!(nodes.keys().any(|path| path.contains("aplic")));
+}
+
+#[test]
+fn rom_missing() {
+  let mut emu = Emu::new(0);
+  emu.cpu.bus.detach(rom::ADDR);
+  assert!(matches!(emu.with_fdt(), Err(FdtError::NoRom)));
+
+  // a ROM with no room left after the reset stub
+  let size = POINTER_TO_DTB - rom::ADDR + 0x10;
+  emu.cpu.bus.attach(rom::ADDR, size, Rom::new(size as usize)).unwrap();
+  assert!(matches!(emu.with_fdt(), Err(FdtError::TooLarge(_))));
+}
+
+#[test]
+fn virtio() {
+  let mut emu = Emu::new(0x10000);
+  emu.cpu.bus.attach_virtio(Console::new(Null)).unwrap();
+  emu.cpu.bus.attach_virtio(Rng::new(None)).unwrap();
+  emu.with_fdt().unwrap();
+  let nodes = parse(&dtb(&mut emu));
+
+  // a node per transport, each on its own interrupt line
+  for slot in 0..2 {
+    let base = virtio::ADDR + slot * MMIO_SIZE;
+    let node = &nodes[&format!("/soc/virtio_mmio@{base:x}")];
+    assert_eq!(b"virtio,mmio\0", &node["compatible"][..]);
+    let irq = VIRTIO_IRQ as u32 + slot as u32;
+    assert_eq!(irq.to_be_bytes(), &node["interrupts"][..]);
+  }
+  assert_eq!(2, nodes.keys().filter(|path| path.contains("virtio")).count());
+}
+
+#[test]
+fn framebuffer() {
+  let mut emu = Emu::new(0x10000);
+  let mode = Mode::packed(640, 480, Format::Xrgb8888);
+  emu.cpu.bus.device_mut::<Vga>().unwrap().set_mode(mode);
+  emu.with_fdt().unwrap();
+  let nodes = parse(&dtb(&mut emu));
+
+  let fb = &nodes[&format!("/soc/framebuffer@{:x}", vga::ADDR)];
+  assert_eq!(b"simple-framebuffer\0", &fb["compatible"][..]);
+  let reg = [vga::ADDR, 640 * 4 * 480].map(u64::to_be_bytes).concat();
+  assert_eq!(reg, fb["reg"]);
+  assert_eq!(640u32.to_be_bytes(), &fb["width"][..]);
+  assert_eq!(2560u32.to_be_bytes(), &fb["stride"][..]);
+  assert_eq!(b"x8r8g8b8\0", &fb["format"][..]);
+}
+
+#[test]
+fn text_framebuffer() {
+  // the default machine starts in text mode with a graphics mode to leave to
+  let mut emu = Emu::new(0x10000);
+  assert!(emu.cpu.bus.device::<Vga>().unwrap().is_text());
+  emu.with_fdt().unwrap();
+  let nodes = parse(&dtb(&mut emu));
+
+  let fb = &nodes[&format!("/soc/framebuffer@{:x}", vga::ADDR)];
+  assert_eq!(b"simple-framebuffer\0", &fb["compatible"][..]);
+  assert_eq!(Vga::MODE.width.to_be_bytes(), &fb["width"][..]);
+  assert_eq!(Vga::MODE.height.to_be_bytes(), &fb["height"][..]);
+}
+
+#[test]
+fn power() {
+  let mut emu = Emu::new(0x10000);
+  emu.with_fdt().unwrap();
+  let nodes = parse(&dtb(&mut emu));
+
+  let rtc = &nodes[&format!("/soc/rtc@{:x}", rtc::ADDR)];
+  assert_eq!(b"google,goldfish-rtc\0", &rtc["compatible"][..]);
+  assert_eq!((RTC_IRQ as u32).to_be_bytes(), &rtc["interrupts"][..]);
+
+  // poweroff and reboot go through the register of the test finisher
+  let test = &nodes[&format!("/soc/test@{:x}", syscon::ADDR)];
+  assert!(test["compatible"].ends_with(b"syscon\0"));
+  for (node, value) in [("poweroff", 0x5555u32), ("reboot", 0x7777)] {
+    let node = &nodes[&format!("/soc/{node}")];
+    assert_eq!(test["phandle"], node["regmap"]);
+    assert_eq!(0u32.to_be_bytes(), &node["offset"][..]);
+    assert_eq!(value.to_be_bytes(), &node["value"][..]);
+  }
+}
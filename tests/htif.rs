@@ -0,0 +1,196 @@
+use {
+  std::sync::{Arc, Mutex},
+  vrisc::{bus::dram, dev::serial::Serial, Emu, Exit, Htif},
+};
+
+const DWORD: u8 = 64;
+
+#[rustfmt::skip]
+const PROGRAM: [u32; 8] = [
+  0x00000013, // nop                  ; x1 holds tohost
+  0x10100113, // addi x2, x0, 0x101
+  0x03011113, // slli x2, x2, 48      ; console putchar
+  0x04110113, // addi x2, x2, 0x41
+  0x0020b023, // sd x2, 0(x1)
+  0x00700193, // addi x3, x0, 7       ; exit 3
+  0x0030b023, // sd x3, 0(x1)
+  0x0000006f, // jal x0, 0
+];
+
+/// Console that records the output of the guest.
+#[derive(Debug, Clone, Default)]
+struct Console(Arc<Mutex<Vec<u8>>>);
+
+impl Serial for Console {
+  fn read(&mut self) -> Option<u8> {
+    None
+  }
+
+  fn write(&mut self, byte: u8) {
+    self.0.lock().unwrap().push(byte);
+  }
+}
+
+fn code() -> Vec<u8> {
+  PROGRAM.iter().flat_map(|inst| inst.to_le_bytes()).collect()
+}
+
+// Executable with a single segment holding `code` at the start of the main
+// memory, running at `vaddr`, and `tohost`/`fromhost` symbols right after its
+// first page.
+fn elf(code: &[u8], vaddr: u64) -> Vec<u8> {
+  const CODE: usize = 0x100;
+  let strtab = b"\0tohost\0fromhost\0";
+  let shstrtab = b"\0.symtab\0.strtab\0.shstrtab\0";
+
+  let symbol = |name: u32, value: u64| {
+    let mut sym = Vec::new();
+    sym.extend(name.to_le_bytes());
+    sym.extend([0x11, 0]); // global object
+    sym.extend(0xfff1u16.to_le_bytes()); // absolute
+    sym.extend(value.to_le_bytes());
+    sym.extend(8u64.to_le_bytes());
+    sym
+  };
+  let mut symtab = vec![0; 24];
+  symtab.extend(symbol(1, dram::ADDR + 0x1000));
+  symtab.extend(symbol(8, dram::ADDR + 0x1008));
+
+  let symtab_off = CODE + code.len();
+  let strtab_off = symtab_off + symtab.len();
+  let shstrtab_off = strtab_off + strtab.len();
+  let shoff = (shstrtab_off + shstrtab.len()).next_multiple_of(8);
+
+  let mut elf = Vec::new();
+  elf.extend(b"\x7fELF\x02\x01\x01\0\0\0\0\0\0\0\0\0");
+  elf.extend(2u16.to_le_bytes()); // executable
+  elf.extend(0xf3u16.to_le_bytes()); // risc-v
+  elf.extend(1u32.to_le_bytes());
+  elf.extend(dram::ADDR.to_le_bytes()); // entry
+  elf.extend(64u64.to_le_bytes()); // program headers
+  elf.extend((shoff as u64).to_le_bytes());
+  elf.extend(0u32.to_le_bytes());
+  for half in [64u16, 56, 1, 64, 4, 3] {
+    elf.extend(half.to_le_bytes());
+  }
+
+  // PT_LOAD, rwx
+  elf.extend(1u32.to_le_bytes());
+  elf.extend(7u32.to_le_bytes());
+  let (filesz, memsz) = (code.len() as u64, 0x2000u64);
+  for word in [CODE as u64, vaddr, dram::ADDR, filesz, memsz, 0x1000] {
+    elf.extend(word.to_le_bytes());
+  }
+
+  elf.resize(CODE, 0);
+  elf.extend(code);
+  elf.extend(&symtab);
+  elf.extend(strtab);
+  elf.extend(shstrtab);
+  elf.resize(shoff, 0);
+
+  let section = |name: u32, kind: u32, off: usize, size: usize, link: u32| {
+    let mut sh = Vec::new();
+    sh.extend(name.to_le_bytes());
+    sh.extend(kind.to_le_bytes());
+    sh.extend(0u64.to_le_bytes());
+    sh.extend(0u64.to_le_bytes());
+    sh.extend((off as u64).to_le_bytes());
+    sh.extend((size as u64).to_le_bytes());
+    sh.extend(link.to_le_bytes());
+    sh.extend(u32::from(link != 0).to_le_bytes()); // first global symbol
+    sh.extend(8u64.to_le_bytes());
+    sh.extend(if kind == 2 { 24u64 } else { 0 }.to_le_bytes());
+    sh
+  };
+  elf.extend(vec![0; 64]);
+  elf.extend(section(1, 2, symtab_off, symtab.len(), 2));
+  elf.extend(section(9, 3, strtab_off, strtab.len(), 0));
+  elf.extend(section(17, 3, shstrtab_off, shstrtab.len(), 0));
+  elf
+}
+
+#[test]
+fn symbols() {
+  let console = Console::default();
+  let mut emu = Emu::new(0x4000);
+  emu.load_elf(&elf(&code(), dram::ADDR)).unwrap();
+
+  let htif = emu.htif.as_mut().unwrap();
+  assert_eq!(
+    (dram::ADDR + 0x1000, Some(dram::ADDR + 0x1008)),
+    (htif.tohost, htif.fromhost)
+  );
+  htif.console = Box::new(console.clone());
+  emu.cpu.xregs.store(1, dram::ADDR + 0x1000);
+
+  assert_eq!(Exit::Fail(3), emu.run());
+  assert_eq!(b"A", &console.0.lock().unwrap()[..]);
+  // the console acknowledged the character
+  let fromhost = emu.cpu.bus.load(dram::ADDR + 0x1008, DWORD).unwrap();
+  assert_eq!(1 << 56 | 1 << 48, fromhost);
+}
+
+#[test]
+fn physical() {
+  let mut emu = Emu::new(0x4000);
+  emu.load_elf(&elf(&code(), 0xffff_ffff_8000_0000)).unwrap();
+  let first = emu.cpu.bus.load(dram::ADDR, 32).unwrap();
+  assert_eq!(PROGRAM[0] as u64, first);
+}
+
+#[test]
+fn configured() {
+  let mut emu = Emu::new(0x4000);
+  emu.with_dram(&code()).with_pc(dram::ADDR).with_htif(Htif::new(
+    dram::ADDR + 0x1000,
+    None,
+    Console::default(),
+  ));
+  emu.cpu.xregs.store(1, dram::ADDR + 0x1000);
+
+  for _ in 0..5 {
+    emu.cycle().unwrap();
+  }
+  assert_eq!(None, emu.exit());
+  emu.cycle().unwrap();
+  emu.cycle().unwrap();
+  assert_eq!(Some(Exit::Fail(3)), emu.exit());
+}
+
+#[test]
+fn syscall() {
+  let console = Console::default();
+  let mut emu = Emu::new(0x4000);
+  emu.with_htif(Htif::new(dram::ADDR + 0x1000, None, console.clone()));
+
+  let bus = &mut emu.cpu.bus;
+  bus.dram.as_slice_mut()[0x300..0x303].copy_from_slice(b"ok\n");
+  for (i, arg) in [64, 1, dram::ADDR + 0x300, 3].into_iter().enumerate() {
+    bus.store(dram::ADDR + 0x200 + 8 * i as u64, arg, DWORD).unwrap();
+  }
+  bus.store(dram::ADDR + 0x1000, dram::ADDR + 0x200, DWORD).unwrap();
+
+  let htif = emu.htif.as_mut().unwrap();
+  assert_eq!(None, htif.poll(&mut emu.cpu.bus));
+  assert_eq!(b"ok\n", &console.0.lock().unwrap()[..]);
+  assert_eq!(3, emu.cpu.bus.load(dram::ADDR + 0x200, DWORD).unwrap());
+
+  // exit(0) through the proxy
+  for (i, arg) in [93, 0].into_iter().enumerate() {
+    let bus = &mut emu.cpu.bus;
+    bus.store(dram::ADDR + 0x200 + 8 * i as u64, arg, DWORD).unwrap();
+  }
+  let bus = &mut emu.cpu.bus;
+  bus.store(dram::ADDR + 0x1000, dram::ADDR + 0x200, DWORD).unwrap();
+  assert_eq!(Some(0), htif.poll(bus));
+
+  // a buffer wrapping around the address space
+  for (i, arg) in [64, 1, !0 - 1, 4].into_iter().enumerate() {
+    bus.store(dram::ADDR + 0x200 + 8 * i as u64, arg, DWORD).unwrap();
+  }
+  bus.store(dram::ADDR + 0x1000, dram::ADDR + 0x200, DWORD).unwrap();
+  assert_eq!(None, htif.poll(bus));
+  let ret = bus.load(dram::ADDR + 0x200, DWORD).unwrap();
+  assert_eq!(-14, ret as i64);
+}
@@ -0,0 +1,57 @@
+use vrisc::{bus::dram, Emu, Exception, Misaligned};
+
+const LW: u32 = 0x00012203; // lw x4, 0(x2)
+const LD: u32 = 0x00013203; // ld x4, 0(x2)
+const AMOADD_W: u32 = 0x0031222f; // amoadd.w x4, x3, (x2)
+
+fn run(
+  inst: u32,
+  addr: u64,
+  misaligned: Misaligned,
+) -> (Emu, Result<u64, Exception>) {
+  let mut emu = Emu::new(0x1000);
+  emu
+    .with_dram(&inst.to_le_bytes())
+    .with_pc(dram::ADDR)
+    .with_misaligned(misaligned);
+  emu.cpu.bus.dram.as_slice_mut()[0x100..0x108]
+    .copy_from_slice(&[1, 2, 3, 4, 5, 6, 7, 8]);
+
+  emu.cpu.xregs.store(2, addr);
+  emu.cpu.xregs.store(3, 1);
+  let result = emu.cycle();
+  (emu, result)
+}
+
+#[test]
+fn emulate() {
+  let (emu, result) = run(LW, dram::ADDR + 0x101, Misaligned::Emulate);
+  assert!(result.is_ok());
+  assert_eq!(0x05040302, emu.cpu.xregs.load(4));
+}
+
+#[test]
+fn trap() {
+  let addr = dram::ADDR + 0x101;
+  let (_, result) = run(LW, addr, Misaligned::Trap);
+  assert_eq!(Err(Exception::LoadAddrMisalign(addr)), result);
+}
+
+#[test]
+fn end_of_ram() {
+  let addr = dram::ADDR + 0xffc;
+  let (_, result) = run(LD, addr, Misaligned::Emulate);
+  assert_eq!(Err(Exception::LoadAccessFault(addr)), result);
+}
+
+#[test]
+fn atomics() {
+  let addr = dram::ADDR + 0x102;
+  let (_, result) = run(AMOADD_W, addr, Misaligned::Emulate);
+  assert_eq!(Err(Exception::StoreAMOAddrMisalign(addr)), result);
+
+  let (emu, result) = run(AMOADD_W, dram::ADDR + 0x100, Misaligned::Emulate);
+  assert!(result.is_ok());
+  assert_eq!(0x04030201, emu.cpu.xregs.load(4));
+  assert_eq!([2, 2, 3, 4], emu.cpu.bus.dram.as_slice()[0x100..0x104]);
+}
@@ -0,0 +1,300 @@
+use {
+  std::{
+    fs,
+    io::{Read, Write},
+    net::{Ipv4Addr, TcpListener, UdpSocket},
+    time::{Duration, Instant},
+  },
+  vrisc::{
+    bus::{dram, virtio},
+    dev::virtio::{
+      net::{Backend, Loopback, Net, Pcap, MAC},
+      reg,
+      slirp::{User, GATEWAY, GATEWAY_MAC, GUEST},
+    },
+    Bus, Emu,
+  },
+};
+
+const WORD: u8 = 32;
+
+fn be16(data: &[u8], at: usize) -> u16 {
+  u16::from_be_bytes([data[at], data[at + 1]])
+}
+
+fn be32(data: &[u8], at: usize) -> u32 {
+  u32::from_be_bytes(data[at..at + 4].try_into().unwrap())
+}
+
+fn ethernet(ethertype: u16, payload: &[u8]) -> Vec<u8> {
+  [&GATEWAY_MAC[..], &MAC, &ethertype.to_be_bytes(), payload].concat()
+}
+
+// IPv4 packet from the guest to the gateway, checksums are left out.
+fn ipv4(proto: u8, data: &[u8]) -> Vec<u8> {
+  let mut packet = vec![0x45, 0];
+  packet.extend(((20 + data.len()) as u16).to_be_bytes());
+  packet.extend([0, 0, 0, 0, 64, proto, 0, 0]);
+  packet.extend(GUEST.octets());
+  packet.extend(GATEWAY.octets());
+  packet.extend(data);
+  ethernet(0x0800, &packet)
+}
+
+fn udp(sport: u16, dport: u16, data: &[u8]) -> Vec<u8> {
+  let mut datagram = [sport.to_be_bytes(), dport.to_be_bytes()].concat();
+  datagram.extend(((8 + data.len()) as u16).to_be_bytes());
+  datagram.extend([0, 0]);
+  datagram.extend(data);
+  ipv4(17, &datagram)
+}
+
+fn tcp(
+  ports: (u16, u16),
+  seq: u32,
+  ack: u32,
+  flags: u8,
+  data: &[u8],
+) -> Vec<u8> {
+  let mut segment = [ports.0.to_be_bytes(), ports.1.to_be_bytes()].concat();
+  segment.extend(seq.to_be_bytes());
+  segment.extend(ack.to_be_bytes());
+  segment.extend([5 << 4, flags, 0xff, 0xff, 0, 0, 0, 0]);
+  segment.extend(data);
+  ipv4(6, &segment)
+}
+
+// Next frame for the guest, waiting for the host sockets.
+fn recv(user: &mut User) -> Vec<u8> {
+  let start = Instant::now();
+  loop {
+    if let Some(frame) = user.recv() {
+      return frame;
+    }
+    assert!(start.elapsed() < Duration::from_secs(5), "no frame");
+  }
+}
+
+// Payload of an IPv4 frame.
+fn ip(frame: &[u8]) -> &[u8] {
+  assert_eq!(0x0800, be16(frame, 12));
+  &frame[34..]
+}
+
+#[test]
+fn arp() {
+  let mut user = User::new();
+  let mut request = vec![0, 1, 8, 0, 6, 4, 0, 1];
+  request.extend(MAC);
+  request.extend(GUEST.octets());
+  request.extend([0; 6]);
+  request.extend(GATEWAY.octets());
+  user.send(&ethernet(0x0806, &request));
+
+  let reply = recv(&mut user);
+  assert_eq!(MAC, reply[..6]);
+  assert_eq!(2, be16(&reply, 20));
+  assert_eq!(GATEWAY_MAC, reply[22..28]);
+  assert_eq!(GATEWAY.octets(), reply[28..32]);
+
+  // ping the gateway
+  user.send(&ipv4(1, &[8, 0, 0, 0, 0x12, 0x34, 0, 1, b'x']));
+  let reply = recv(&mut user);
+  assert_eq!([0, 0], reply[34..36]);
+  assert_eq!(b'x', reply[42]);
+}
+
+#[test]
+fn dhcp() {
+  let mut user = User::new();
+  let mut discover = vec![0; 240];
+  discover[..3].copy_from_slice(&[1, 1, 6]);
+  discover[4..8].copy_from_slice(&0xdead_beef_u32.to_be_bytes());
+  discover[28..34].copy_from_slice(&MAC);
+  discover[236..240].copy_from_slice(&[99, 130, 83, 99]);
+  discover.extend([53, 1, 1, 255]);
+  user.send(&udp(68, 67, &discover));
+
+  let offer = recv(&mut user);
+  assert_eq!([0xff; 6], offer[..6]);
+  let offer = &ip(&offer)[8..];
+  assert_eq!(2, offer[0]);
+  assert_eq!(0xdead_beef, be32(offer, 4));
+  assert_eq!(GUEST.octets(), offer[16..20]);
+  assert_eq!([53, 1, 2], offer[240..243]);
+}
+
+#[test]
+fn udp_forward() {
+  let host = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+  host.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+  let port = host.local_addr().unwrap().port();
+
+  let mut user = User::new();
+  user.send(&udp(5000, port, b"ping"));
+  let mut buf = [0; 16];
+  let (len, from) = host.recv_from(&mut buf).unwrap();
+  assert_eq!(b"ping", &buf[..len]);
+
+  host.send_to(b"pong", from).unwrap();
+  let frame = recv(&mut user);
+  let datagram = ip(&frame);
+  assert_eq!((port, 5000), (be16(datagram, 0), be16(datagram, 2)));
+  assert_eq!(b"pong", &datagram[8..]);
+}
+
+#[test]
+fn tcp_forward() {
+  const SYN: u8 = 2;
+  const ACK: u8 = 16;
+  const FIN: u8 = 1;
+  let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+  let port = listener.local_addr().unwrap().port();
+  let ports = (5000, port);
+
+  let mut user = User::new();
+  user.send(&tcp(ports, 100, 0, SYN, &[]));
+  let frame = recv(&mut user);
+  let segment = ip(&frame);
+  assert_eq!(SYN | ACK, segment[13]);
+  assert_eq!(101, be32(segment, 8));
+  let mut seq = be32(segment, 4).wrapping_add(1);
+
+  let (mut stream, _) = listener.accept().unwrap();
+  stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+  user.send(&tcp(ports, 101, seq, ACK, b"ping"));
+  let segment = ip(&recv(&mut user)).to_vec();
+  assert_eq!(105, be32(&segment, 8));
+
+  // the data reaches the host on the next poll of the sockets
+  for _ in 0..1024 {
+    assert_eq!(None, user.recv());
+  }
+  let mut buf = [0; 4];
+  stream.read_exact(&mut buf).unwrap();
+  assert_eq!(b"ping", &buf);
+
+  stream.write_all(b"pong").unwrap();
+  drop(stream);
+  let frame = recv(&mut user);
+  let segment = ip(&frame);
+  assert_eq!(seq, be32(segment, 4));
+  assert_eq!(b"pong", &segment[20..]);
+  seq += 4;
+
+  let frame = recv(&mut user);
+  let segment = ip(&frame);
+  assert_eq!(FIN, segment[13] & FIN);
+  assert_eq!(seq, be32(segment, 4));
+}
+
+#[test]
+fn tcp_retransmit() {
+  const SYN: u8 = 2;
+  const RST: u8 = 4;
+  const ACK: u8 = 16;
+  let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+  let port = listener.local_addr().unwrap().port();
+  let ports = (5001, port);
+
+  let mut user = User::new();
+  user.send(&tcp(ports, 100, 0, SYN, &[]));
+  let segment = ip(&recv(&mut user)).to_vec();
+  assert_eq!(SYN | ACK, segment[13]);
+  let seq = be32(&segment, 4).wrapping_add(1);
+  user.send(&tcp(ports, 101, seq, ACK, &[]));
+
+  // data the guest doesn't acknowledge comes again, and again only once
+  let (mut stream, _) = listener.accept().unwrap();
+  stream.write_all(b"pong").unwrap();
+  let first = recv(&mut user);
+  assert_eq!(b"pong", &ip(&first)[20..]);
+  assert_eq!(ip(&first), ip(&recv(&mut user)));
+  user.send(&tcp(ports, 101, seq + 4, ACK, &[]));
+  for _ in 0..64 * 1024 {
+    assert_eq!(None, user.recv());
+  }
+
+  // a port nobody listens on is reset once the host gave up connecting
+  drop(listener);
+  let ports = (5002, port);
+  user.send(&tcp(ports, 100, 0, SYN, &[]));
+  let segment = ip(&recv(&mut user)).to_vec();
+  assert_eq!(RST | ACK, segment[13]);
+  assert_eq!(101, be32(&segment, 8));
+}
+
+// Guest memory layout of the driver, queue `i` at `QUEUES + i * 0x2000`.
+const QUEUES: u64 = dram::ADDR;
+const BUFFERS: u64 = dram::ADDR + 0x4000;
+
+fn setup(bus: &mut Bus) {
+  let reg = |bus: &mut Bus, reg, value| {
+    bus.store(virtio::ADDR + reg, value, WORD).unwrap();
+  };
+  reg(bus, reg::STATUS, 1 | 2 | 8);
+  for i in 0..2 {
+    let queue = QUEUES + i * 0x2000;
+    reg(bus, reg::QUEUE_SEL, i);
+    reg(bus, reg::QUEUE_NUM, 4);
+    reg(bus, reg::QUEUE_DESC_LOW, queue);
+    reg(bus, reg::QUEUE_DRIVER_LOW, queue + 0x100);
+    reg(bus, reg::QUEUE_DEVICE_LOW, queue + 0x200);
+    reg(bus, reg::QUEUE_READY, 1);
+  }
+  reg(bus, reg::STATUS, 1 | 2 | 8 | 4);
+}
+
+fn mem(bus: &mut Bus, addr: u64, data: &[u8]) {
+  let at = (addr - dram::ADDR) as usize;
+  bus.dram.as_slice_mut()[at..at + data.len()].copy_from_slice(data);
+}
+
+// Make the single descriptor `buf` available in the queue `i`.
+fn post(bus: &mut Bus, i: u64, buf: u64, len: u32, write: bool) {
+  let queue = QUEUES + i * 0x2000;
+  let flags: u16 = if write { 2 } else { 0 };
+  let desc = [&buf.to_le_bytes()[..], &len.to_le_bytes(), &flags.to_le_bytes()];
+  mem(bus, queue, &desc.concat());
+  mem(bus, queue + 0x100 + 2, &1u16.to_le_bytes());
+  bus.store(virtio::ADDR + reg::QUEUE_NOTIFY, i, WORD).unwrap();
+}
+
+#[test]
+fn loopback() {
+  let path = std::env::temp_dir().join("vrisc-loopback.pcap");
+  let mut emu = Emu::new(0x10000);
+  let bus = &mut emu.cpu.bus;
+  let net = Net::new(Loopback::new()).with_pcap(Pcap::create(&path).unwrap());
+  bus.attach_virtio(net).unwrap();
+  assert_eq!(1, bus.load(virtio::ADDR + reg::DEVICE_ID, WORD).unwrap());
+  let config = virtio::ADDR + reg::CONFIG;
+  let mac: Vec<_> =
+    (0..6).map(|i| bus.load(config + i, 8).unwrap() as u8).collect();
+  assert_eq!(MAC, mac[..]);
+  setup(bus);
+
+  // a frame behind the header of the device
+  let frame = ethernet(0x88b5, b"hello");
+  let packet = [&[0; 12][..], &frame].concat();
+  mem(bus, BUFFERS, &packet);
+  post(bus, 1, BUFFERS, packet.len() as u32, false);
+  bus.tick();
+
+  let rx = BUFFERS + 0x1000;
+  post(bus, 0, rx, 1514, true);
+  bus.tick();
+  let used = QUEUES + 0x200;
+  assert_eq!(1, bus.load(used + 2, 16).unwrap());
+  assert_eq!(packet.len() as u64, bus.load(used + 8, WORD).unwrap());
+  let at = (rx - dram::ADDR) as usize + 12;
+  assert_eq!(frame, bus.dram.as_slice()[at..at + frame.len()]);
+
+  // both directions are captured
+  drop(emu);
+  let pcap = fs::read(&path).unwrap();
+  assert_eq!(0xa1b2_c3d4, u32::from_le_bytes(pcap[..4].try_into().unwrap()));
+  assert_eq!(24 + 2 * (16 + frame.len()), pcap.len());
+  assert_eq!(frame, pcap[40..40 + frame.len()]);
+  fs::remove_file(path).unwrap();
+}
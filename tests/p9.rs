@@ -0,0 +1,417 @@
+#![cfg(target_os = "linux")]
+
+use {
+  std::{
+    fs,
+    os::unix::fs::{MetadataExt, PermissionsExt},
+    path::PathBuf,
+  },
+  vrisc::{
+    bus::dram,
+    dev::virtio::{
+      p9::{feature, msg, P9, VERSION},
+      reg,
+    },
+    Bus, Emu,
+  },
+};
+
+const WORD: u8 = 32;
+
+const NOFID: u32 = !0;
+
+// Directory of the export: `file` with "hello", `dir/inner`, and `link`
+// pointing out of it.
+fn export(name: &str) -> PathBuf {
+  let root = std::env::temp_dir().join(format!("vrisc-9p-{name}"));
+  let _ = fs::remove_dir_all(&root);
+  fs::create_dir_all(root.join("dir")).unwrap();
+  fs::write(root.join("file"), "hello").unwrap();
+  fs::write(root.join("dir/inner"), "inner").unwrap();
+  std::os::unix::fs::symlink("/etc", root.join("link")).unwrap();
+  root
+}
+
+fn string(s: &[u8]) -> Vec<u8> {
+  [&(s.len() as u16).to_le_bytes()[..], s].concat()
+}
+
+fn message(kind: u8, body: &[u8]) -> Vec<u8> {
+  let size = 7 + body.len() as u32;
+  [&size.to_le_bytes()[..], &[kind], &1u16.to_le_bytes(), body].concat()
+}
+
+// Reply to a request: its type and body.
+fn call(p9: &mut P9, kind: u8, body: &[&[u8]]) -> (u8, Vec<u8>) {
+  let reply = p9.serve(&message(kind, &body.concat()));
+  let size = u32::from_le_bytes(reply[..4].try_into().unwrap());
+  assert_eq!(reply.len(), size as usize);
+  (reply[4], reply[7..].to_vec())
+}
+
+// Error of a request, failing if it succeeds.
+fn error(p9: &mut P9, kind: u8, body: &[&[u8]]) -> i32 {
+  let (kind, body) = call(p9, kind, body);
+  assert_eq!(msg::RLERROR, kind);
+  i32::from_le_bytes(body[..4].try_into().unwrap())
+}
+
+fn ok(p9: &mut P9, kind: u8, body: &[&[u8]]) -> Vec<u8> {
+  let (reply, body) = call(p9, kind, body);
+  assert_eq!(kind + 1, reply, "error {body:?}");
+  body
+}
+
+fn attach(p9: &mut P9) {
+  let version =
+    ok(p9, msg::TVERSION, &[&8192u32.to_le_bytes(), &string(b"9P2000.L")]);
+  assert_eq!(
+    [&8192u32.to_le_bytes()[..], &string(VERSION.as_bytes())].concat(),
+    version
+  );
+  let qid = ok(
+    p9,
+    msg::TATTACH,
+    &[
+      &0u32.to_le_bytes(),
+      &NOFID.to_le_bytes(),
+      &string(b"root"),
+      &string(b""),
+      &0u32.to_le_bytes(),
+    ],
+  );
+  assert_eq!(0x80, qid[0]);
+}
+
+fn walk(p9: &mut P9, fid: u32, newfid: u32, names: &[&[u8]]) -> (u8, Vec<u8>) {
+  let names: Vec<_> = names.iter().map(|name| string(name)).collect();
+  call(
+    p9,
+    msg::TWALK,
+    &[
+      &fid.to_le_bytes(),
+      &newfid.to_le_bytes(),
+      &(names.len() as u16).to_le_bytes(),
+      &names.concat(),
+    ],
+  )
+}
+
+fn read(p9: &mut P9, fid: u32, offset: u64, count: u32) -> Vec<u8> {
+  let body = ok(
+    p9,
+    msg::TREAD,
+    &[&fid.to_le_bytes(), &offset.to_le_bytes(), &count.to_le_bytes()],
+  );
+  body[4..].to_vec()
+}
+
+#[test]
+fn files() {
+  let root = export("files");
+  let mut p9 = P9::open(&root, "share", false).unwrap();
+  attach(&mut p9);
+
+  // read `dir/inner`
+  let (kind, qids) = walk(&mut p9, 0, 1, &[b"dir", b"inner"]);
+  assert_eq!(msg::TWALK + 1, kind);
+  assert_eq!(2, u16::from_le_bytes([qids[0], qids[1]]));
+  assert_eq!([0x80, 0], [qids[2], qids[15]]);
+  ok(&mut p9, msg::TLOPEN, &[&1u32.to_le_bytes(), &0u32.to_le_bytes()]);
+  assert_eq!(b"inner", &read(&mut p9, 1, 0, 64)[..]);
+  assert_eq!(b"er", &read(&mut p9, 1, 3, 64)[..]);
+
+  // create, write and rename a file
+  let (kind, _) = walk(&mut p9, 0, 2, &[]);
+  assert_eq!(msg::TWALK + 1, kind);
+  ok(
+    &mut p9,
+    msg::TLCREATE,
+    &[
+      &2u32.to_le_bytes(),
+      &string(b"new"),
+      &2u32.to_le_bytes(), // O_RDWR
+      &0o644u32.to_le_bytes(),
+      &0u32.to_le_bytes(),
+    ],
+  );
+  let written = ok(
+    &mut p9,
+    msg::TWRITE,
+    &[&2u32.to_le_bytes(), &0u64.to_le_bytes(), &5u32.to_le_bytes(), b"world"],
+  );
+  assert_eq!(5u32.to_le_bytes(), written[..]);
+  ok(
+    &mut p9,
+    msg::TRENAMEAT,
+    &[
+      &0u32.to_le_bytes(),
+      &string(b"new"),
+      &0u32.to_le_bytes(),
+      &string(b"renamed"),
+    ],
+  );
+  assert_eq!("world", fs::read_to_string(root.join("renamed")).unwrap());
+  // the fid follows the file
+  let attr =
+    ok(&mut p9, msg::TGETATTR, &[&2u32.to_le_bytes(), &0x7ffu64.to_le_bytes()]);
+  assert_eq!(5, u64::from_le_bytes(attr[49..57].try_into().unwrap()));
+
+  // mode, size and modification time
+  let valid = 1 | 1 << 3 | 1 << 5 | 1 << 8;
+  let times = [0u64, 0, 1000, 0].map(u64::to_le_bytes).concat();
+  ok(
+    &mut p9,
+    msg::TSETATTR,
+    &[
+      &2u32.to_le_bytes(),
+      &(valid as u32).to_le_bytes(),
+      &0o600u32.to_le_bytes(),
+      &[0; 8],
+      &2u64.to_le_bytes(),
+      &times,
+    ],
+  );
+  let meta = fs::metadata(root.join("renamed")).unwrap();
+  assert_eq!(0o600, meta.permissions().mode() & 0o7777);
+  assert_eq!(2, meta.len());
+  assert_eq!(1000, meta.mtime());
+
+  // list the root: the entries after `.` and `..`, sorted
+  ok(&mut p9, msg::TLOPEN, &[&0u32.to_le_bytes(), &0u32.to_le_bytes()]);
+  let entries = ok(
+    &mut p9,
+    msg::TREADDIR,
+    &[&0u32.to_le_bytes(), &2u64.to_le_bytes(), &4096u32.to_le_bytes()],
+  );
+  let mut names = Vec::new();
+  let mut at = 4;
+  while at < entries.len() {
+    let len = u16::from_le_bytes([entries[at + 22], entries[at + 23]]) as usize;
+    names.push(
+      String::from_utf8(entries[at + 24..at + 24 + len].to_vec()).unwrap(),
+    );
+    at += 24 + len;
+  }
+  assert_eq!(["dir", "file", "link", "renamed"], &names[..]);
+
+  // remove a directory with its flag only
+  ok(
+    &mut p9,
+    msg::TMKDIR,
+    &[
+      &0u32.to_le_bytes(),
+      &string(b"empty"),
+      &0o755u32.to_le_bytes(),
+      &0u32.to_le_bytes(),
+    ],
+  );
+  let unlink = |flags: u32| {
+    [
+      0u32.to_le_bytes().to_vec(),
+      string(b"empty"),
+      flags.to_le_bytes().to_vec(),
+    ]
+  };
+  let [a, b, c] = unlink(0);
+  assert_eq!(libc::EISDIR, error(&mut p9, msg::TUNLINKAT, &[&a, &b, &c]));
+  let [a, b, c] = unlink(0x200);
+  ok(&mut p9, msg::TUNLINKAT, &[&a, &b, &c]);
+  assert!(!root.join("empty").exists());
+
+  fs::remove_dir_all(root).unwrap();
+}
+
+#[test]
+fn confinement() {
+  let root = export("confinement");
+  let mut p9 = P9::open(root.join("dir"), "share", false).unwrap();
+  attach(&mut p9);
+
+  // `..` stops at the root of the export
+  let (kind, _) = walk(&mut p9, 0, 1, &[b"..", b"..", b"inner"]);
+  assert_eq!(msg::TWALK + 1, kind);
+  ok(&mut p9, msg::TLOPEN, &[&1u32.to_le_bytes(), &0u32.to_le_bytes()]);
+  assert_eq!(b"inner", &read(&mut p9, 1, 0, 64)[..]);
+
+  // names are single components
+  assert_eq!(libc::EINVAL, walk_error(&mut p9, &[b"../file"]));
+  assert_eq!(libc::EINVAL, walk_error(&mut p9, &[b"/etc"]));
+  assert_eq!(libc::ENOENT, walk_error(&mut p9, &[b"file"]));
+
+  // symlinks are not walked through nor opened
+  let mut p9 = P9::open(&root, "share", false).unwrap();
+  attach(&mut p9);
+  let (kind, qids) = walk(&mut p9, 0, 1, &[b"link", b"passwd"]);
+  // the partial walk leaves no fid behind
+  assert_eq!(msg::TWALK + 1, kind);
+  assert_eq!(1, u16::from_le_bytes([qids[0], qids[1]]));
+  assert_eq!(0x02, qids[2]);
+  let open = [1u32.to_le_bytes(), 0u32.to_le_bytes()];
+  assert_eq!(libc::EBADF, error(&mut p9, msg::TLOPEN, &[&open[0], &open[1]]));
+  walk(&mut p9, 0, 1, &[b"link"]);
+  assert_eq!(libc::ELOOP, error(&mut p9, msg::TLOPEN, &[&open[0], &open[1]]));
+
+  fs::remove_dir_all(root).unwrap();
+}
+
+#[test]
+fn replaced() {
+  let root = export("replaced");
+  let mut p9 = P9::open(&root, "share", false).unwrap();
+  attach(&mut p9);
+  let mkdir = |p9: &mut P9, fid: u32, name: &[u8]| {
+    let mode = 0o755u32.to_le_bytes();
+    let body = [&fid.to_le_bytes()[..], &string(name), &mode, &[0; 4]];
+    ok(p9, msg::TMKDIR, &body);
+  };
+  let rmdir = |p9: &mut P9, fid: u32, name: &[u8]| {
+    let flags = 0x200u32.to_le_bytes();
+    ok(p9, msg::TUNLINKAT, &[&fid.to_le_bytes()[..], &string(name), &flags]);
+  };
+
+  // a fid in `a/etc`, then `a` turned into a symlink to the root of the host
+  mkdir(&mut p9, 0, b"a");
+  walk(&mut p9, 0, 1, &[b"a"]);
+  mkdir(&mut p9, 1, b"etc");
+  walk(&mut p9, 0, 2, &[b"a", b"etc"]);
+  rmdir(&mut p9, 1, b"etc");
+  rmdir(&mut p9, 0, b"a");
+  ok(
+    &mut p9,
+    msg::TSYMLINK,
+    &[&0u32.to_le_bytes(), &string(b"a"), &string(b"/"), &0u32.to_le_bytes()],
+  );
+
+  // the stale fid reaches nothing of the host
+  let (kind, _) = walk(&mut p9, 2, 3, &[b"passwd"]);
+  assert_eq!(msg::RLERROR, kind);
+  let rdwr = [2u32.to_le_bytes(), 2u32.to_le_bytes()];
+  assert_eq!(libc::ENOTDIR, error(&mut p9, msg::TLOPEN, &[&rdwr[0], &rdwr[1]]));
+  let readdir =
+    [&2u32.to_le_bytes()[..], &0u64.to_le_bytes(), &4096u32.to_le_bytes()];
+  assert_eq!(libc::ENOTDIR, error(&mut p9, msg::TREADDIR, &readdir));
+  let getattr = [&2u32.to_le_bytes()[..], &0x7ffu64.to_le_bytes()];
+  assert_eq!(libc::ENOTDIR, error(&mut p9, msg::TGETATTR, &getattr));
+
+  fs::remove_dir_all(root).unwrap();
+}
+
+fn walk_error(p9: &mut P9, names: &[&[u8]]) -> i32 {
+  let (kind, body) = walk(p9, 0, 9, names);
+  assert_eq!(msg::RLERROR, kind);
+  i32::from_le_bytes(body[..4].try_into().unwrap())
+}
+
+#[test]
+fn readonly() {
+  let root = export("readonly");
+  let mut p9 = P9::open(&root, "share", true).unwrap();
+  attach(&mut p9);
+
+  walk(&mut p9, 0, 1, &[b"file"]);
+  let rdwr = [1u32.to_le_bytes(), 2u32.to_le_bytes()];
+  assert_eq!(libc::EROFS, error(&mut p9, msg::TLOPEN, &[&rdwr[0], &rdwr[1]]));
+  ok(&mut p9, msg::TLOPEN, &[&1u32.to_le_bytes(), &0u32.to_le_bytes()]);
+  assert_eq!(b"hello", &read(&mut p9, 1, 0, 64)[..]);
+  let write = [
+    1u32.to_le_bytes().to_vec(),
+    0u64.to_le_bytes().to_vec(),
+    1u32.to_le_bytes().to_vec(),
+  ];
+  assert_eq!(
+    libc::EROFS,
+    error(&mut p9, msg::TWRITE, &[&write[0], &write[1], &write[2], b"x"])
+  );
+  assert_eq!(
+    libc::EROFS,
+    error(
+      &mut p9,
+      msg::TMKDIR,
+      &[
+        &0u32.to_le_bytes(),
+        &string(b"new"),
+        &0o755u32.to_le_bytes(),
+        &0u32.to_le_bytes(),
+      ]
+    )
+  );
+  assert_eq!(libc::EROFS, error(&mut p9, msg::TREMOVE, &[&1u32.to_le_bytes()]));
+  assert_eq!("hello", fs::read_to_string(root.join("file")).unwrap());
+
+  fs::remove_dir_all(root).unwrap();
+}
+
+// Guest memory layout of the driver: the queue, the request and the reply.
+const QUEUE: u64 = dram::ADDR;
+const REQUEST: u64 = dram::ADDR + 0x1000;
+const REPLY: u64 = dram::ADDR + 0x2000;
+
+#[test]
+fn transport() {
+  let root = export("transport");
+  let mut emu = Emu::new(0x10000);
+  let bus = &mut emu.cpu.bus;
+  let base =
+    bus.attach_virtio(P9::open(&root, "share", false).unwrap()).unwrap();
+  assert_eq!(9, bus.load(base + reg::DEVICE_ID, WORD).unwrap());
+  assert_eq!(
+    feature::MOUNT_TAG,
+    bus.load(base + reg::DEVICE_FEATURES, WORD).unwrap()
+  );
+  // the mount tag
+  assert_eq!(5, bus.load(base + reg::CONFIG, 16).unwrap());
+  let tag =
+    (0..5).map(|i| bus.load(base + reg::CONFIG + 2 + i, 8).unwrap() as u8);
+  assert_eq!(b"share", &tag.collect::<Vec<_>>()[..]);
+
+  let reg =
+    |bus: &mut Bus, reg, value| bus.store(base + reg, value, WORD).unwrap();
+  reg(bus, reg::STATUS, 1 | 2 | 8);
+  reg(bus, reg::QUEUE_SEL, 0);
+  reg(bus, reg::QUEUE_NUM, 8);
+  reg(bus, reg::QUEUE_DESC_LOW, QUEUE);
+  reg(bus, reg::QUEUE_DRIVER_LOW, QUEUE + 0x80);
+  reg(bus, reg::QUEUE_DEVICE_LOW, QUEUE + 0xc0);
+  reg(bus, reg::QUEUE_READY, 1);
+  reg(bus, reg::STATUS, 1 | 2 | 8 | 4);
+
+  // the request and the buffer of the reply, chained
+  let request = message(
+    msg::TVERSION,
+    &[&8192u32.to_le_bytes()[..], &string(b"9P2000.L")].concat(),
+  );
+  let at = (REQUEST - dram::ADDR) as usize;
+  bus.dram.as_slice_mut()[at..at + request.len()].copy_from_slice(&request);
+  let descs =
+    [(REQUEST, request.len() as u32, 1u16, 1u16), (REPLY, 0x100, 2, 0)];
+  for (i, (addr, len, flags, next)) in descs.into_iter().enumerate() {
+    let desc = [
+      &addr.to_le_bytes()[..],
+      &len.to_le_bytes(),
+      &flags.to_le_bytes(),
+      &next.to_le_bytes(),
+    ]
+    .concat();
+    let at = (QUEUE - dram::ADDR) as usize + 16 * i;
+    bus.dram.as_slice_mut()[at..at + 16].copy_from_slice(&desc);
+  }
+  let at = (QUEUE + 0x80 - dram::ADDR) as usize;
+  bus.dram.as_slice_mut()[at..at + 6].copy_from_slice(&[0, 0, 1, 0, 0, 0]);
+  reg(bus, reg::QUEUE_NOTIFY, 0);
+  bus.tick();
+
+  assert_eq!(1, bus.load(QUEUE + 0xc0 + 2, 16).unwrap());
+  let len = bus.load(QUEUE + 0xc0 + 8, WORD).unwrap();
+  let reply: Vec<_> =
+    (0..len).map(|i| bus.load(REPLY + i, 8).unwrap() as u8).collect();
+  assert_eq!(
+    message(
+      msg::TVERSION + 1,
+      &[&8192u32.to_le_bytes()[..], &string(b"9P2000.L")].concat()
+    ),
+    reply
+  );
+
+  fs::remove_dir_all(root).unwrap();
+}
@@ -0,0 +1,104 @@
+use vrisc::{
+  bus::plic,
+  csr::{MEIP_BIT, SEIP_BIT},
+  dev::{
+    plic::{ctx, reg, Plic},
+    Device,
+  },
+  Cpu, Exception,
+};
+
+const WORD: u8 = 32;
+const CLAIM_M: u64 = plic::ADDR + reg::CONTEXT + ctx::CLAIM;
+const CLAIM_S: u64 = plic::ADDR + reg::CONTEXT + 0x1000 + ctx::CLAIM;
+
+/// Device that raises its interrupt line while a non-zero value is written.
+#[derive(Debug, Default)]
+struct Line(bool);
+
+impl Device for Line {
+  fn load(&mut self, _addr: u64, _size: u8) -> Result<u64, Exception> {
+    Ok(self.0 as u64)
+  }
+
+  fn store(
+    &mut self,
+    _addr: u64,
+    value: u64,
+    _size: u8,
+  ) -> Result<(), Exception> {
+    self.0 = value != 0;
+    Ok(())
+  }
+
+  fn irq(&self) -> bool {
+    self.0
+  }
+}
+
+fn cpu() -> Cpu {
+  let mut cpu = Cpu::new(0);
+  let bus = &mut cpu.bus;
+  for (src, prio) in [(3, 1), (5, 2)] {
+    bus.store(plic::ADDR + reg::PRIORITY + 4 * src, prio, WORD).unwrap();
+  }
+  bus.store(plic::ADDR + reg::ENABLE, 1 << 3 | 1 << 5, WORD).unwrap();
+  cpu
+}
+
+#[test]
+fn claim() {
+  let mut cpu = cpu();
+  let bus = &mut cpu.bus;
+
+  bus.set_irq(3, true);
+  bus.set_irq(5, true);
+  assert_eq!(MEIP_BIT, bus.mip() & (MEIP_BIT | SEIP_BIT));
+  assert_eq!(
+    1 << 3 | 1 << 5,
+    bus.load(plic::ADDR + reg::PENDING, WORD).unwrap()
+  );
+
+  // the higher priority goes first, a claimed source stays quiet until it
+  // is completed
+  assert_eq!(5, bus.load(CLAIM_M, WORD).unwrap());
+  assert_eq!(3, bus.load(CLAIM_M, WORD).unwrap());
+  assert_eq!(0, bus.load(CLAIM_M, WORD).unwrap());
+  assert_eq!(0, bus.mip() & MEIP_BIT);
+
+  bus.set_irq(3, false);
+  bus.store(CLAIM_M, 3, WORD).unwrap();
+  bus.store(CLAIM_M, 5, WORD).unwrap();
+  assert_eq!(5, bus.device::<Plic>().unwrap().top(0));
+}
+
+#[test]
+fn threshold() {
+  let mut cpu = cpu();
+  let bus = &mut cpu.bus;
+  bus.set_irq(3, true);
+
+  bus.store(plic::ADDR + reg::CONTEXT + ctx::THRESHOLD, 1, WORD).unwrap();
+  assert_eq!(0, bus.mip() & MEIP_BIT);
+
+  // the supervisor context has its own enables
+  assert_eq!(0, bus.load(CLAIM_S, WORD).unwrap());
+  bus.store(plic::ADDR + reg::ENABLE + 0x80, 1 << 3, WORD).unwrap();
+  assert_eq!(SEIP_BIT, bus.mip() & SEIP_BIT);
+  assert_eq!(3, bus.load(CLAIM_S, WORD).unwrap());
+}
+
+#[test]
+fn device() {
+  let mut cpu = cpu();
+  let bus = &mut cpu.bus;
+  bus.attach_irq(0x3000_0000, 0x100, 5, Line::default()).unwrap();
+
+  bus.store(0x3000_0000, 1, WORD).unwrap();
+  bus.tick();
+  assert_eq!(5, bus.device::<Plic>().unwrap().top(0));
+
+  bus.store(0x3000_0000, 0, WORD).unwrap();
+  bus.tick();
+  assert_eq!(0, bus.mip() & MEIP_BIT);
+}
@@ -0,0 +1,89 @@
+use vrisc::{
+  bus::{dram, plic, rtc, RTC_IRQ},
+  dev::{
+    plic::reg as plic_reg,
+    rtc::{reg, Clock, Rtc},
+  },
+  Emu,
+};
+
+const WORD: u8 = 32;
+const LOOP: u32 = 0x0000006f; // jal x0, 0
+
+fn emu(clock: Clock) -> Emu {
+  let mut emu = Emu::new(0x1000);
+  emu.with_dram(&LOOP.to_le_bytes()).with_pc(dram::ADDR);
+  emu.cpu.bus.device_mut::<Rtc>().unwrap().clock = clock;
+  emu
+}
+
+fn time(emu: &mut Emu) -> u64 {
+  let low = emu.cpu.bus.load(rtc::ADDR + reg::TIME_LOW, WORD).unwrap();
+  let high = emu.cpu.bus.load(rtc::ADDR + reg::TIME_HIGH, WORD).unwrap();
+  high << 32 | low
+}
+
+#[test]
+fn virtual_clock() {
+  let mut emu = emu(Clock::Virtual(5 << 32));
+  assert_eq!(5 << 32, time(&mut emu));
+
+  // a tick lasts 100 ns at 10 MHz
+  for _ in 0..10 {
+    emu.cycle().unwrap();
+  }
+  assert_eq!((5 << 32) + 1000, time(&mut emu));
+}
+
+#[test]
+fn host_clock() {
+  let mut emu = emu(Clock::Host);
+  // after 2020
+  assert!(time(&mut emu) > 1_577_836_800_000_000_000);
+}
+
+#[test]
+fn set_time() {
+  let mut emu = emu(Clock::Virtual(0));
+  emu.cpu.bus.store(rtc::ADDR + reg::TIME_HIGH, 7, WORD).unwrap();
+  emu.cpu.bus.store(rtc::ADDR + reg::TIME_LOW, 100, WORD).unwrap();
+  assert_eq!(7 << 32 | 100, time(&mut emu));
+
+  emu.cycle().unwrap();
+  assert_eq!(7 << 32 | 200, time(&mut emu));
+
+  // the time survives a reset
+  emu.reset();
+  assert_eq!(7 << 32 | 200, time(&mut emu));
+}
+
+#[test]
+fn alarm() {
+  let mut emu = emu(Clock::Virtual(0));
+  let pending = |emu: &mut Emu| {
+    let pending =
+      emu.cpu.bus.load(plic::ADDR + plic_reg::PENDING, WORD).unwrap();
+    pending & 1 << RTC_IRQ != 0
+  };
+  let store = |emu: &mut Emu, reg, value| {
+    emu.cpu.bus.store(rtc::ADDR + reg, value, WORD).unwrap();
+  };
+
+  store(&mut emu, reg::IRQ_ENABLED, 1);
+  store(&mut emu, reg::ALARM_HIGH, 0);
+  store(&mut emu, reg::ALARM_LOW, 300_000);
+  assert_eq!(1, emu.cpu.bus.load(rtc::ADDR + reg::ALARM_STATUS, WORD).unwrap());
+
+  // 300 us are 3000 ticks, the alarm is checked every 1024 ticks
+  for _ in 0..3071 {
+    emu.cycle().unwrap();
+  }
+  assert!(!pending(&mut emu));
+  emu.cycle().unwrap();
+  assert!(pending(&mut emu));
+  assert_eq!(0, emu.cpu.bus.load(rtc::ADDR + reg::ALARM_STATUS, WORD).unwrap());
+
+  store(&mut emu, reg::CLEAR_INTERRUPT, 1);
+  emu.cycle().unwrap();
+  assert!(!pending(&mut emu));
+}
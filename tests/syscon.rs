@@ -0,0 +1,75 @@
+use vrisc::{
+  bus::{dram, syscon},
+  dev::{
+    syscon::value,
+    vga::{Format, Mode, Vga},
+  },
+  Emu, Exception, Exit, RESET_VECTOR,
+};
+
+const WORD: u8 = 32;
+
+#[rustfmt::skip]
+const PROGRAM: [u32; 6] = [
+  0x010000b7, // lui x1, 0x1000       ; the syscon
+  0x00005137, // lui x2, 0x5
+  0x55510113, // addi x2, x2, 0x555   ; poweroff
+  0x00700193, // addi x3, x0, 7
+  0x0020a023, // sw x2, 0(x1)
+  0x0000006f, // jal x0, 0
+];
+
+fn emu() -> Emu {
+  let mut emu = Emu::new(0x1000);
+  let code: Vec<_> = PROGRAM.iter().flat_map(|i| i.to_le_bytes()).collect();
+  emu.with_dram(&code).with_pc(dram::ADDR);
+  emu
+}
+
+#[test]
+fn poweroff() {
+  let mut emu = emu();
+  assert_eq!(Exit::Pass, emu.run());
+  assert_eq!(dram::ADDR + 4 * 5, emu.cpu.pc);
+}
+
+#[test]
+fn fail() {
+  let mut emu = emu();
+  let fail = 3 << 16 | value::FAIL as u64;
+  emu.cpu.bus.store(syscon::ADDR, fail, WORD).unwrap();
+  emu.cycle().unwrap();
+  assert_eq!(Some(Exit::Fail(3)), emu.exit());
+}
+
+#[test]
+fn reboot() {
+  let mut emu = emu();
+  for _ in 0..3 {
+    emu.cycle().unwrap();
+  }
+  assert_eq!(syscon::ADDR, emu.cpu.xregs.load(1));
+  let vga = emu.cpu.bus.device_mut::<Vga>().unwrap();
+  vga.set_mode(Mode::packed(64, 64, Format::Xrgb8888));
+
+  emu.cpu.bus.store(syscon::ADDR, value::RESET as u64, WORD).unwrap();
+  emu.cycle().unwrap();
+  assert_eq!(None, emu.exit());
+  assert_eq!(RESET_VECTOR, emu.cpu.pc);
+  assert_eq!(0, emu.cpu.xregs.load(1));
+  assert!(emu.cpu.bus.device::<Vga>().unwrap().is_text());
+}
+
+#[test]
+fn access() {
+  let mut emu = emu();
+  let halt = value::PASS as u64;
+  assert_eq!(
+    Err(Exception::StoreAMOAccessFault(syscon::ADDR)),
+    emu.cpu.bus.store(syscon::ADDR, halt, 8)
+  );
+  // only the register at offset 0 acts
+  emu.cpu.bus.store(syscon::ADDR + 4, halt, WORD).unwrap();
+  emu.cycle().unwrap();
+  assert_eq!(None, emu.exit());
+}
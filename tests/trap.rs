@@ -0,0 +1,161 @@
+use vrisc::{
+  bus::{clint, dram, plic},
+  csr::{
+    x, MCAUSE, MEPC, MIDELEG, MIE, MISA, MTIP_BIT, MTVAL, MTVEC, SCAUSE,
+    SEIP_BIT, SEPC, STVEC,
+  },
+  dev::{
+    clint::reg::MTIMECMP,
+    plic::reg::{ENABLE, PRIORITY},
+  },
+  Emu, Exception, Mode, INTERRUPT,
+};
+
+const NOP: u32 = 0x00000013; // addi x0, x0, 0
+
+fn emu(program: &[u32]) -> Emu {
+  let mut emu = Emu::new(0x1000);
+  let bytes: Vec<u8> = program.iter().flat_map(|x| x.to_le_bytes()).collect();
+  emu.with_dram(&bytes).with_pc(dram::ADDR);
+  emu
+}
+
+#[test]
+fn access_fault() {
+  let mut emu = emu(&[
+    0x00000013, // nop
+    0x00012203, // lw x4, 0(x2)
+  ]);
+  emu.cpu.xregs.store(2, 0x10);
+  emu.cpu.state.store(MTVEC, dram::ADDR + 0x100);
+
+  emu.cycle().unwrap();
+  let ex = emu.cycle().unwrap_err();
+  assert_eq!(Exception::LoadAccessFault(0x10), ex);
+
+  emu.cpu.catch_exception(ex);
+  assert_eq!(dram::ADDR + 0x100, emu.cpu.pc);
+  assert_eq!(dram::ADDR + 4, emu.cpu.state.load(MEPC));
+  assert_eq!(5, emu.cpu.state.load(MCAUSE));
+  assert_eq!(0x10, emu.cpu.state.load(MTVAL));
+}
+
+#[test]
+fn misaligned_jump() {
+  let mut emu = emu(&[
+    0x002000ef, // jal x1, 2
+  ]);
+
+  // 2-byte aligned targets are only legal with the compressed extension
+  emu.cpu.state.store(MISA, emu.cpu.state.load(MISA) & !(1 << 2));
+
+  let ex = emu.cycle().unwrap_err();
+  assert_eq!(Exception::InstAddrMisalign(dram::ADDR + 2), ex);
+  assert_eq!(0, emu.cpu.xregs.load(1));
+
+  emu.cpu.catch_exception(ex);
+  assert_eq!(dram::ADDR, emu.cpu.state.load(MEPC));
+  assert_eq!(dram::ADDR + 2, emu.cpu.state.load(MTVAL));
+}
+
+#[test]
+fn mret() {
+  let mut emu = emu(&[
+    0x00100073, // ebreak
+    0x30200073, // mret
+  ]);
+  emu.cpu.state.store(MTVEC, dram::ADDR + 4);
+
+  let ex = emu.cycle().unwrap_err();
+  emu.cpu.catch_exception(ex);
+  assert_eq!(dram::ADDR, emu.cpu.state.load(MEPC));
+  assert_eq!(dram::ADDR, emu.cpu.state.load(MTVAL));
+
+  emu.cycle().unwrap();
+  assert_eq!(dram::ADDR, emu.cpu.pc);
+}
+
+#[test]
+fn xret_privilege() {
+  const SRET: u32 = 0x10200073;
+  const MRET: u32 = 0x30200073;
+  let mut emu = emu(&[MRET, SRET]);
+  emu.cpu.state.store_mstatus(x::MPP, Mode::Machine as u64);
+  emu.cpu.state.store(MEPC, dram::ADDR + 0x100);
+
+  // `mret` is reserved to M-mode, whatever `mstatus.MPP` holds
+  for mode in [Mode::User, Mode::Supervisor] {
+    emu.cpu.mode = mode;
+    emu.cpu.pc = dram::ADDR;
+    assert_eq!(Err(Exception::IllegalInst(MRET as u64)), emu.cycle());
+    assert_eq!(mode, emu.cpu.mode);
+  }
+
+  // `sret` from U-mode
+  emu.cpu.mode = Mode::User;
+  emu.cpu.pc = dram::ADDR + 4;
+  assert_eq!(Err(Exception::IllegalInst(SRET as u64)), emu.cycle());
+  assert_eq!(Mode::User, emu.cpu.mode);
+
+  emu.cpu.mode = Mode::Supervisor;
+  emu.cpu.state.store(SEPC, dram::ADDR + 0x100);
+  emu.cycle().unwrap();
+  assert_eq!(Mode::User, emu.cpu.mode);
+  assert_eq!(dram::ADDR + 0x100, emu.cpu.pc);
+}
+
+#[test]
+fn timer_interrupt() {
+  let mut emu = emu(&[NOP; 0x80]);
+  let mtimecmp = clint::ADDR + MTIMECMP;
+  emu.cpu.bus.store(mtimecmp, 2, 64).unwrap();
+  emu.cpu.state.store(MTVEC, dram::ADDR + 0x100);
+  emu.cpu.state.store(MIE, MTIP_BIT);
+
+  // masked in M-mode until `mstatus.MIE` is set
+  for _ in 0..4 {
+    emu.cycle().unwrap();
+  }
+  assert_eq!(dram::ADDR + 0x10, emu.cpu.pc);
+  emu.cpu.state.store_mstatus(x::MIE, 1);
+
+  // the handler runs its first instruction in the same cycle
+  emu.cycle().unwrap();
+  assert_eq!(dram::ADDR + 0x104, emu.cpu.pc);
+  assert_eq!(INTERRUPT | 7, emu.cpu.state.load(MCAUSE));
+  assert_eq!(dram::ADDR + 0x10, emu.cpu.state.load(MEPC));
+  assert_eq!(0, emu.cpu.state.load(MTVAL));
+  assert_eq!(0, emu.cpu.state.load_mstatus(x::MIE));
+  assert_eq!(1, emu.cpu.state.load_mstatus(x::MPIE));
+  assert_eq!(None, emu.cpu.pending_interrupt());
+}
+
+#[test]
+fn external_interrupt() {
+  let mut emu = emu(&[NOP; 0x80]);
+  let bus = &mut emu.cpu.bus;
+  bus.store(plic::ADDR + PRIORITY + 4 * 3, 1, 32).unwrap();
+  bus.store(plic::ADDR + ENABLE + 0x80, 1 << 3, 32).unwrap();
+
+  // delegated to S-mode, which takes it at its vector
+  emu.cpu.mode = Mode::Supervisor;
+  emu.cpu.state.store(STVEC, (dram::ADDR + 0x100) | 1);
+  emu.cpu.state.store(MIDELEG, SEIP_BIT);
+  emu.cpu.state.store(MIE, SEIP_BIT);
+  emu.cpu.state.store_sstatus(x::SIE, 1);
+  emu.cycle().unwrap();
+  emu.cpu.bus.set_irq(3, true);
+
+  emu.cycle().unwrap();
+  assert_eq!(Mode::Supervisor, emu.cpu.mode);
+  assert_eq!(dram::ADDR + 0x100 + 4 * 9 + 4, emu.cpu.pc);
+  assert_eq!(INTERRUPT | 9, emu.cpu.state.load(SCAUSE));
+  assert_eq!(dram::ADDR + 4, emu.cpu.state.load(SEPC));
+  assert_eq!(1, emu.cpu.state.load_sstatus(x::SPP));
+  assert_eq!(0, emu.cpu.state.load_sstatus(x::SIE));
+
+  // M-mode never takes a delegated interrupt
+  emu.cpu.mode = Mode::Machine;
+  emu.cpu.state.store_sstatus(x::SIE, 1);
+  assert_eq!(None, emu.cpu.pending_interrupt());
+}
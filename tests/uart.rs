@@ -0,0 +1,117 @@
+use {
+  std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+  },
+  vrisc::{
+    bus::{plic, uart},
+    dev::{
+      plic::reg::PENDING,
+      serial::Serial,
+      uart::{ier, iir, lsr, reg},
+    },
+    Emu,
+  },
+};
+
+const BYTE: u8 = 8;
+const WORD: u8 = 32;
+
+/// Serial line backed by queues shared with the test.
+#[derive(Debug, Clone, Default)]
+struct Pipe {
+  input: Arc<Mutex<VecDeque<u8>>>,
+  output: Arc<Mutex<Vec<u8>>>,
+}
+
+impl Serial for Pipe {
+  fn read(&mut self) -> Option<u8> {
+    self.input.lock().unwrap().pop_front()
+  }
+
+  fn write(&mut self, byte: u8) {
+    self.output.lock().unwrap().push(byte);
+  }
+}
+
+fn emu(pipe: &Pipe) -> Emu {
+  let mut emu = Emu::new(0);
+  emu.with_serial(pipe.clone());
+  emu
+}
+
+fn load(emu: &mut Emu, reg: u64) -> u8 {
+  emu.cpu.bus.load(uart::ADDR + reg, BYTE).unwrap() as u8
+}
+
+fn store(emu: &mut Emu, reg: u64, value: u8) {
+  emu.cpu.bus.store(uart::ADDR + reg, value as u64, BYTE).unwrap();
+}
+
+fn tick(emu: &mut Emu, n: usize) {
+  for _ in 0..n {
+    emu.cpu.bus.tick();
+  }
+}
+
+#[test]
+fn transmit() {
+  let pipe = Pipe::default();
+  let mut emu = emu(&pipe);
+
+  for &byte in b"hi\n" {
+    assert_ne!(0, load(&mut emu, reg::LSR) & lsr::THRE);
+    store(&mut emu, reg::THR, byte);
+  }
+  assert_eq!(b"hi\n", &pipe.output.lock().unwrap()[..]);
+
+  // the divisor latch shadows the data registers
+  store(&mut emu, reg::LCR, 0x80);
+  store(&mut emu, reg::DLL, 1);
+  store(&mut emu, reg::LCR, 0x03);
+  assert_eq!(3, pipe.output.lock().unwrap().len());
+}
+
+#[test]
+fn receive() {
+  let pipe = Pipe::default();
+  let mut emu = emu(&pipe);
+  store(&mut emu, reg::FCR, 0x41); // trigger at 4 bytes
+  store(&mut emu, reg::IER, ier::ERBFI);
+
+  pipe.input.lock().unwrap().extend(b"ab");
+  tick(&mut emu, 1024);
+  assert_eq!(iir::FIFO | iir::TIMEOUT, load(&mut emu, reg::IIR));
+
+  pipe.input.lock().unwrap().extend(b"cd");
+  tick(&mut emu, 1024);
+  assert_eq!(iir::FIFO | iir::RDA, load(&mut emu, reg::IIR));
+
+  let mut read = Vec::new();
+  while load(&mut emu, reg::LSR) & lsr::DR != 0 {
+    read.push(load(&mut emu, reg::RBR));
+  }
+  assert_eq!(b"abcd", &read[..]);
+  assert_eq!(iir::FIFO | iir::NONE, load(&mut emu, reg::IIR));
+}
+
+#[test]
+fn interrupt() {
+  let pipe = Pipe::default();
+  let mut emu = emu(&pipe);
+  store(&mut emu, reg::IER, ier::ETBEI);
+  emu.cpu.bus.tick();
+  assert_ne!(0, emu.cpu.bus.load(plic::ADDR + PENDING, WORD).unwrap());
+
+  // reading `IIR` acknowledges the empty transmitter
+  assert_eq!(iir::THRE, load(&mut emu, reg::IIR));
+  assert_eq!(iir::NONE, load(&mut emu, reg::IIR));
+
+  // the line loops back to the receiver
+  store(&mut emu, reg::MCR, 0x10);
+  store(&mut emu, reg::IER, ier::ERBFI);
+  store(&mut emu, reg::THR, b'x');
+  assert_eq!(iir::RDA, load(&mut emu, reg::IIR));
+  assert_eq!(b'x', load(&mut emu, reg::RBR));
+  assert!(pipe.output.lock().unwrap().is_empty());
+}
@@ -0,0 +1,462 @@
+use vrisc::{
+  bus::{dram, plic, vga, VGA_IRQ},
+  dev::{
+    clint::TIMEBASE_FREQ,
+    vga::{
+      blit::{self, op},
+      control, reg, status,
+      text::{self, crtc, FONT, PALETTE},
+      tile::{self, flag, Sprite},
+      Format, Mode, Vga, VRAM,
+    },
+  },
+  Bus, Emu,
+};
+
+const WORD: u8 = 32;
+
+fn vga(emu: &Emu) -> &Vga {
+  emu.cpu.bus.device::<Vga>().unwrap()
+}
+
+// Colour of the pixel at `x`, `y` of the picture.
+fn px(vga: &Vga, x: u32, y: u32) -> [u8; 3] {
+  let (width, _) = vga.resolution().unwrap();
+  let at = 3 * (y * width + x) as usize;
+  vga.rgb()[at..at + 3].try_into().unwrap()
+}
+
+// Show `n` frames, a frame a tick.
+fn frames(emu: &mut Emu, n: u64) {
+  let bus = &mut emu.cpu.bus;
+  bus.store(vga::ADDR + VRAM + reg::FPS, TIMEBASE_FREQ, WORD).unwrap();
+  for _ in 0..n {
+    bus.tick();
+  }
+}
+
+#[test]
+fn text_mode() {
+  let mut emu = Emu::new(0);
+  let bus = &mut emu.cpu.bus;
+  // yellow on blue, after a blank grey on black
+  bus.store(vga::ADDR, 0x0700, 16).unwrap();
+  bus.store(vga::ADDR + 2, 0x1e00 | b'A' as u64, 16).unwrap();
+
+  let vga = vga(&emu);
+  assert!(vga.is_text());
+  assert_eq!(None, vga.mode());
+  assert_eq!(Some((text::WIDTH, text::HEIGHT)), vga.resolution());
+  let rgb = vga.rgb();
+  assert_eq!(3 * 640 * 400, rgb.len());
+  for (line, bits) in FONT[b'A' as usize * 16..][..16].iter().enumerate() {
+    for x in 0..8 {
+      let colour = if bits & 0x80 >> x != 0 { 14 } else { 1 };
+      assert_eq!(PALETTE[colour], px(vga, 8 + x, line as u32));
+    }
+  }
+  // the cursor underlines the first cell
+  assert_eq!(PALETTE[0], px(vga, 0, 13));
+  assert_eq!(PALETTE[7], px(vga, 0, 14));
+}
+
+#[test]
+fn cursor() {
+  let mut emu = Emu::new(0);
+  let bus = &mut emu.cpu.bus;
+  let crtc = |bus: &mut Bus, index: u8, data| {
+    bus.store(vga::ADDR + VRAM + reg::CRTC_INDEX, index as u64, WORD).unwrap();
+    bus.store(vga::ADDR + VRAM + reg::CRTC_DATA, data, WORD).unwrap();
+  };
+  // a block cursor on the second line
+  crtc(bus, crtc::CURSOR_START, 0);
+  crtc(bus, crtc::CURSOR_LOW, 80);
+  bus.store(vga::ADDR + 160, 0x0200, 16).unwrap();
+  assert_eq!(PALETTE[2], px(vga(&emu), 0, 16));
+  assert_eq!(PALETTE[0], px(vga(&emu), 0, 0));
+
+  frames(&mut emu, text::BLINK);
+  assert_eq!(PALETTE[0], px(vga(&emu), 0, 16));
+
+  // turned off
+  let bus = &mut emu.cpu.bus;
+  bus.reset();
+  crtc(bus, crtc::CURSOR_START, crtc::CURSOR_OFF as u64);
+  bus.store(vga::ADDR, 0x0700, 16).unwrap();
+  assert_eq!(PALETTE[0], px(vga(&emu), 0, 15));
+  let bus = &mut emu.cpu.bus;
+  bus
+    .store(vga::ADDR + VRAM + reg::CRTC_INDEX, crtc::CURSOR_START as u64, WORD)
+    .unwrap();
+  assert_eq!(
+    crtc::CURSOR_OFF as u64,
+    bus.load(vga::ADDR + VRAM + reg::CRTC_DATA, WORD).unwrap()
+  );
+}
+
+#[test]
+fn blink() {
+  let mut emu = Emu::new(0);
+  let bus = &mut emu.cpu.bus;
+  // white on red, blinking
+  bus.store(vga::ADDR, 0xcf00 | b'|' as u64, 16).unwrap();
+  let glyph = FONT[b'|' as usize * 16 + 8];
+  let x = glyph.leading_zeros();
+  assert_eq!(PALETTE[15], px(vga(&emu), x, 8));
+  assert_eq!(PALETTE[4], px(vga(&emu), 0, 8));
+
+  frames(&mut emu, 2 * text::BLINK);
+  assert_eq!(PALETTE[4], px(vga(&emu), x, 8));
+
+  // without blinking, the background is bright instead
+  let bus = &mut emu.cpu.bus;
+  bus
+    .store(vga::ADDR + VRAM + reg::CONTROL, control::TEXT as u64, WORD)
+    .unwrap();
+  assert_eq!(PALETTE[15], px(vga(&emu), x, 8));
+  assert_eq!(PALETTE[12], px(vga(&emu), 0, 8));
+}
+
+#[test]
+fn graphics_mode() {
+  let mut emu = Emu::new(0);
+  let bus = &mut emu.cpu.bus;
+  bus.store(vga::ADDR + VRAM + reg::CONTROL, 0, WORD).unwrap();
+  assert_eq!(224, bus.load(vga::ADDR + VRAM + reg::WIDTH, WORD).unwrap());
+  assert_eq!(VRAM, bus.load(vga::ADDR + VRAM + reg::VRAM_SIZE, WORD).unwrap());
+
+  // a little-endian 0xrrggbb
+  bus.store(vga::ADDR, 0x2010, 16).unwrap();
+  bus.store(vga::ADDR + 2, 0x30, 8).unwrap();
+  let rgb = vga(&emu).rgb();
+  assert_eq!(3 * 224 * 126, rgb.len());
+  assert_eq!([0x30, 0x20, 0x10], rgb[..3]);
+}
+
+#[test]
+fn registers() {
+  let mut emu = Emu::new(0);
+  let bus = &mut emu.cpu.bus;
+  let set = |bus: &mut Bus, reg, value| {
+    bus.store(vga::ADDR + VRAM + reg, value, WORD).unwrap();
+  };
+  set(bus, reg::CONTROL, 0);
+  set(bus, reg::FORMAT, Format::Rgb565 as u64);
+  set(bus, reg::WIDTH, 2);
+  set(bus, reg::HEIGHT, 2);
+  set(bus, reg::STRIDE, 8);
+
+  // white, then pure red on the second line
+  bus.store(vga::ADDR, 0xffff, 16).unwrap();
+  bus.store(vga::ADDR + 8 + 2, 0xf800, 16).unwrap();
+  let expected =
+    Mode { width: 2, height: 2, stride: 8, format: Format::Rgb565 };
+  assert_eq!(Some(expected), vga(&emu).mode());
+  assert_eq!(vec![255, 255, 255, 0, 0, 0, 0, 0, 0, 255, 0, 0], vga(&emu).rgb());
+
+  // lines don't fit the stride
+  let bus = &mut emu.cpu.bus;
+  set(bus, reg::WIDTH, 5);
+  assert_eq!(None, vga(&emu).mode());
+  assert!(vga(&emu).rgb().is_empty());
+}
+
+#[test]
+fn relocate() {
+  let mut emu = Emu::new(0);
+  emu.cpu.bus.remap(vga::ADDR, 0x4000_0000).unwrap();
+  let bus = &mut emu.cpu.bus;
+  bus.store(0x4000_0000 + VRAM + reg::CONTROL, 0, WORD).unwrap();
+  bus.store(0x4000_0000 + VRAM + reg::HEIGHT, 100, WORD).unwrap();
+  assert_eq!(100, vga(&emu).mode().unwrap().height);
+}
+
+#[test]
+fn indexed() {
+  let mut emu = Emu::new(0);
+  let bus = &mut emu.cpu.bus;
+  let set = |bus: &mut Bus, reg, value| {
+    bus.store(vga::ADDR + VRAM + reg, value, WORD).unwrap();
+  };
+  set(bus, reg::CONTROL, 0);
+  set(bus, reg::FORMAT, Format::Indexed8 as u64);
+  set(bus, reg::WIDTH, 3);
+  set(bus, reg::HEIGHT, 1);
+  set(bus, reg::STRIDE, 4);
+
+  // the text colours, then the colour cube
+  assert_eq!(0xaa_5500, vga(&emu).palette()[6]);
+  assert_eq!(0xff_0000, vga(&emu).palette()[196]);
+  let bus = &mut emu.cpu.bus;
+  set(bus, reg::PALETTE + 4 * 0x80, 0x12_3456);
+  assert_eq!(
+    0x12_3456,
+    bus.load(vga::ADDR + VRAM + reg::PALETTE + 0x200, WORD).unwrap()
+  );
+  bus.store(vga::ADDR, 0x80_0f06, 32).unwrap();
+  assert_eq!(
+    vec![0xaa, 0x55, 0, 0xff, 0xff, 0xff, 0x12, 0x34, 0x56],
+    vga(&emu).rgb()
+  );
+}
+
+#[test]
+fn page_flip() {
+  let mut emu = Emu::new(0);
+  let mode = Mode::packed(2, 1, Format::Xrgb8888);
+  emu.cpu.bus.device_mut::<Vga>().unwrap().set_mode(mode);
+  let bus = &mut emu.cpu.bus;
+  let back = mode.size();
+
+  // drawing the back buffer leaves the picture alone
+  bus.store(vga::ADDR + back, 0xff_0000, 32).unwrap();
+  assert_eq!(vec![0; 6], vga(&emu).rgb());
+
+  let bus = &mut emu.cpu.bus;
+  bus.store(vga::ADDR + VRAM + reg::FRONT, back, WORD).unwrap();
+  assert_eq!(back as u32, vga(&emu).front());
+  assert_eq!(vec![0xff, 0, 0, 0, 0, 0], vga(&emu).rgb());
+
+  // the front buffer has to fit
+  let bus = &mut emu.cpu.bus;
+  bus.store(vga::ADDR + VRAM + reg::FRONT, VRAM - 4, WORD).unwrap();
+  assert_eq!(None, vga(&emu).mode());
+}
+
+#[test]
+fn vblank() {
+  let mut emu = Emu::new(0);
+  let bus = &mut emu.cpu.bus;
+  let regs = vga::ADDR + VRAM;
+  assert_eq!(60, bus.load(regs + reg::FPS, WORD).unwrap());
+  bus.store(regs + reg::FPS, TIMEBASE_FREQ / 4, WORD).unwrap();
+  bus.store(regs + reg::CONTROL, control::VBLANK as u64, WORD).unwrap();
+  let pending =
+    |bus: &mut Bus| bus.load(plic::ADDR + 0x1000, WORD).unwrap() >> VGA_IRQ & 1;
+
+  for _ in 0..3 {
+    bus.tick();
+  }
+  assert_eq!(0, bus.load(regs + reg::FRAME, WORD).unwrap());
+  assert_eq!(0, pending(bus));
+  bus.tick();
+  assert_eq!(1, bus.load(regs + reg::FRAME, WORD).unwrap());
+  assert_eq!(
+    status::VBLANK as u64,
+    bus.load(regs + reg::STATUS, WORD).unwrap()
+  );
+  assert_eq!(1, pending(bus));
+
+  // acknowledged
+  bus.store(regs + reg::STATUS, status::VBLANK as u64, WORD).unwrap();
+  bus.tick();
+  assert_eq!(0, bus.load(regs + reg::STATUS, WORD).unwrap());
+  assert_eq!(0, pending(bus));
+  for _ in 0..7 {
+    bus.tick();
+  }
+  assert_eq!(3, vga(&emu).frame());
+
+  // no frames at all
+  let bus = &mut emu.cpu.bus;
+  bus.store(regs + reg::FPS, 0, WORD).unwrap();
+  for _ in 0..8 {
+    bus.tick();
+  }
+  assert_eq!(3, vga(&emu).frame());
+}
+
+// Run a blit set up by the blitter `registers`, and return the status.
+fn blit(emu: &mut Emu, registers: &[(u64, u64)]) -> u64 {
+  let bus = &mut emu.cpu.bus;
+  let regs = vga::ADDR + VRAM;
+  for &(reg, value) in registers {
+    bus.store(regs + reg::BLIT + reg, value, WORD).unwrap();
+  }
+  assert_eq!(status::BUSY as u64, bus.load(regs + reg::STATUS, WORD).unwrap());
+  bus.tick();
+  let status = bus.load(regs + reg::STATUS, WORD).unwrap();
+  bus.store(regs + reg::STATUS, status, WORD).unwrap();
+  status
+}
+
+#[test]
+fn blit_fill_copy() {
+  let mut emu = Emu::new(0);
+  let mode = Mode::packed(4, 2, Format::Xrgb8888);
+  emu.cpu.bus.device_mut::<Vga>().unwrap().set_mode(mode);
+
+  let done = status::BLIT as u64;
+  let fill = [
+    (blit::reg::DST_PITCH, 16),
+    (blit::reg::WIDTH, 2),
+    (blit::reg::HEIGHT, 2),
+    (blit::reg::FG, 0xff_0000),
+    (blit::reg::OP, op::FILL as u64),
+  ];
+  assert_eq!(done, blit(&mut emu, &fill));
+  let red = [0xff, 0, 0];
+  let black = [0, 0, 0];
+  let line = [red, red, black, black].concat();
+  assert_eq!([&line[..], &line].concat(), vga(&emu).rgb());
+
+  // a pixel to the right, over the source
+  let copy = [
+    (blit::reg::SRC_PITCH, 16),
+    (blit::reg::DST_LOW, 4),
+    (blit::reg::OP, op::COPY as u64),
+  ];
+  assert_eq!(done, blit(&mut emu, &copy));
+  let line = [red, red, red, black].concat();
+  assert_eq!([&line[..], &line].concat(), vga(&emu).rgb());
+  assert_eq!(8, vga(&emu).blitted());
+  let bus = &mut emu.cpu.bus;
+  let pixels = vga::ADDR + VRAM + reg::BLIT + blit::reg::PIXELS_LOW;
+  assert_eq!(8, bus.load(pixels, WORD).unwrap());
+}
+
+#[test]
+fn blit_dram() {
+  let mut emu = Emu::new(0x1000);
+  let mode = Mode::packed(4, 1, Format::Rgb565);
+  emu.cpu.bus.device_mut::<Vga>().unwrap().set_mode(mode);
+  let bus = &mut emu.cpu.bus;
+  bus
+    .store(vga::ADDR + VRAM + reg::CONTROL, control::BLIT as u64, WORD)
+    .unwrap();
+  // a sprite of a magenta key and blue, then a glyph
+  bus.store(dram::ADDR, 0x001f_f81f, 32).unwrap();
+  bus.store(dram::ADDR + 0x10, 0b1010_0000, 8).unwrap();
+
+  let key = [
+    (blit::reg::SRC_LOW, dram::ADDR),
+    (blit::reg::WIDTH, 2),
+    (blit::reg::HEIGHT, 1),
+    (blit::reg::BYTES, 2),
+    (blit::reg::KEY, 0xf81f),
+    (blit::reg::DST_LOW, 4),
+    (blit::reg::OP, (op::KEY | op::SRC_DRAM) as u64),
+  ];
+  let bus = &mut emu.cpu.bus;
+  let pending =
+    |bus: &mut Bus| bus.load(plic::ADDR + 0x1000, WORD).unwrap() >> VGA_IRQ & 1;
+  for &(reg, value) in &key {
+    bus.store(vga::ADDR + VRAM + reg::BLIT + reg, value, WORD).unwrap();
+  }
+  bus.tick();
+  assert_eq!(1, pending(bus));
+  bus.store(vga::ADDR + VRAM + reg::STATUS, status::BLIT as u64, WORD).unwrap();
+  bus.tick();
+  assert_eq!(0, pending(bus));
+
+  let glyph = [
+    (blit::reg::SRC_LOW, dram::ADDR + 0x10),
+    (blit::reg::WIDTH, 4),
+    (blit::reg::DST_LOW, 0),
+    (blit::reg::FG, 0xffff),
+    (blit::reg::OP, (op::EXPAND | op::SRC_DRAM | op::TRANSPARENT) as u64),
+  ];
+  assert_eq!(status::BLIT as u64, blit(&mut emu, &glyph));
+  let (white, black, blue) = ([0xff; 3], [0; 3], [0, 0, 0xff]);
+  assert_eq!([white, black, white, blue].concat(), vga(&emu).rgb());
+
+  // the destination doesn't fit the pixel memory
+  let out = [(blit::reg::DST_LOW, VRAM - 2), (blit::reg::OP, op::FILL as u64)];
+  let error = (status::BLIT | status::BLIT_ERROR) as u64;
+  assert_eq!(error, blit(&mut emu, &out));
+}
+
+#[test]
+fn blit_bounds() {
+  let mut emu = Emu::new(0x1000);
+  let mode = Mode::packed(4, 2, Format::Xrgb8888);
+  emu.cpu.bus.device_mut::<Vga>().unwrap().set_mode(mode);
+  let error = (status::BLIT | status::BLIT_ERROR) as u64;
+
+  // a line longer than the memories fails before it is drawn
+  let wide = [
+    (blit::reg::WIDTH, 0xffff_ffff),
+    (blit::reg::HEIGHT, 1),
+    (blit::reg::OP, op::FILL as u64),
+  ];
+  assert_eq!(error, blit(&mut emu, &wide));
+
+  // the last line of a copy up the memory wraps around the address space
+  let wrap = [
+    (blit::reg::WIDTH, 1),
+    (blit::reg::HEIGHT, 0xffff),
+    (blit::reg::SRC_PITCH, 0),
+    (blit::reg::DST_PITCH, 0xffff_ffff),
+    (blit::reg::DST_LOW, 4),
+    (blit::reg::DST_HIGH, 0xffff_ffff),
+    (blit::reg::OP, op::COPY as u64),
+  ];
+  assert_eq!(error, blit(&mut emu, &wrap));
+
+  // a blit of many lines goes on over several ticks
+  let tall = [
+    (blit::reg::DST_LOW, 0),
+    (blit::reg::DST_HIGH, 0),
+    (blit::reg::DST_PITCH, 0),
+    (blit::reg::HEIGHT, 0xffff_ffff),
+    (blit::reg::OP, op::FILL as u64),
+  ];
+  assert_eq!(status::BUSY as u64, blit(&mut emu, &tall));
+  assert_eq!(blit::STEP, vga(&emu).blitted());
+}
+
+#[test]
+fn tiles() {
+  let mut emu = Emu::new(0);
+  let mode = Mode::packed(16, 2, Format::Indexed8);
+  emu.cpu.bus.device_mut::<Vga>().unwrap().set_mode(mode);
+  let bus = &mut emu.cpu.bus;
+  let regs = vga::ADDR + VRAM;
+  let (tiles, map, sprites) = (0x1000, 0x2000, 0x3000);
+  // a blank tile, a green one, and one with a dot at the top left
+  for i in 0..64 {
+    bus.store(vga::ADDR + tiles + 64 + i, 2, 8).unwrap();
+  }
+  bus.store(vga::ADDR + tiles + 128, 5, 8).unwrap();
+  bus.store(vga::ADDR + map, 1, 16).unwrap();
+  let table = [
+    Sprite { x: 8, y: 0, tile: 2, flags: flag::FLIP_X },
+    Sprite { x: 0, y: 0, tile: 2, flags: flag::BEHIND },
+    Sprite { x: 4, y: 0, tile: 2, flags: flag::BEHIND },
+  ];
+  for (i, sprite) in table.iter().enumerate() {
+    let entry = u64::from_le_bytes(sprite.to_bytes());
+    bus.store(vga::ADDR + sprites + 8 * i as u64, entry, 64).unwrap();
+  }
+
+  let setup = [
+    (tile::reg::TILES, tiles),
+    (tile::reg::MAP, map),
+    (tile::reg::MAP_WIDTH, 2),
+    (tile::reg::MAP_HEIGHT, 1),
+    (tile::reg::SCROLL_X, 4),
+    (tile::reg::SPRITES, sprites),
+    (tile::reg::COUNT, 3),
+  ];
+  for (reg, value) in setup {
+    bus.store(regs + reg::TILE + reg, value, WORD).unwrap();
+  }
+  bus.store(regs + reg::CONTROL, control::TILES as u64, WORD).unwrap();
+  frames(&mut emu, 1);
+
+  // the map scrolled by half a tile, wrapping around, then the sprites but
+  // the one behind the green tile
+  let line: Vec<_> = (0..16)
+    .map(|x| emu.cpu.bus.load(vga::ADDR + x, 8).unwrap() as u8)
+    .collect();
+  let expected = [2, 2, 2, 2, 5, 0, 0, 0, 0, 0, 0, 0, 2, 2, 2, 5];
+  assert_eq!(expected, line[..]);
+
+  // composed in the format of the mode
+  let mode = Mode::packed(16, 2, Format::Xrgb8888);
+  emu.cpu.bus.device_mut::<Vga>().unwrap().set_mode(mode);
+  frames(&mut emu, 1);
+  let rgb = vga(&emu).rgb();
+  assert_eq!(PALETTE[2], rgb[..3]);
+  assert_eq!(PALETTE[5], rgb[3 * 15..3 * 16]);
+}
@@ -0,0 +1,431 @@
+use {
+  std::{cell::RefCell, collections::VecDeque, fs, path::PathBuf, rc::Rc},
+  vrisc::{
+    bus::{dram, plic, virtio, VIRTIO_IRQ},
+    dev::{
+      serial::Serial,
+      virtio::{
+        blk::Blk,
+        console::Console,
+        input::{ev, Button, Event, Input},
+        int, reg,
+        rng::Rng,
+        Mmio,
+      },
+    },
+    Bus, Emu,
+  },
+};
+
+const WORD: u8 = 32;
+
+// Guest memory layout of the driver.
+const DESC: u64 = dram::ADDR;
+const AVAIL: u64 = dram::ADDR + 0x1000;
+const USED: u64 = dram::ADDR + 0x2000;
+const HEADER: u64 = dram::ADDR + 0x3000;
+const DATA: u64 = dram::ADDR + 0x4000;
+const STATUS: u64 = dram::ADDR + 0x5000;
+
+fn image(name: &str, sectors: usize) -> PathBuf {
+  let path = std::env::temp_dir().join(format!("vrisc-{name}.img"));
+  let data: Vec<u8> = (0..sectors * 512).map(|i| (i / 512) as u8).collect();
+  fs::write(&path, data).unwrap();
+  path
+}
+
+fn mem(bus: &mut Bus, addr: u64, data: &[u8]) {
+  let at = (addr - dram::ADDR) as usize;
+  bus.dram.as_slice_mut()[at..at + data.len()].copy_from_slice(data);
+}
+
+fn reg(bus: &mut Bus, reg: u64, value: u64) {
+  bus.store(virtio::ADDR + reg, value, WORD).unwrap();
+}
+
+fn driver(bus: &mut Bus) {
+  reg(bus, reg::STATUS, 1 | 2); // acknowledge, driver
+  reg(bus, reg::DRIVER_FEATURES_SEL, 1);
+  reg(bus, reg::DRIVER_FEATURES, 1); // VIRTIO_F_VERSION_1
+  reg(bus, reg::STATUS, 1 | 2 | 8); // features ok
+  reg(bus, reg::QUEUE_SEL, 0);
+  reg(bus, reg::QUEUE_NUM, 8);
+  reg(bus, reg::QUEUE_DESC_LOW, DESC);
+  reg(bus, reg::QUEUE_DRIVER_LOW, AVAIL);
+  reg(bus, reg::QUEUE_DEVICE_LOW, USED);
+  reg(bus, reg::QUEUE_READY, 1);
+  reg(bus, reg::STATUS, 1 | 2 | 8 | 4); // driver ok
+}
+
+// Submit a request of three descriptors and return the used length.
+fn request(bus: &mut Bus, kind: u32, sector: u64, data: &[u8]) -> u32 {
+  let mut header = kind.to_le_bytes().to_vec();
+  header.extend([0; 4]);
+  header.extend(sector.to_le_bytes());
+  mem(bus, HEADER, &header);
+  mem(bus, DATA, data);
+
+  // the device writes the data of reads
+  let flags = if kind == 0 { 1 | 2 } else { 1 };
+  let descs =
+    [(HEADER, 16, 1, 1), (DATA, data.len(), flags, 2), (STATUS, 1, 2, 0)];
+  for (i, (addr, len, flags, next)) in descs.into_iter().enumerate() {
+    let mut desc = addr.to_le_bytes().to_vec();
+    desc.extend((len as u32).to_le_bytes());
+    desc.extend((flags as u16).to_le_bytes());
+    desc.extend((next as u16).to_le_bytes());
+    mem(bus, DESC + 16 * i as u64, &desc);
+  }
+
+  let idx = bus.load(AVAIL + 2, 16).unwrap();
+  mem(bus, AVAIL + 4 + 2 * (idx % 8), &0u16.to_le_bytes());
+  mem(bus, AVAIL + 2, &(idx as u16 + 1).to_le_bytes());
+
+  reg(bus, reg::QUEUE_NOTIFY, 0);
+  bus.tick();
+  assert_eq!(idx + 1, bus.load(USED + 2, 16).unwrap());
+  bus.load(USED + 4 + 8 * (idx % 8) + 4, WORD).unwrap() as u32
+}
+
+fn pending(bus: &mut Bus) -> bool {
+  let pending = bus.load(plic::ADDR + 0x1000, WORD).unwrap();
+  pending >> VIRTIO_IRQ & 1 == 1
+}
+
+fn status(bus: &mut Bus) -> u64 {
+  bus.load(STATUS, 8).unwrap()
+}
+
+#[test]
+fn blk() {
+  let path = image("rw", 4);
+  let mut emu = Emu::new(0x10000);
+  let bus = &mut emu.cpu.bus;
+  assert_eq!(
+    virtio::ADDR,
+    bus.attach_virtio(Blk::open(&path, false).unwrap()).unwrap()
+  );
+
+  assert_eq!(0x74726976, bus.load(virtio::ADDR + reg::MAGIC, WORD).unwrap());
+  assert_eq!(2, bus.load(virtio::ADDR + reg::DEVICE_ID, WORD).unwrap());
+  // capacity in sectors
+  assert_eq!(4, bus.load(virtio::ADDR + reg::CONFIG, 64).unwrap());
+  driver(bus);
+
+  // read sector 2
+  assert_eq!(513, request(bus, 0, 2, &[0xff; 512]));
+  assert_eq!(0, status(bus));
+  assert_eq!(2, bus.load(DATA + 511, 8).unwrap());
+
+  // the device raised its interrupt line
+  let interrupt = virtio::ADDR + reg::INTERRUPT_STATUS;
+  assert_eq!(int::USED_BUFFER as u64, bus.load(interrupt, WORD).unwrap());
+  assert!(pending(bus));
+  reg(bus, reg::INTERRUPT_ACK, int::USED_BUFFER as u64);
+  bus.tick();
+  assert_eq!(0, bus.load(interrupt, WORD).unwrap());
+  assert!(!pending(bus));
+
+  // write sector 1, flush, and past the end of the disk
+  assert_eq!(1, request(bus, 1, 1, &[0xaa; 512]));
+  assert_eq!(0, status(bus));
+  assert_eq!(1, request(bus, 4, 0, &[]));
+  assert_eq!(0, status(bus));
+  assert_eq!(1, request(bus, 1, 4, &[0xaa; 512]));
+  assert_eq!(1, status(bus));
+
+  let image = fs::read(&path).unwrap();
+  assert!(image[512..1024].iter().all(|&b| b == 0xaa));
+  fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn readonly() {
+  let path = image("ro", 2);
+  let mut emu = Emu::new(0x10000);
+  let bus = &mut emu.cpu.bus;
+  bus.attach_virtio(Blk::open(&path, true).unwrap()).unwrap();
+  driver(bus);
+
+  assert_eq!(1, request(bus, 1, 0, &[0xaa; 512]));
+  assert_eq!(1, status(bus));
+  assert!(bus.device::<Mmio<Blk>>().is_some());
+  assert_eq!(vec![0; 512], fs::read(&path).unwrap()[..512]);
+  fs::remove_file(path).unwrap();
+}
+
+// Virtqueues of the generic driver: the queue `i` and its buffers of 0x100
+// bytes at `QUEUES + i * 0x1000`.
+const QUEUES: u64 = dram::ADDR + 0x8000;
+const NUM: u64 = 8;
+
+fn setup(bus: &mut Bus, base: u64, queues: u64) {
+  let reg = |bus: &mut Bus, reg, value| {
+    bus.store(base + reg, value, WORD).unwrap();
+  };
+  reg(bus, reg::STATUS, 1 | 2 | 8);
+  for i in 0..queues {
+    let queue = QUEUES + i * 0x1000;
+    reg(bus, reg::QUEUE_SEL, i);
+    reg(bus, reg::QUEUE_NUM, NUM);
+    reg(bus, reg::QUEUE_DESC_LOW, queue);
+    reg(bus, reg::QUEUE_DRIVER_LOW, queue + 0x80);
+    reg(bus, reg::QUEUE_DEVICE_LOW, queue + 0xc0);
+    reg(bus, reg::QUEUE_READY, 1);
+  }
+  reg(bus, reg::STATUS, 1 | 2 | 8 | 4);
+}
+
+// Make a buffer with `data`, or one of `len` bytes the device writes into,
+// available in the queue `i`.
+fn post(bus: &mut Bus, base: u64, i: u64, data: &[u8], len: u32) {
+  let queue = QUEUES + i * 0x1000;
+  let idx = bus.load(queue + 0x80 + 2, 16).unwrap();
+  let slot = idx % NUM;
+  let buf = queue + 0x100 * (slot + 1);
+  mem(bus, buf, data);
+
+  let (len, flags) =
+    if data.is_empty() { (len, 2u16) } else { (data.len() as u32, 0) };
+  let desc =
+    [&buf.to_le_bytes()[..], &len.to_le_bytes(), &flags.to_le_bytes(), &[0, 0]];
+  mem(bus, queue + 16 * slot, &desc.concat());
+  mem(bus, queue + 0x80 + 4 + 2 * slot, &(slot as u16).to_le_bytes());
+  mem(bus, queue + 0x80 + 2, &(idx as u16 + 1).to_le_bytes());
+  bus.store(base + reg::QUEUE_NOTIFY, i, WORD).unwrap();
+}
+
+// Buffers of the queue `i` used by the device, as they were used.
+fn used(bus: &mut Bus, i: u64) -> Vec<Vec<u8>> {
+  let queue = QUEUES + i * 0x1000;
+  let idx = bus.load(queue + 0xc0 + 2, 16).unwrap();
+  (0..idx)
+    .map(|n| {
+      let at = queue + 0xc0 + 4 + 8 * (n % NUM);
+      let slot = bus.load(at, WORD).unwrap();
+      let len = bus.load(at + 4, WORD).unwrap();
+      let buf = queue + 0x100 * (slot + 1);
+      (0..len).map(|b| bus.load(buf + b, 8).unwrap() as u8).collect()
+    })
+    .collect()
+}
+
+#[test]
+fn rng() {
+  let bytes = |seed, len| {
+    let mut emu = Emu::new(0x10000);
+    let bus = &mut emu.cpu.bus;
+    let base = bus.attach_virtio(Rng::new(seed)).unwrap();
+    assert_eq!(4, bus.load(base + reg::DEVICE_ID, WORD).unwrap());
+    setup(bus, base, 1);
+    post(bus, base, 0, &[], len);
+    bus.tick();
+    used(bus, 0).remove(0)
+  };
+
+  // a seeded source repeats itself
+  assert_eq!(32, bytes(Some(7), 32).len());
+  assert_eq!(bytes(Some(7), 32), bytes(Some(7), 32));
+  assert_ne!(bytes(Some(7), 32), bytes(Some(8), 32));
+  assert_ne!(bytes(None, 32), bytes(None, 32));
+
+  // a large buffer gets a page and is told so
+  assert_eq!(4096, bytes(Some(7), u32::MAX).len());
+}
+
+#[test]
+fn input() {
+  let mut emu = Emu::new(0x10000);
+  assert!(!emu.input(Event::Key(30, true)));
+  let base = emu.cpu.bus.attach_virtio(Input::new()).unwrap();
+  let bus = &mut emu.cpu.bus;
+  assert_eq!(18, bus.load(base + reg::DEVICE_ID, WORD).unwrap());
+
+  // the keys and the buttons in the bitmap of the key events
+  bus.store(base + reg::CONFIG, 0x11 | (ev::KEY as u64) << 8, 16).unwrap();
+  assert_eq!(0x112 / 8 + 1, bus.load(base + reg::CONFIG + 2, 8).unwrap());
+  let bits = bus.load(base + reg::CONFIG + 8 + 0x110 / 8, 8).unwrap();
+  assert_eq!(0b111, bits);
+  bus.store(base + reg::CONFIG, 0x01, 8).unwrap();
+  let name = (0..11).map(|i| bus.load(base + reg::CONFIG + 8 + i, 8).unwrap());
+  assert_eq!(b"vrisc input", &name.map(|b| b as u8).collect::<Vec<_>>()[..]);
+
+  setup(bus, base, 2);
+  assert!(emu.input(Event::Key(30, true)));
+  assert!(emu.input(Event::Motion(-3, 4)));
+  assert!(emu.input(Event::Button(Button::Left, false)));
+
+  // the events wait for the buffers of the driver
+  let bus = &mut emu.cpu.bus;
+  for _ in 0..4 {
+    post(bus, base, 0, &[], 8);
+  }
+  bus.tick();
+  let event = |kind: u16, code: u16, value: i32| {
+    [&kind.to_le_bytes()[..], &code.to_le_bytes(), &value.to_le_bytes()]
+      .concat()
+  };
+  let report = event(ev::SYN, ev::SYN_REPORT, 0);
+  let key = [event(ev::KEY, 30, 1), report.clone()];
+  let motion = [event(ev::REL, ev::REL_X, -3), event(ev::REL, ev::REL_Y, 4)];
+  assert_eq!([&key[..], &motion].concat(), used(bus, 0));
+
+  for _ in 0..4 {
+    post(bus, base, 0, &[], 8);
+  }
+  bus.tick();
+  let button = event(ev::KEY, ev::BTN_LEFT, 0);
+  assert_eq!([report.clone(), button, report], used(bus, 0)[4..]);
+}
+
+// Host end of a console port shared with the test.
+#[derive(Debug, Clone)]
+struct Channel(Rc<RefCell<(VecDeque<u8>, Vec<u8>)>>);
+
+impl Serial for Channel {
+  fn read(&mut self) -> Option<u8> {
+    self.0.borrow_mut().0.pop_front()
+  }
+
+  fn write(&mut self, byte: u8) {
+    self.0.borrow_mut().1.push(byte);
+  }
+}
+
+fn control(id: u32, event: u16, value: u16) -> Vec<u8> {
+  [&id.to_le_bytes()[..], &event.to_le_bytes(), &value.to_le_bytes()].concat()
+}
+
+#[test]
+fn console() {
+  const DEVICE_READY: u16 = 0;
+  const DEVICE_ADD: u16 = 1;
+  const PORT_READY: u16 = 3;
+  const CONSOLE_PORT: u16 = 4;
+  const PORT_OPEN: u16 = 6;
+  const PORT_NAME: u16 = 7;
+  let (console, agent) = (Channel(Rc::default()), Channel(Rc::default()));
+
+  let mut emu = Emu::new(0x10000);
+  let bus = &mut emu.cpu.bus;
+  let device = Console::new(console.clone()).with_port("agent", agent.clone());
+  let base = bus.attach_virtio(device).unwrap();
+  assert_eq!(3, bus.load(base + reg::DEVICE_ID, WORD).unwrap());
+  // max_nr_ports
+  assert_eq!(2, bus.load(base + reg::CONFIG + 4, WORD).unwrap());
+  setup(bus, base, 6);
+
+  // the ports are announced once the driver is ready
+  for _ in 0..6 {
+    post(bus, base, 2, &[], 64);
+  }
+  post(bus, base, 3, &control(0, DEVICE_READY, 1), 0);
+  bus.tick();
+  let expected = [control(0, DEVICE_ADD, 0), control(1, DEVICE_ADD, 0)];
+  assert_eq!(expected.to_vec(), used(bus, 2));
+
+  post(bus, base, 3, &control(0, PORT_READY, 1), 0);
+  post(bus, base, 3, &control(1, PORT_READY, 1), 0);
+  post(bus, base, 3, &control(1, PORT_OPEN, 1), 0);
+  bus.tick();
+  let mut name = control(1, PORT_NAME, 0);
+  name.extend(b"agent");
+  let expected = [
+    control(0, CONSOLE_PORT, 1),
+    control(0, PORT_OPEN, 1),
+    name,
+    control(1, PORT_OPEN, 1),
+  ];
+  assert_eq!(expected.to_vec(), used(bus, 2)[2..]);
+  assert!(bus.device::<Mmio<Console>>().unwrap().device.is_open(1));
+
+  // each port has its own pair of queues
+  post(bus, base, 1, b"login: ", 0);
+  post(bus, base, 5, b"ping", 0);
+  bus.tick();
+  assert_eq!(b"login: ", &console.0.borrow().1[..]);
+  assert_eq!(b"ping", &agent.0.borrow().1[..]);
+
+  agent.0.borrow_mut().0.extend(b"pong");
+  post(bus, base, 4, &[], 64);
+  for _ in 0..1024 {
+    bus.tick();
+  }
+  assert_eq!(vec![b"pong".to_vec()], used(bus, 4));
+  assert!(used(bus, 0).is_empty());
+
+  // emergency writes go to the console
+  bus.store(base + reg::CONFIG + 8, b'!' as u64, WORD).unwrap();
+  assert_eq!(b"login: !", &console.0.borrow().1[..]);
+}
+
+#[test]
+fn many_queues() {
+  let last = Channel(Rc::default());
+  let mut device = Console::new(Channel(Rc::default()));
+  for port in 1..40 {
+    let backend =
+      if port == 39 { last.clone() } else { Channel(Rc::default()) };
+    device = device.with_port(&format!("port{port}"), backend);
+  }
+
+  // notifications of queues past the 64th are served too
+  let mut emu = Emu::new(0x100000);
+  let bus = &mut emu.cpu.bus;
+  let base = bus.attach_virtio(device).unwrap();
+  setup(bus, base, 82);
+  post(bus, base, 81, b"ping", 0);
+  bus.tick();
+  assert_eq!(b"ping", &last.0.borrow().1[..]);
+}
+
+#[test]
+fn ring_bounds() {
+  let path = image("bounds", 4);
+  let mut emu = Emu::new(0x10000);
+  let bus = &mut emu.cpu.bus;
+  bus.attach_virtio(Blk::open(&path, false).unwrap()).unwrap();
+  driver(bus);
+
+  // a read as long as the address space fails before touching the disk
+  let mut header = 0u32.to_le_bytes().to_vec();
+  header.extend([0; 12]);
+  mem(bus, HEADER, &header);
+  let descs =
+    [(HEADER, 16, 1, 1), (DATA, u32::MAX, 1 | 2, 2), (STATUS, 1, 2, 0)];
+  for (i, (addr, len, flags, next)) in descs.into_iter().enumerate() {
+    let desc = [
+      &addr.to_le_bytes()[..],
+      &len.to_le_bytes(),
+      &(flags as u16).to_le_bytes(),
+      &(next as u16).to_le_bytes(),
+    ];
+    mem(bus, DESC + 16 * i as u64, &desc.concat());
+  }
+  mem(bus, AVAIL + 2, &1u16.to_le_bytes());
+  reg(bus, reg::QUEUE_NOTIFY, 0);
+  bus.tick();
+  assert_eq!(1, status(bus));
+
+  // rings at the end of the address space are out of range
+  let end = |bus: &mut Bus, high, low| {
+    reg(bus, high, 0xffff_ffff);
+    reg(bus, low, 0xffff_fffe);
+  };
+  let notify = |bus: &mut Bus, idx: u16| {
+    mem(bus, AVAIL + 2 + 2 * idx as u64, &0u16.to_le_bytes());
+    mem(bus, AVAIL + 2, &idx.to_le_bytes());
+    reg(bus, reg::QUEUE_NOTIFY, 0);
+    bus.tick();
+  };
+  end(bus, reg::QUEUE_DESC_HIGH, reg::QUEUE_DESC_LOW);
+  notify(bus, 2);
+  reg(bus, reg::QUEUE_DESC_HIGH, 0);
+  reg(bus, reg::QUEUE_DESC_LOW, DESC);
+  end(bus, reg::QUEUE_DEVICE_HIGH, reg::QUEUE_DEVICE_LOW);
+  notify(bus, 3);
+  end(bus, reg::QUEUE_DRIVER_HIGH, reg::QUEUE_DRIVER_LOW);
+  reg(bus, reg::QUEUE_NOTIFY, 0);
+  bus.tick();
+  fs::remove_file(path).unwrap();
+}